@@ -0,0 +1,71 @@
+extern crate cart_cache;
+extern crate rand;
+
+use cart_cache::CartCache;
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+fn bench(capacity: usize, keyspace: u64, ops: usize) {
+    let mut cache: CartCache<u64, u64> = CartCache::new(capacity).unwrap();
+    let mut rng = StdRng::seed_from_u64(capacity as u64);
+    let keys: Vec<u64> = (0..ops).map(|_| rng.gen_range(0..keyspace)).collect();
+    let start = Instant::now();
+    let mut hits = 0;
+    for &key in &keys {
+        if cache.get(&key).is_some() {
+            hits += 1;
+        } else {
+            cache.insert(key, key);
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "capacity={:>8} keyspace={:>8} ops={} hits={} {:.1} ns/op",
+        capacity,
+        keyspace,
+        ops,
+        hits,
+        elapsed.as_nanos() as f64 / ops as f64
+    );
+}
+
+fn bench_hits(resident: usize, ops: usize) {
+    let mut cache: CartCache<u64, u64> = CartCache::with_resident_capacity(resident).unwrap();
+    let mut map: HashMap<u64, u64> = HashMap::with_capacity(resident);
+    for key in 0..resident as u64 {
+        cache.insert(key, key);
+        map.insert(key, key);
+    }
+    let mut rng = StdRng::seed_from_u64(resident as u64);
+    let keys: Vec<u64> = (0..ops)
+        .map(|_| rng.gen_range(0..resident as u64))
+        .collect();
+    let mut sum = 0u64;
+    let start = Instant::now();
+    for key in &keys {
+        sum = sum.wrapping_add(cache.get_copied(key).unwrap_or(0));
+    }
+    let cache_elapsed = start.elapsed();
+    let start = Instant::now();
+    for key in &keys {
+        sum = sum.wrapping_add(map.get(key).copied().unwrap_or(0));
+    }
+    let map_elapsed = start.elapsed();
+    println!(
+        "resident={:>8} get_copied {:.1} ns/op, HashMap::get {:.1} ns/op ({})",
+        resident,
+        cache_elapsed.as_nanos() as f64 / ops as f64,
+        map_elapsed.as_nanos() as f64 / ops as f64,
+        sum
+    );
+}
+
+fn main() {
+    for &capacity in &[1_000, 100_000, 1_000_000] {
+        bench(capacity, capacity as u64 * 2, 2_000_000);
+    }
+    for &resident in &[1_000, 100_000, 1_000_000] {
+        bench_hits(resident, 2_000_000);
+    }
+}
@@ -0,0 +1,140 @@
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitStr, Type};
+
+/// Derives `Hash`, `PartialEq`, `Eq` and `cart_cache::CacheKey` for a struct.
+///
+/// The hash starts with an identifier of the key namespace, computed at
+/// compile time, so that keys of different types never share hashes. The
+/// namespace defaults to the path of the type, and can be set with
+/// `#[cache_key(namespace = "...")]`.
+///
+/// Structs with named fields also get a `<Name>Ref<'a>` struct holding
+/// references to the fields, usable for lookups without building an owned
+/// key. `#[cache_key(borrow = "str")]` on a field sets its borrowed type.
+#[proc_macro_derive(CacheKey, attributes(cache_key))]
+pub fn derive_cache_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "CacheKey cannot be derived for generic types",
+        ));
+    }
+    let fields = match input.data {
+        Data::Struct(data) => data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "CacheKey can only be derived for structs",
+            ))
+        }
+    };
+    let name = &input.ident;
+    let mut namespace = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("cache_key"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("namespace") {
+                namespace = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("Unsupported cache_key attribute"))
+            }
+        })?;
+    }
+    let namespace = match namespace {
+        Some(namespace) => quote!(#namespace),
+        None => {
+            let name = LitStr::new(&name.to_string(), Span::call_site());
+            quote!(concat!(module_path!(), "::", #name))
+        }
+    };
+
+    let members: Vec<_> = fields.members().collect();
+    let mut out = quote! {
+        impl ::cart_cache::CacheKey for #name {
+            const NAMESPACE: &'static str = #namespace;
+        }
+
+        impl ::std::hash::Hash for #name {
+            fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+                state.write_u64(<Self as ::cart_cache::CacheKey>::NAMESPACE_ID);
+                #(::std::hash::Hash::hash(&self.#members, state);)*
+            }
+        }
+
+        impl ::std::cmp::PartialEq for #name {
+            fn eq(&self, other: &Self) -> bool {
+                true #(&& self.#members == other.#members)*
+            }
+        }
+
+        impl ::std::cmp::Eq for #name {}
+    };
+
+    if let Fields::Named(named) = &fields {
+        let ref_name = format_ident!("{}Ref", name);
+        let mut idents: Vec<&Ident> = Vec::new();
+        let mut owned: Vec<&Type> = Vec::new();
+        let mut types: Vec<Type> = Vec::new();
+        for field in &named.named {
+            let mut ty = field.ty.clone();
+            for attr in field
+                .attrs
+                .iter()
+                .filter(|a| a.path().is_ident("cache_key"))
+            {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("borrow") {
+                        ty = meta.value()?.parse::<LitStr>()?.parse()?;
+                        Ok(())
+                    } else {
+                        Err(meta.error("Unsupported cache_key attribute"))
+                    }
+                })?;
+            }
+            idents.push(field.ident.as_ref().expect("Unnamed field"));
+            owned.push(&field.ty);
+            types.push(ty);
+        }
+        let vis = &input.vis;
+        let doc = format!("Borrowed form of [`{}`], for lookups.", name);
+        out.extend(quote! {
+            #[doc = #doc]
+            #[derive(Clone, Copy)]
+            #vis struct #ref_name<'a> {
+                #(#vis #idents: &'a #types,)*
+            }
+
+            impl<'a> ::std::hash::Hash for #ref_name<'a> {
+                fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+                    state.write_u64(<#name as ::cart_cache::CacheKey>::NAMESPACE_ID);
+                    #(::std::hash::Hash::hash(self.#idents, state);)*
+                }
+            }
+
+            impl<'a> ::cart_cache::Equivalent<#name> for #ref_name<'a> {
+                fn equivalent(&self, key: &#name) -> bool {
+                    true #(&& <#owned as ::std::borrow::Borrow<#types>>::borrow(&key.#idents) == self.#idents)*
+                }
+            }
+        });
+    }
+    Ok(out)
+}
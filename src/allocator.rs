@@ -0,0 +1,128 @@
+use std::alloc::{AllocError, Allocator, Global, Layout};
+use std::fmt;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+/// The allocator of the entries and list nodes of a cache, set with
+/// `CartCacheBuilder::allocator()`, e.g. an arena or a NUMA-pinned allocator.
+/// It is shared by clones of the cache, and defaults to the global allocator.
+///
+/// Requires the `allocator_api` feature, and a nightly compiler.
+#[derive(Clone)]
+pub struct CacheAlloc(Arc<dyn Allocator + Send + Sync>);
+
+impl CacheAlloc {
+    pub fn new<A: Allocator + Send + Sync + 'static>(alloc: A) -> Self {
+        CacheAlloc(Arc::new(alloc))
+    }
+}
+
+impl Default for CacheAlloc {
+    fn default() -> Self {
+        CacheAlloc::new(Global)
+    }
+}
+
+impl fmt::Debug for CacheAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CacheAlloc")
+    }
+}
+
+unsafe impl Allocator for CacheAlloc {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate(layout)
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.deallocate(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.grow(ptr, old_layout, new_layout)
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.shrink(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator::CacheAlloc;
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use {CartCache, CartCacheBuilder};
+
+    #[derive(Default)]
+    struct Counts {
+        allocated: AtomicUsize,
+        live: AtomicUsize,
+    }
+
+    struct Counting(Arc<Counts>);
+
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.allocated.fetch_add(1, Ordering::Relaxed);
+            self.0.live.fetch_add(1, Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.live.fetch_sub(1, Ordering::Relaxed);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn allocator() {
+        let counts = Arc::new(Counts::default());
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(64)
+            .allocator(CacheAlloc::new(Counting(counts.clone())))
+            .build()
+            .unwrap();
+        let allocated = counts.allocated.load(Ordering::Relaxed);
+        assert!(allocated >= 2);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(counts.allocated.load(Ordering::Relaxed), allocated);
+        let clone = cache.clone();
+        assert!(counts.allocated.load(Ordering::Relaxed) > allocated);
+        drop(clone);
+        drop(cache);
+        assert_eq!(counts.live.load(Ordering::Relaxed), 0);
+    }
+}
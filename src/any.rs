@@ -0,0 +1,122 @@
+use std::any::{Any, TypeId};
+use std::hash::Hash;
+
+use {CartCache, Equivalent, Stats};
+
+type AnyValue = Box<dyn Any + Send>;
+
+#[derive(Hash)]
+struct TypedKey<'a, Q: ?Sized + 'a>(TypeId, &'a Q);
+
+impl<'a, K, Q> Equivalent<(TypeId, K)> for TypedKey<'a, Q>
+where
+    Q: Equivalent<K> + ?Sized,
+{
+    fn equivalent(&self, key: &(TypeId, K)) -> bool {
+        self.0 == key.0 && self.1.equivalent(&key.1)
+    }
+}
+
+/// A cache holding values of any type, keyed by the value type and `K`, so
+/// that several memoization sites can share a single capacity budget.
+pub struct AnyCache<K>
+where
+    K: Eq + Hash,
+{
+    cache: CartCache<(TypeId, K), AnyValue>,
+}
+
+impl<K: Eq + Hash + Clone> AnyCache<K> {
+    pub fn new(capacity: usize) -> Result<Self, &'static str> {
+        Ok(AnyCache {
+            cache: CartCache::new(capacity)?,
+        })
+    }
+
+    pub fn from_cache(cache: CartCache<(TypeId, K), AnyValue>) -> Self {
+        AnyCache { cache }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.cache.stats()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear()
+    }
+
+    pub fn insert_typed<T: Any + Send>(&mut self, key: K, value: T) -> bool {
+        self.cache.insert((TypeId::of::<T>(), key), Box::new(value))
+    }
+
+    pub fn contains_typed<T, Q>(&self, key: &Q) -> bool
+    where
+        T: Any + Send,
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.cache.contains_key(&TypedKey(TypeId::of::<T>(), key))
+    }
+
+    pub fn get_typed<T, Q>(&mut self, key: &Q) -> Option<&T>
+    where
+        T: Any + Send,
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.cache
+            .get(&TypedKey(TypeId::of::<T>(), key))
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_typed_mut<T, Q>(&mut self, key: &Q) -> Option<&mut T>
+    where
+        T: Any + Send,
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.cache
+            .get_mut(&TypedKey(TypeId::of::<T>(), key))
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub fn get_or_insert_typed_with<T, F>(&mut self, key: K, f: F) -> &T
+    where
+        T: Any + Send,
+        F: FnOnce() -> T,
+    {
+        self.cache
+            .get_or_insert_with((TypeId::of::<T>(), key), || Box::new(f()))
+            .downcast_ref()
+            .expect("Value type does not match its key")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use any::AnyCache;
+
+    #[test]
+    fn typed_access() {
+        let mut cache: AnyCache<String> = AnyCache::new(16).unwrap();
+        cache.insert_typed("a".to_string(), 1u32);
+        cache.insert_typed("a".to_string(), "one");
+        assert_eq!(cache.get_typed::<u32, _>("a"), Some(&1));
+        assert_eq!(cache.get_typed::<&str, _>("a"), Some(&"one"));
+        assert_eq!(cache.get_typed::<u64, _>("a"), None);
+        assert_eq!(
+            *cache.get_or_insert_typed_with("b".to_string(), || 2.5f64),
+            2.5
+        );
+        assert_eq!(cache.len(), 3);
+    }
+}
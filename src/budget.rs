@@ -0,0 +1,170 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A weight budget shared by several caches, set with
+/// `CartCacheBuilder::shared_budget()`.
+///
+/// Every member adds the weight of its entries to the budget, history
+/// entries included, as they keep their values. A member that inserts while
+/// the budget is exceeded evicts its own entries until the total fits again
+/// or until it is back to its fair share, the maximum weight divided by the
+/// number of members, so that caches that keep growing give way first and
+/// small members are not emptied. Members that don't insert shed their
+/// excess weight in `run_maintenance()` instead, so a member that stays
+/// under its share can make the others give way.
+#[derive(Debug)]
+pub struct WeightBudget {
+    max_weight: usize,
+    weight: AtomicUsize,
+    members: AtomicUsize,
+}
+
+impl WeightBudget {
+    pub fn new(max_weight: usize) -> Arc<Self> {
+        Arc::new(WeightBudget {
+            max_weight,
+            weight: AtomicUsize::new(0),
+            members: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn max_weight(&self) -> usize {
+        self.max_weight
+    }
+
+    /// Total weight of the members.
+    pub fn weight(&self) -> usize {
+        self.weight.load(Ordering::Relaxed)
+    }
+
+    pub fn members(&self) -> usize {
+        self.members.load(Ordering::Relaxed)
+    }
+
+    /// Maximum weight divided by the number of members.
+    pub fn fair_share(&self) -> usize {
+        self.max_weight / self.members().max(1)
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.weight() > self.max_weight
+    }
+}
+
+// The share of a member, which returns its weight to the budget when the
+// member goes away.
+#[derive(Debug)]
+pub(crate) struct BudgetShare {
+    budget: Arc<WeightBudget>,
+    weight: usize,
+}
+
+impl BudgetShare {
+    pub fn new(budget: Arc<WeightBudget>, weight: usize) -> Self {
+        budget.members.fetch_add(1, Ordering::Relaxed);
+        budget.weight.fetch_add(weight, Ordering::Relaxed);
+        BudgetShare { budget, weight }
+    }
+
+    #[inline]
+    pub fn set(&mut self, weight: usize) {
+        if weight > self.weight {
+            self.budget
+                .weight
+                .fetch_add(weight - self.weight, Ordering::Relaxed);
+        } else {
+            self.budget
+                .weight
+                .fetch_sub(self.weight - weight, Ordering::Relaxed);
+        }
+        self.weight = weight;
+    }
+
+    pub fn budget(&self) -> &Arc<WeightBudget> {
+        &self.budget
+    }
+
+    // Whether the member has to evict to let `incoming` more weight in: the
+    // budget would be exceeded, and the member would be over its share.
+    #[inline]
+    pub fn must_evict(&self, incoming: usize) -> bool {
+        self.budget.weight() + incoming > self.budget.max_weight
+            && self.weight + incoming > self.budget.fair_share()
+    }
+}
+
+impl Drop for BudgetShare {
+    fn drop(&mut self) {
+        self.set(0);
+        self.budget.members.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use budget::WeightBudget;
+    use {CartCache, CartCacheBuilder};
+
+    #[test]
+    fn shared_budget() {
+        let budget = WeightBudget::new(10);
+        let mut a: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .shared_budget(budget.clone())
+            .build()
+            .unwrap();
+        let mut b: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .shared_budget(budget.clone())
+            .build()
+            .unwrap();
+        for i in 0..8 {
+            a.insert(i, i);
+        }
+        assert_eq!(budget.weight(), 8);
+        for i in 0..4 {
+            b.insert(i, i);
+        }
+        assert_eq!(budget.fair_share(), 5);
+        assert_eq!((b.weight(), budget.weight()), (4, 12));
+        a.insert(8, 8);
+        assert_eq!((a.weight(), budget.weight()), (6, 10));
+        for i in 4..8 {
+            b.insert(i, i);
+        }
+        assert_eq!((b.weight(), budget.weight()), (5, 11));
+        let mut c = a.clone();
+        assert_eq!((budget.members(), budget.weight()), (2, 11));
+        assert!(c.shared_budget().is_none());
+        c.set_shared_budget(a.shared_budget().cloned());
+        assert_eq!((budget.members(), budget.weight()), (3, 17));
+        drop(c);
+        a.clear();
+        assert_eq!(budget.weight(), 5);
+        drop(b);
+        assert_eq!(budget.weight(), 0);
+    }
+
+    #[test]
+    fn idle_member_sheds() {
+        let budget = WeightBudget::new(10);
+        let mut idle: CartCache<u32, u32> = CartCacheBuilder::new(32)
+            .shared_budget(budget.clone())
+            .build()
+            .unwrap();
+        for i in 0..10 {
+            idle.insert(i, i);
+        }
+        let mut active: CartCache<u32, u32> = CartCacheBuilder::new(32)
+            .shared_budget(budget.clone())
+            .build()
+            .unwrap();
+        for i in 0..4 {
+            active.insert(i, i);
+        }
+        assert_eq!((idle.weight(), budget.weight()), (10, 14));
+        idle.run_maintenance();
+        assert_eq!((idle.weight(), budget.weight()), (6, 10));
+        assert_eq!(idle.stats().evicted_by_weight, 4);
+        active.run_maintenance();
+        assert_eq!(active.weight(), 4);
+    }
+}
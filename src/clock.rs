@@ -0,0 +1,165 @@
+use slots::Slots;
+use {Node, XLinkedList, MAX_REFERENCES};
+
+/// Identifies an item in a `ClockList`. Handles of removed items may be
+/// reused by later pushes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClockHandle(usize);
+
+/// The CLOCK list used by the cache: a circular list swept by a hand, where
+/// referenced items get another turn instead of being evicted. Items keep a
+/// small reference counter, so that with `max_references` above 1 they
+/// survive that many sweeps.
+pub struct ClockList<T> {
+    items: Slots<T>,
+    nodes: Vec<Node>,
+    list: XLinkedList,
+    max_references: u8,
+}
+
+impl<T> ClockList<T> {
+    pub fn new() -> Self {
+        ClockList {
+            items: Slots::new(),
+            nodes: Vec::new(),
+            list: XLinkedList::new(),
+            max_references: 1,
+        }
+    }
+
+    pub fn with_max_references(max_references: u8) -> Result<Self, &'static str> {
+        if max_references == 0 || max_references > MAX_REFERENCES {
+            return Err("Invalid reference counter");
+        }
+        Ok(ClockList {
+            max_references,
+            ..ClockList::new()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Adds an unreferenced item right behind the hand, so that it is swept
+    /// last.
+    pub fn push(&mut self, item: T) -> ClockHandle {
+        let token = self.items.insert(item);
+        if token == self.nodes.len() {
+            self.nodes.push(Node::default());
+        } else {
+            self.nodes[token] = Node::default();
+        }
+        self.list.push_back(&mut self.nodes, token);
+        ClockHandle(token)
+    }
+
+    pub fn get(&self, handle: ClockHandle) -> Option<&T> {
+        self.items.get(handle.0)
+    }
+
+    pub fn get_mut(&mut self, handle: ClockHandle) -> Option<&mut T> {
+        self.items.get_mut(handle.0)
+    }
+
+    /// Increments the reference counter of an item. Returns `false` if the
+    /// item is gone.
+    pub fn reference(&mut self, handle: ClockHandle) -> bool {
+        if !self.items.contains(handle.0) {
+            return false;
+        }
+        self.nodes[handle.0].reference(self.max_references);
+        true
+    }
+
+    pub fn references(&self, handle: ClockHandle) -> Option<u8> {
+        if !self.items.contains(handle.0) {
+            return None;
+        }
+        Some(self.nodes[handle.0].references())
+    }
+
+    pub fn remove(&mut self, handle: ClockHandle) -> Option<T> {
+        let item = self.items.try_remove(handle.0)?;
+        self.list.remove(&mut self.nodes, handle.0);
+        Some(item)
+    }
+
+    /// Moves the hand past referenced items, decrementing their counters,
+    /// and removes the first unreferenced one.
+    pub fn sweep(&mut self) -> Option<T> {
+        self.sweep_by(|_, _| false)
+    }
+
+    /// Like `sweep()`, but unreferenced items for which `keep` returns
+    /// `true` are skipped as well, e.g. to implement admission buffers.
+    /// Returns `None` once a full turn found nothing to remove.
+    pub fn sweep_by<F>(&mut self, mut keep: F) -> Option<T>
+    where
+        F: FnMut(&T, u8) -> bool,
+    {
+        let mut remaining = self.list.len() * (self.max_references as usize + 1);
+        while let Some(token) = self.list.front() {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            let references = self.nodes[token].references();
+            if references > 0 {
+                self.nodes[token].decay_references(1);
+            } else if !keep(&self.items[token], references) {
+                self.list.pop_front(&mut self.nodes);
+                return Some(self.items.remove(token));
+            }
+            self.list.advance(&self.nodes);
+        }
+        None
+    }
+
+    /// Items from the hand onwards, with their handles.
+    pub fn iter(&self) -> impl Iterator<Item = (ClockHandle, &T)> {
+        self.list
+            .iter(&self.nodes)
+            .map(move |token| (ClockHandle(token), &self.items[token]))
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.nodes.clear();
+        self.list.clear();
+    }
+}
+
+impl<T> Default for ClockList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::ClockList;
+
+    #[test]
+    fn clock_list() {
+        let mut clock = ClockList::with_max_references(2).unwrap();
+        let a = clock.push("a");
+        let b = clock.push("b");
+        clock.push("c");
+        assert!(clock.reference(a));
+        assert!(clock.reference(a));
+        clock.reference(b);
+        assert_eq!(clock.sweep(), Some("c"));
+        assert_eq!(clock.references(a), Some(1));
+        assert_eq!(clock.sweep(), Some("b"));
+        assert_eq!(clock.sweep_by(|_, _| true), None);
+        assert_eq!(clock.remove(a), Some("a"));
+        assert!(!clock.reference(a));
+        assert!(clock.is_empty());
+        assert!(ClockList::<u8>::with_max_references(8).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::mem;
+use std::sync::Arc;
+
+use hashbrown::HashTable;
+
+use {KeyedState, Token};
+
+/// Encodes the keys of history entries, set with
+/// `CartCacheBuilder::ghost_key_codec()`. Keys are decoded when a history
+/// entry is promoted or dropped, and `decode()` must return a key equal to
+/// the one passed to `encode()`. Equal keys must have the same encoding.
+pub trait KeyCodec<K>: Send + Sync {
+    fn encode(&self, key: &K) -> Vec<u8>;
+
+    fn decode(&self, encoded: &[u8]) -> K;
+}
+
+/// Replaces the longest matching prefix of string keys, out of a dictionary
+/// of up to 255 prefixes such as URL schemes and host names, with its index.
+#[derive(Clone, Debug)]
+pub struct PrefixCodec {
+    prefixes: Vec<Box<str>>,
+}
+
+impl PrefixCodec {
+    pub fn new<I, P>(prefixes: I) -> Result<Self, &'static str>
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<Box<str>>,
+    {
+        let prefixes: Vec<Box<str>> = prefixes.into_iter().map(Into::into).collect();
+        if prefixes.len() > u8::MAX as usize {
+            return Err("Too many prefixes");
+        }
+        Ok(PrefixCodec { prefixes })
+    }
+}
+
+impl<K: AsRef<str> + From<String>> KeyCodec<K> for PrefixCodec {
+    fn encode(&self, key: &K) -> Vec<u8> {
+        let key = key.as_ref();
+        let (id, prefix_len) = self
+            .prefixes
+            .iter()
+            .enumerate()
+            .filter(|(_, prefix)| key.starts_with(&***prefix))
+            .map(|(i, prefix)| (i + 1, prefix.len()))
+            .max_by_key(|&(_, prefix_len)| prefix_len)
+            .unwrap_or((0, 0));
+        let mut encoded = Vec::with_capacity(1 + key.len() - prefix_len);
+        encoded.push(id as u8);
+        encoded.extend_from_slice(&key.as_bytes()[prefix_len..]);
+        encoded
+    }
+
+    fn decode(&self, encoded: &[u8]) -> K {
+        let prefix = match encoded[0] {
+            0 => "",
+            id => &self.prefixes[id as usize - 1],
+        };
+        K::from(prefix.to_owned() + &String::from_utf8_lossy(&encoded[1..]))
+    }
+}
+
+// Encoded keys of the history entries, by token, and the index used to find
+// the history entry of a key being inserted.
+#[derive(Clone)]
+pub(crate) struct GhostKeys<K> {
+    codec: Arc<dyn KeyCodec<K>>,
+    keys: HashMap<Token, Box<[u8]>>,
+    index: HashTable<Token>,
+    hasher: KeyedState,
+}
+
+impl<K> GhostKeys<K> {
+    pub fn new(codec: Arc<dyn KeyCodec<K>>) -> Self {
+        GhostKeys {
+            codec,
+            keys: HashMap::new(),
+            index: HashTable::new(),
+            hasher: KeyedState::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn insert(&mut self, token: Token, key: &K) {
+        let encoded = self.codec.encode(key).into_boxed_slice();
+        let (keys, hasher) = (&self.keys, &self.hasher);
+        self.index
+            .insert_unique(hasher.hash_one(&encoded), token, |token| {
+                hasher.hash_one(&keys[token])
+            });
+        self.keys.insert(token, encoded);
+    }
+
+    pub fn find(&self, key: &K) -> Option<Token> {
+        let encoded = self.codec.encode(key).into_boxed_slice();
+        self.index
+            .find(self.hasher.hash_one(&encoded), |token| {
+                self.keys[token] == encoded
+            })
+            .cloned()
+    }
+
+    pub fn key(&self, token: Token) -> Option<K> {
+        self.keys.get(&token).map(|encoded| self.decode(encoded))
+    }
+
+    pub fn remove(&mut self, token: Token) -> Option<Box<[u8]>> {
+        let encoded = self.keys.remove(&token)?;
+        if let Ok(entry) = self
+            .index
+            .find_entry(self.hasher.hash_one(&encoded), |&other| other == token)
+        {
+            entry.remove();
+        }
+        Some(encoded)
+    }
+
+    #[inline]
+    pub fn decode(&self, encoded: &[u8]) -> K {
+        self.codec.decode(encoded)
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.index.clear();
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.keys.capacity() * (mem::size_of::<(Token, Box<[u8]>)>() + 1)
+            + self
+                .keys
+                .values()
+                .map(|encoded| encoded.len())
+                .sum::<usize>()
+            + self.index.capacity() * (mem::size_of::<Token>() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codec::{KeyCodec, PrefixCodec};
+    use {CartCache, CartCacheBuilder, NotificationMode};
+
+    #[test]
+    fn prefix_codec() {
+        let codec = PrefixCodec::new(vec!["https://", "https://example.com/"]).unwrap();
+        for key in ["https://example.com/a", "https://other.org/", "ftp://x", ""] {
+            let encoded = KeyCodec::<String>::encode(&codec, &key.to_owned());
+            assert!(encoded.len() <= key.len() + 1);
+            assert_eq!(KeyCodec::<String>::decode(&codec, &encoded), key);
+        }
+        assert_eq!(
+            KeyCodec::<String>::encode(&codec, &"https://example.com/a".to_owned()),
+            [2, b'a']
+        );
+        assert!(PrefixCodec::new((0..256).map(|i| i.to_string())).is_err());
+    }
+
+    #[test]
+    fn ghost_key_codec() {
+        let url = |i: usize| format!("https://example.com/assets/{}.png", i);
+        let mut cache: CartCache<String, usize> = CartCacheBuilder::new(16)
+            .ghost_key_codec(PrefixCodec::new(vec!["https://example.com/"]).unwrap())
+            .eviction_notifications(NotificationMode::Queue)
+            .build()
+            .unwrap();
+        let mut plain: CartCache<String, usize> = CartCacheBuilder::new(16)
+            .eviction_notifications(NotificationMode::Queue)
+            .build()
+            .unwrap();
+        for i in 0..500 {
+            let key = url(i * 7 % 23 + i % 5);
+            assert_eq!(cache.insert(key.clone(), i), plain.insert(key, i));
+            cache.check_invariants().unwrap();
+        }
+        assert_eq!(cache.state_digest(), plain.state_digest());
+        assert_eq!(cache.policy_state(), plain.policy_state());
+        assert_eq!(cache.len(), plain.len());
+        let evicted = |cache: &mut CartCache<String, usize>| -> Vec<String> {
+            cache
+                .drain_evictions()
+                .into_iter()
+                .map(|event| event.key)
+                .collect()
+        };
+        let evictions = evicted(&mut plain);
+        assert!(!evictions.is_empty());
+        assert_eq!(evicted(&mut cache), evictions);
+
+        let ghost = (0..30)
+            .map(url)
+            .find(|key| plain.contains_key(key) && !plain.scan().iter().any(|(k, _)| k == key))
+            .unwrap();
+        assert!(!cache.contains_key(&ghost));
+        let len = cache.len();
+        assert!(!cache.insert(ghost.clone(), 0));
+        assert!(!plain.insert(ghost.clone(), 0));
+        assert_eq!(cache.get(&ghost), plain.get(&ghost));
+        assert_eq!(cache.len(), len);
+        assert_eq!(cache.state_digest(), plain.state_digest());
+        cache.check_invariants().unwrap();
+        assert!(cache.pop_victim().is_some());
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!(CartCacheBuilder::<String, usize>::new(8)
+            .ghost_key_codec(PrefixCodec::new(vec!["https://"]).unwrap())
+            .max_weight(8)
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,514 @@
+use std::cmp::{max, min};
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use hashbrown::HashTable;
+
+use {Equivalent, KeyedState, Node, Stats, Token, XLinkedList};
+
+/// A CART cache for small `Copy` keys and values, such as integers, built
+/// with `CartCacheBuilder::build_compact()`.
+///
+/// Keys, values and list nodes are stored inline in separate arrays, and the
+/// index only holds 32-bit slot numbers, so a slot takes the size of its key
+/// and value plus about 17 bytes, instead of a full entry with its weight,
+/// group, deadline and checksum, and a second copy of the key in the index.
+/// Clock sweeps only read the node array.
+///
+/// Replacement is the same as that of a `CartCache` with the default
+/// options. Weights, TTLs, groups, callbacks and notifications are not
+/// supported.
+pub struct CompactCartCache<K, V, S = KeyedState> {
+    keys: Vec<K>,
+    values: Vec<V>,
+    nodes: Vec<Node>,
+    // Vacant slots, reused first.
+    free: Vec<u32>,
+    index: HashTable<u32>,
+    hasher: S,
+    t1: XLinkedList,
+    t2: XLinkedList,
+    b1: XLinkedList,
+    b2: XLinkedList,
+    c: usize,
+    capacity: usize,
+    p: usize,
+    p_min: usize,
+    p_max: usize,
+    q: usize,
+    shortterm_count: usize,
+    longterm_count: usize,
+    max_references: u8,
+    reference_decrement: u8,
+    inserted: u64,
+    evicted: u64,
+    hits: u64,
+    misses: u64,
+    exhausted: u64,
+    resets: u64,
+}
+
+impl<K: Copy + Eq + Hash, V: Copy, S: BuildHasher> CompactCartCache<K, V, S> {
+    pub(crate) fn with_parameters(
+        capacity: usize,
+        hasher: S,
+        (p_min, p_max): (usize, usize),
+        (max_references, reference_decrement): (u8, u8),
+    ) -> Self {
+        CompactCartCache {
+            keys: Vec::new(),
+            values: Vec::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            index: HashTable::new(),
+            hasher,
+            t1: XLinkedList::new(),
+            t2: XLinkedList::new(),
+            b1: XLinkedList::new(),
+            b2: XLinkedList::new(),
+            c: capacity / 2,
+            capacity,
+            p: p_min,
+            p_min,
+            p_max,
+            q: 0,
+            shortterm_count: 0,
+            longterm_count: 0,
+            max_references,
+            reference_decrement,
+            inserted: 0,
+            evicted: 0,
+            hits: 0,
+            misses: 0,
+            exhausted: 0,
+            resets: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of resident and history entries.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn recent_len(&self) -> usize {
+        self.t1.len()
+    }
+
+    pub fn frequent_len(&self) -> usize {
+        self.t2.len()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            inserted: self.inserted,
+            evicted: self.evicted,
+            evicted_by_count: self.evicted,
+            hits: self.hits,
+            misses: self.misses,
+            exhausted: self.exhausted,
+            resets: self.resets,
+            ..Stats::default()
+        }
+    }
+
+    /// Memory used by the cache, which never depends on the keys and values
+    /// as they can't own heap data.
+    pub fn heap_bytes(&self) -> usize {
+        self.keys.capacity() * mem::size_of::<K>()
+            + self.values.capacity() * mem::size_of::<V>()
+            + self.nodes.capacity() * mem::size_of::<Node>()
+            + self.free.capacity() * mem::size_of::<u32>()
+            + self.index.capacity() * (mem::size_of::<u32>() + 1)
+    }
+
+    fn find<Q>(&self, hash: u64, key: &Q) -> Option<Token>
+    where
+        Q: Equivalent<K> + ?Sized,
+    {
+        let keys = &self.keys;
+        self.index
+            .find(hash, |&token| key.equivalent(&keys[token as usize]))
+            .map(|&token| token as Token)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.find(self.hasher.hash_one(key), key).is_some()
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.find(self.hasher.hash_one(key), key) {
+            Some(token) => {
+                self.nodes[token].reference(self.max_references);
+                self.hits += 1;
+                Some(&self.values[token])
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Returns `true` if the key was resident, and its value was replaced.
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let hash = self.hasher.hash_one(key);
+        let token = match self.find(hash, &key) {
+            Some(token) if !self.nodes[token].is_history() => {
+                self.nodes[token].reference(self.max_references);
+                self.values[token] = value;
+                return true;
+            }
+            token => token,
+        };
+        self.evict_if_full(token.is_some());
+        match token {
+            None => self.insert_new_entry(hash, key, value),
+            Some(token) => {
+                if self.nodes[token].is_longterm() {
+                    self.promote_from_b2(token);
+                } else {
+                    self.promote_from_b1(token);
+                }
+                self.values[token] = value;
+            }
+        }
+        false
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = self.find(self.hasher.hash_one(key), key)?;
+        let node = self.nodes[token];
+        let list = match (node.is_history(), node.is_longterm(), node.is_in_t2()) {
+            (true, true, _) => &mut self.b2,
+            (true, false, _) => &mut self.b1,
+            (false, _, true) => &mut self.t2,
+            (false, _, false) => &mut self.t1,
+        };
+        list.remove(&mut self.nodes, token);
+        if !node.is_history() {
+            if node.is_longterm() {
+                self.longterm_count -= 1;
+            } else {
+                self.shortterm_count -= 1;
+            }
+        }
+        self.drop_entry(token);
+        Some(self.values[token])
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+        self.nodes.clear();
+        self.free.clear();
+        self.index.clear();
+        self.t1.clear();
+        self.t2.clear();
+        self.b1.clear();
+        self.b2.clear();
+        self.p = self.p_min;
+        self.q = 0;
+        self.shortterm_count = 0;
+        self.longterm_count = 0;
+        self.inserted = 0;
+        self.evicted = 0;
+        self.hits = 0;
+        self.misses = 0;
+        self.exhausted = 0;
+        self.resets += 1;
+    }
+
+    fn insert_new_entry(&mut self, hash: u64, key: K, value: V) {
+        let token = match self.free.pop() {
+            Some(token) => {
+                let token = token as Token;
+                self.keys[token] = key;
+                self.values[token] = value;
+                self.nodes[token] = Node::default();
+                token
+            }
+            None => {
+                self.keys.push(key);
+                self.values.push(value);
+                self.nodes.push(Node::default());
+                self.nodes.len() - 1
+            }
+        };
+        self.t1.push_back(&mut self.nodes, token);
+        self.shortterm_count += 1;
+        let (keys, hasher) = (&self.keys, &self.hasher);
+        self.index.insert_unique(hash, token as u32, |&token| {
+            hasher.hash_one(keys[token as usize])
+        });
+        self.inserted += 1;
+        self.shrink_to_capacity();
+    }
+
+    // Removes an unlinked entry from the index. Its slot keeps the key and
+    // the value until it is reused.
+    fn drop_entry(&mut self, token: Token) {
+        let hash = self.hasher.hash_one(self.keys[token]);
+        if let Ok(entry) = self
+            .index
+            .find_entry(hash, |&other| other as Token == token)
+        {
+            entry.remove();
+        }
+        self.free.push(token as u32);
+    }
+
+    fn evict_if_full(&mut self, is_history: bool) {
+        if self.t1.len() + self.t2.len() >= self.c {
+            let demoted = self.replace().is_some();
+            if !is_history && self.b1.len() + self.b2.len() > self.c {
+                self.evict_history();
+            }
+            if demoted {
+                self.evicted += 1;
+            }
+        }
+    }
+
+    fn shrink_to_capacity(&mut self) {
+        while self.t1.len() + self.t2.len() > self.c {
+            if self.replace().is_none() {
+                break;
+            }
+            self.evicted += 1;
+        }
+        while self.b1.len() + self.b2.len() > self.c {
+            if !self.evict_history() {
+                break;
+            }
+        }
+    }
+
+    fn evict_history(&mut self) -> bool {
+        let token = if self.b1.len() > self.q || self.b2.is_empty() {
+            self.b1.pop_front(&mut self.nodes)
+        } else {
+            self.b2.pop_front(&mut self.nodes)
+        };
+        match token {
+            Some(token) => {
+                self.drop_entry(token);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_p(&mut self, p: usize) {
+        self.p = min(max(p, self.p_min), self.p_max);
+    }
+
+    fn promote_from_b1(&mut self, token: Token) {
+        let p = self.p + max(1, self.shortterm_count / self.b1.len());
+        self.set_p(p);
+        self.b1.remove(&mut self.nodes, token);
+        self.revive(token);
+    }
+
+    fn promote_from_b2(&mut self, token: Token) {
+        let p = self
+            .p
+            .saturating_sub(max(1, self.longterm_count / self.b2.len()));
+        self.set_p(p);
+        self.b2.remove(&mut self.nodes, token);
+        self.revive(token);
+        if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count >= self.c {
+            self.q = min(self.q + 1, self.capacity.saturating_sub(self.t1.len()));
+        }
+    }
+
+    // Makes a history entry a long-term resident of T1.
+    fn revive(&mut self, token: Token) {
+        let node = &mut self.nodes[token];
+        node.set_history(false);
+        node.set_references(0);
+        node.set_longterm(true);
+        self.longterm_count += 1;
+        self.t1.push_back(&mut self.nodes, token);
+    }
+
+    fn replace(&mut self) -> Option<Token> {
+        self.replace_t2();
+        self.replace_t1();
+        self.demote_victim()
+    }
+
+    fn replace_t2(&mut self) {
+        while let Some(token) = self.t2.front() {
+            if !self.nodes[token].is_reference() {
+                break;
+            }
+            self.nodes[token].decay_references(self.reference_decrement);
+            if self.nodes[token].is_reference() {
+                self.t2.advance(&self.nodes);
+                continue;
+            }
+            self.t2.pop_front(&mut self.nodes);
+            self.nodes[token].set_in_t2(false);
+            self.t1.push_back(&mut self.nodes, token);
+            if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count >= self.c {
+                self.q = min(self.q + 1, self.capacity.saturating_sub(self.t1.len()));
+            }
+        }
+    }
+
+    fn replace_t1(&mut self) {
+        while let Some(token) = self.t1.front() {
+            let node = self.nodes[token];
+            if !(node.is_longterm() || node.is_reference()) {
+                break;
+            }
+            if node.is_reference() {
+                self.t1.advance(&self.nodes);
+                let threshold = min(self.p + 1, self.b1.len());
+                let node = &mut self.nodes[token];
+                node.decay_references(self.reference_decrement);
+                if self.t1.len() >= threshold && !node.is_longterm() {
+                    node.set_longterm(true);
+                    self.shortterm_count -= 1;
+                    self.longterm_count += 1;
+                }
+            } else {
+                self.t1.remove(&mut self.nodes, token);
+                self.nodes[token].set_in_t2(true);
+                self.t2.push_back(&mut self.nodes, token);
+                self.q = if self.q > 0 {
+                    max(self.q - 1, self.c.saturating_sub(self.t1.len()))
+                } else {
+                    self.c.saturating_sub(self.t1.len())
+                };
+            }
+        }
+    }
+
+    // Like `CartCache` with `Exhausted::OverProvision`, nothing is demoted
+    // if T1 is below its target size and T2 is empty.
+    fn demote_victim(&mut self) -> Option<Token> {
+        let token = if self.t1.len() >= max(1, self.p) {
+            self.t1.front()?
+        } else if self.t2.is_empty() && !self.t1.is_empty() {
+            self.exhausted += 1;
+            return None;
+        } else {
+            self.t2.front()?
+        };
+        let node = &mut self.nodes[token];
+        let longterm = node.is_longterm();
+        let in_t2 = node.is_in_t2();
+        node.set_history(true);
+        node.set_in_t2(false);
+        if in_t2 {
+            self.t2.remove(&mut self.nodes, token);
+        } else {
+            self.t1.remove(&mut self.nodes, token);
+        }
+        if longterm {
+            self.longterm_count -= 1;
+            self.b2.push_back(&mut self.nodes, token);
+        } else {
+            self.shortterm_count -= 1;
+            self.b1.push_back(&mut self.nodes, token);
+        }
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use self::rand::prelude::*;
+    use compact::CompactCartCache;
+    use {CartCache, CartCacheBuilder};
+
+    #[test]
+    fn compact() {
+        let mut cache: CompactCartCache<u32, u32> =
+            CartCacheBuilder::new(8).build_compact().unwrap();
+        for i in 0..4 {
+            assert!(!cache.insert(i, i * 10));
+        }
+        assert!(cache.insert(0, 1));
+        assert_eq!(cache.get(&0), Some(&1));
+        assert_eq!(cache.remove(&0), Some(1));
+        assert!(!cache.contains_key(&0));
+        for i in 4..32 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.recent_len() + cache.frequent_len(), 4);
+        assert_eq!(cache.len(), 8);
+        assert_eq!(cache.get(&31), Some(&310));
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!(CartCacheBuilder::<u32, u32>::new(8)
+            .max_weight(4)
+            .build_compact()
+            .is_err());
+    }
+
+    #[test]
+    fn same_replacement() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut cache: CartCache<u16, u16> = CartCacheBuilder::new(64).build().unwrap();
+        let mut compact: CompactCartCache<u16, u16> =
+            CartCacheBuilder::new(64).build_compact().unwrap();
+        for _ in 0..20_000 {
+            let key = rng.gen_range(0..256);
+            match rng.gen_range(0..10) {
+                0 => assert_eq!(cache.remove(&key), compact.remove(&key)),
+                1..=4 => assert_eq!(cache.get(&key), compact.get(&key)),
+                _ => assert_eq!(cache.insert(key, key), compact.insert(key, key)),
+            }
+            assert_eq!((cache.p, cache.q), (compact.p, compact.q));
+            assert_eq!(cache.len(), compact.len());
+        }
+        let mut resident: Vec<u16> = cache.scan().iter().map(|(&key, _)| key).collect();
+        let mut compact_resident: Vec<u16> = compact
+            .t1
+            .iter(&compact.nodes)
+            .chain(compact.t2.iter(&compact.nodes))
+            .map(|token| compact.keys[token])
+            .collect();
+        resident.sort_unstable();
+        compact_resident.sort_unstable();
+        assert_eq!(resident, compact_resident);
+        assert_eq!(cache.stats().hits, compact.stats().hits);
+    }
+
+    #[test]
+    fn footprint() {
+        let mut cache: CartCache<u64, u64> = CartCacheBuilder::new(4096)
+            .measure_memory()
+            .build()
+            .unwrap();
+        let mut compact: CompactCartCache<u64, u64> =
+            CartCacheBuilder::new(4096).build_compact().unwrap();
+        for i in 0..8192 {
+            cache.insert(i, i);
+            compact.insert(i, i);
+        }
+        assert_eq!(cache.len(), compact.len());
+        assert!(compact.heap_bytes() * 3 < cache.heap_bytes().unwrap());
+    }
+}
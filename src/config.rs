@@ -0,0 +1,132 @@
+use std::hash::Hash;
+use std::time::Duration;
+
+use {CartCache, CartCacheBuilder, NotificationMode};
+
+/// The closure-free part of the builder settings, constructible in `const`
+/// context so that global caches can be declared next to their settings:
+///
+/// ```
+/// use cart_cache::{CartCache, CartCacheConfig};
+/// use std::sync::{Mutex, OnceLock};
+///
+/// const CONFIG: CartCacheConfig = CartCacheConfig::new(1024).key_filter(true);
+/// static CACHE: OnceLock<Mutex<CartCache<u64, String>>> = OnceLock::new();
+///
+/// let cache = CACHE.get_or_init(|| Mutex::new(CartCache::from_config(&CONFIG).unwrap()));
+/// cache.lock().unwrap().insert(1, "one".to_string());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CartCacheConfig {
+    pub capacity: usize,
+    pub max_weight: Option<usize>,
+    pub ttl: Option<Duration>,
+    pub ttl_jitter: u8,
+    pub early_expiration: Option<f64>,
+    pub target_bounds: (f64, f64),
+    pub static_parameters: Option<(usize, usize)>,
+    pub reference_counter: (u8, u8),
+    pub promotion_threshold: f64,
+    pub key_filter: bool,
+    pub eviction_notifications: Option<NotificationMode>,
+    pub trace_window: Option<usize>,
+}
+
+impl CartCacheConfig {
+    pub const fn new(capacity: usize) -> Self {
+        CartCacheConfig {
+            capacity,
+            max_weight: None,
+            ttl: None,
+            ttl_jitter: 0,
+            early_expiration: None,
+            target_bounds: (0.0, 1.0),
+            static_parameters: None,
+            reference_counter: (1, 1),
+            promotion_threshold: 1.0,
+            key_filter: false,
+            eviction_notifications: None,
+            trace_window: None,
+        }
+    }
+
+    pub const fn max_weight(mut self, max_weight: usize) -> Self {
+        self.max_weight = Some(max_weight);
+        self
+    }
+
+    pub const fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub const fn ttl_jitter(mut self, percent: u8) -> Self {
+        self.ttl_jitter = percent;
+        self
+    }
+
+    pub const fn early_expiration(mut self, beta: f64) -> Self {
+        self.early_expiration = Some(beta);
+        self
+    }
+
+    pub const fn target_bounds(mut self, p_min: f64, p_max: f64) -> Self {
+        self.target_bounds = (p_min, p_max);
+        self
+    }
+
+    pub const fn static_parameters(mut self, p: usize, q: usize) -> Self {
+        self.static_parameters = Some((p, q));
+        self
+    }
+
+    pub const fn reference_counter(mut self, max_references: u8, decrement: u8) -> Self {
+        self.reference_counter = (max_references, decrement);
+        self
+    }
+
+    pub const fn promotion_threshold(mut self, factor: f64) -> Self {
+        self.promotion_threshold = factor;
+        self
+    }
+
+    pub const fn key_filter(mut self, enabled: bool) -> Self {
+        self.key_filter = enabled;
+        self
+    }
+
+    pub const fn eviction_notifications(mut self, mode: NotificationMode) -> Self {
+        self.eviction_notifications = Some(mode);
+        self
+    }
+
+    pub const fn trace_window(mut self, window: usize) -> Self {
+        self.trace_window = Some(window);
+        self
+    }
+}
+
+impl<K: Eq + Hash, V> CartCacheBuilder<K, V> {
+    /// Starts from `config`; closures such as a weigher can then be added.
+    pub fn from_config(config: &CartCacheConfig) -> Self {
+        let mut builder = CartCacheBuilder::new(config.capacity);
+        builder.max_weight = config.max_weight;
+        builder.ttl = config.ttl;
+        builder.ttl_jitter = config.ttl_jitter;
+        builder.early_expiration = config.early_expiration;
+        builder.target_bounds = config.target_bounds;
+        builder.static_parameters = config.static_parameters;
+        builder.reference_counter = config.reference_counter;
+        builder.promotion_threshold = config.promotion_threshold;
+        builder.key_filter = config.key_filter;
+        builder.eviction_notifications = config.eviction_notifications;
+        builder.trace_window = config.trace_window;
+        builder
+    }
+}
+
+impl<K: Eq + Hash, V> CartCache<K, V> {
+    pub fn from_config(config: &CartCacheConfig) -> Result<Self, &'static str> {
+        CartCacheBuilder::from_config(config).build()
+    }
+}
@@ -0,0 +1,129 @@
+use std::hash::Hash;
+
+use CartCache;
+
+/// The observable behavior shared by caches, whatever their replacement
+/// policy. Implement it to run `conformance()` against another cache.
+pub trait CacheBehavior<K, V> {
+    /// Returns `true` if a value was already stored for `key`.
+    fn insert(&mut self, key: K, value: V) -> bool;
+
+    fn get(&mut self, key: &K) -> Option<V>;
+
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    fn clear(&mut self);
+
+    /// Maximum number of keys `get()` can find at once.
+    fn capacity(&self) -> usize;
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> CacheBehavior<K, V> for CartCache<K, V> {
+    fn insert(&mut self, key: K, value: V) -> bool {
+        CartCache::insert(self, key, value)
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        CartCache::get(self, key).cloned()
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        CartCache::remove(self, key)
+    }
+
+    fn clear(&mut self) {
+        CartCache::clear(self)
+    }
+
+    fn capacity(&self) -> usize {
+        CartCache::capacity(self)
+    }
+}
+
+/// Runs a cache built by `new_cache(capacity)` through insert, get, remove
+/// and capacity edge cases. On failure, returns the expectation that was
+/// not met.
+pub fn conformance<C, F>(new_cache: F) -> Result<(), &'static str>
+where
+    C: CacheBehavior<u32, u32>,
+    F: Fn(usize) -> C,
+{
+    let mut cache = new_cache(16);
+    if cache.get(&1).is_some() {
+        return Err("A new cache is not empty");
+    }
+    if cache.insert(1, 10) || cache.get(&1) != Some(10) {
+        return Err("An inserted value is not found");
+    }
+    if !cache.insert(1, 11) || cache.get(&1) != Some(11) {
+        return Err("An overwritten value is not replaced");
+    }
+    if cache.remove(&1) != Some(11) || cache.get(&1).is_some() {
+        return Err("A removed value is still found");
+    }
+    if cache.remove(&1).is_some() {
+        return Err("A missing key is removed");
+    }
+    if cache.insert(1, 12) {
+        return Err("A removed key is reported as overwritten");
+    }
+
+    for capacity in [2, 16, 64] {
+        let mut cache = new_cache(capacity);
+        for key in 0..10 * capacity as u32 {
+            cache.insert(key, key);
+            if cache.get(&key) != Some(key) {
+                return Err("The last inserted value is not found");
+            }
+        }
+        let found = (0..10 * capacity as u32)
+            .filter(|key| cache.get(key).is_some())
+            .count();
+        if found > cache.capacity() {
+            return Err("More values found than the capacity");
+        }
+        if found == 0 {
+            return Err("Nothing is found after filling the cache");
+        }
+        cache.clear();
+        if (0..10 * capacity as u32).any(|key| cache.get(&key).is_some()) {
+            return Err("A value is found after clear()");
+        }
+    }
+
+    let mut cache = new_cache(64);
+    cache.insert(0, 0);
+    for key in 1..1000 {
+        cache.insert(key, key);
+        if cache.get(&0) != Some(0) {
+            return Err("A key read after every insert is evicted");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use conformance::conformance;
+    use std::time::Duration;
+    use {CartCache, CartCacheBuilder};
+
+    #[test]
+    fn cart_cache() {
+        assert_eq!(
+            conformance(|capacity| CartCache::<u32, u32>::new(capacity).unwrap()),
+            Ok(())
+        );
+        assert_eq!(
+            conformance(|capacity| {
+                CartCacheBuilder::<u32, u32>::new(capacity)
+                    .key_filter(true)
+                    .ttl(Duration::from_secs(60))
+                    .promotion_threshold(0.5)
+                    .build()
+                    .unwrap()
+            }),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, Hash};
+
+use {CartCache, CartCacheBuilder, Token, Transition, XLinkedList};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugNode<K> {
+    pub key: K,
+    pub references: u8,
+    pub longterm: bool,
+}
+
+/// Snapshot of the replacement state. Lists are listed from their clock hand,
+/// values are not included.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugDump<K> {
+    pub capacity: usize,
+    pub c: usize,
+    pub p: usize,
+    pub q: usize,
+    pub p_min: usize,
+    pub p_max: usize,
+    pub adaptive: bool,
+    pub max_references: u8,
+    pub reference_decrement: u8,
+    pub shortterm_count: usize,
+    pub longterm_count: usize,
+    pub t1: Vec<DebugNode<K>>,
+    pub t2: Vec<DebugNode<K>>,
+    pub b1: Vec<DebugNode<K>>,
+    pub b2: Vec<DebugNode<K>>,
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> CartCache<K, V, S> {
+    fn debug_list<'a, T, F>(&'a self, list: &XLinkedList, key: &F) -> Vec<DebugNode<T>>
+    where
+        F: Fn(&'a K) -> T,
+    {
+        list.iter(&self.nodes)
+            .filter_map(|token: Token| {
+                let node = &self.nodes[token];
+                Some(DebugNode {
+                    key: key(&self.slab.get(token)?.key),
+                    references: node.references(),
+                    longterm: node.is_longterm(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn debug_dump_with<'a, T, F>(&'a self, key: F) -> DebugDump<T>
+    where
+        F: Fn(&'a K) -> T,
+    {
+        DebugDump {
+            capacity: self.capacity,
+            c: self.c,
+            p: self.p,
+            q: self.q,
+            p_min: self.p_min,
+            p_max: self.p_max,
+            adaptive: self.adaptive,
+            max_references: self.max_references,
+            reference_decrement: self.reference_decrement,
+            shortterm_count: self.shortterm_count,
+            longterm_count: self.longterm_count,
+            t1: self.debug_list(&self.t1, &key),
+            t2: self.debug_list(&self.t2, &key),
+            b1: self.debug_list(&self.b1, &key),
+            b2: self.debug_list(&self.b2, &key),
+        }
+    }
+
+    pub fn debug_dump(&self) -> DebugDump<K>
+    where
+        K: Clone,
+    {
+        self.debug_dump_with(K::clone)
+    }
+
+    /// Same as `to_json_debug()`, with keys replaced by their `key_hash()`,
+    /// so that they cannot be recovered without the cache's hash keys.
+    pub fn to_json_debug_anonymized(&self) -> String {
+        let dump = self.debug_dump_with(|key| self.map.hasher().hash_one(key));
+        serde_json::to_string_pretty(&dump).expect("Unable to serialize debug dump")
+    }
+
+    pub fn to_json_debug(&self) -> String
+    where
+        K: Serialize,
+    {
+        let dump = self.debug_dump_with(|key| key);
+        serde_json::to_string_pretty(&dump).expect("Unable to serialize debug dump")
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> CartCache<K, V> {
+    /// Rebuilds a cache with the exact lists, flags and parameters of `dump`,
+    /// using `value` to provide values for the dumped keys.
+    pub fn from_debug_dump<F>(dump: DebugDump<K>, mut value: F) -> Result<Self, &'static str>
+    where
+        F: FnMut(&K) -> V,
+    {
+        if dump.capacity == 0 || dump.c != dump.capacity / 2 {
+            return Err("Invalid debug dump");
+        }
+        if dump.p_min > dump.p_max || dump.p_max > dump.c || dump.p > dump.c || dump.q > 2 * dump.c
+        {
+            return Err("Invalid debug dump");
+        }
+        if dump.t1.len() + dump.t2.len() > dump.c || dump.b1.len() + dump.b2.len() > dump.c {
+            return Err("Invalid debug dump");
+        }
+        let mut cache = CartCacheBuilder::new(dump.capacity)
+            .reference_counter(dump.max_references, dump.reference_decrement)
+            .build()?;
+        let (mut shortterm_count, mut longterm_count) = (0, 0);
+        cache.p = dump.p;
+        cache.q = dump.q;
+        cache.p_min = dump.p_min;
+        cache.p_max = dump.p_max;
+        cache.adaptive = dump.adaptive;
+        let lists = vec![
+            (dump.t1, false, false),
+            (dump.t2, false, true),
+            (dump.b1, true, false),
+            (dump.b2, true, false),
+        ];
+        for (list_index, (list, history, in_t2)) in lists.into_iter().enumerate() {
+            for debug_node in list {
+                if cache.map.contains_key(&debug_node.key)
+                    || debug_node.references > dump.max_references
+                {
+                    return Err("Invalid debug dump");
+                }
+                let value = value(&debug_node.key);
+                let token = cache.insert_new_entry(debug_node.key, value);
+                cache.unlink(token);
+                if history {
+                    cache.track_resident(token, false);
+                }
+                let longterm = debug_node.longterm || list_index == 3;
+                let node = &mut cache.nodes[token];
+                node.set_history(history);
+                node.set_in_t2(in_t2);
+                node.set_references(debug_node.references);
+                node.set_longterm(longterm);
+                match (history, longterm) {
+                    (true, _) => {}
+                    (false, true) => longterm_count += 1,
+                    (false, false) => shortterm_count += 1,
+                }
+                let list = match list_index {
+                    0 => &mut cache.t1,
+                    1 => &mut cache.t2,
+                    2 => &mut cache.b1,
+                    _ => &mut cache.b2,
+                };
+                list.push_back(&mut cache.nodes, token);
+                cache.notify_transition(token, Transition::Admitted);
+                if history {
+                    cache.notify_transition(token, Transition::Demoted);
+                }
+            }
+        }
+        cache.shortterm_count = shortterm_count;
+        cache.longterm_count = longterm_count;
+        cache.inserted = 0;
+        Ok(cache)
+    }
+
+    pub fn from_json_debug<F>(json: &str, value: F) -> Result<Self, &'static str>
+    where
+        K: DeserializeOwned,
+        F: FnMut(&K) -> V,
+    {
+        let dump = serde_json::from_str(json).map_err(|_| "Invalid debug dump")?;
+        Self::from_debug_dump(dump, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use debug::DebugDump;
+    use CartCache;
+
+    #[test]
+    fn debug_dump_roundtrip() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        for i in 0..40 {
+            cache.insert(i % 13, i);
+            cache.get(&(i % 5));
+        }
+        let json = cache.to_json_debug();
+        let mut restored = CartCache::from_json_debug(&json, |&key| key).unwrap();
+        assert_eq!(restored.debug_dump(), cache.debug_dump());
+        for i in 40..80 {
+            cache.insert(i % 17, i);
+            restored.insert(i % 17, i);
+        }
+        assert_eq!(restored.debug_dump(), cache.debug_dump());
+    }
+
+    #[test]
+    fn debug_dump_counts_are_recomputed() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        for i in 0..40 {
+            cache.insert(i % 13, i);
+            cache.get(&(i % 5));
+        }
+        let mut dump = cache.debug_dump();
+        dump.shortterm_count = 1000;
+        dump.longterm_count = 0;
+        let restored = CartCache::from_debug_dump(dump, |&key| key).unwrap();
+        assert_eq!(restored.debug_dump(), cache.debug_dump());
+        assert!(restored.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn json_debug() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        for i in 0..6 {
+            cache.insert(i, i);
+        }
+        cache.get(&5);
+        let dump: DebugDump<u32> = serde_json::from_str(&cache.to_json_debug()).unwrap();
+        assert_eq!(dump, cache.debug_dump());
+        assert_eq!(dump.t1.len() + dump.t2.len(), 4);
+        assert!(dump
+            .t1
+            .iter()
+            .any(|node| node.key == 5 && node.references > 0));
+        let anonymized: DebugDump<u64> =
+            serde_json::from_str(&cache.to_json_debug_anonymized()).unwrap();
+        assert_eq!(anonymized.b1.len(), dump.b1.len());
+    }
+}
@@ -0,0 +1,278 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Arc;
+
+use {CartCache, CartCacheBuilder, NotificationMode, Stats};
+
+/// A value shared by every key whose content hashes the same. Only the entry
+/// of one of these keys, the owner, carries the weight of the payload.
+pub struct Shared<V> {
+    payload: Arc<V>,
+    weight: usize,
+}
+
+struct Payload<K, V> {
+    value: Arc<V>,
+    weight: usize,
+    owner: K,
+    holders: HashSet<K>,
+}
+
+/// A cache storing identical values once. Values are identified by a
+/// user-provided content hash, so hash collisions must be ruled out by the
+/// hash function itself.
+///
+/// The weight of a shared value is counted once, and moves to another key
+/// when the key holding it leaves the cache.
+///
+/// Eviction notifications are used to release shared values, so they are
+/// not available to callers.
+pub struct DedupCache<K, V>
+where
+    K: Eq + Hash,
+{
+    cache: CartCache<K, Shared<V>>,
+    payloads: HashMap<u64, Payload<K, V>>,
+    hashes: HashMap<K, u64>,
+    content_hash: Box<dyn Fn(&V) -> u64 + Send + Sync>,
+    weigher: Box<dyn Fn(&V) -> usize + Send + Sync>,
+}
+
+impl<K, V> DedupCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new<H, W>(
+        capacity: usize,
+        max_weight: usize,
+        content_hash: H,
+        weigher: W,
+    ) -> Result<Self, &'static str>
+    where
+        H: Fn(&V) -> u64 + Send + Sync + 'static,
+        W: Fn(&V) -> usize + Send + Sync + 'static,
+    {
+        Self::from_builder(
+            CartCacheBuilder::new(capacity).max_weight(max_weight),
+            content_hash,
+            weigher,
+        )
+    }
+
+    /// The builder's weigher is replaced.
+    pub fn from_builder<H, W>(
+        builder: CartCacheBuilder<K, Shared<V>>,
+        content_hash: H,
+        weigher: W,
+    ) -> Result<Self, &'static str>
+    where
+        H: Fn(&V) -> u64 + Send + Sync + 'static,
+        W: Fn(&V) -> usize + Send + Sync + 'static,
+    {
+        Ok(DedupCache {
+            cache: builder
+                .weigher(|_, shared: &Shared<V>| shared.weight)
+                .eviction_notifications(NotificationMode::Queue)
+                .build()?,
+            payloads: HashMap::new(),
+            hashes: HashMap::new(),
+            content_hash: Box::new(content_hash),
+            weigher: Box::new(weigher),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Total weight, with shared values counted once.
+    pub fn weight(&self) -> usize {
+        self.cache.weight()
+    }
+
+    /// Number of distinct values.
+    pub fn payloads(&self) -> usize {
+        self.payloads.len()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.cache.stats()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.payloads.clear();
+        self.hashes.clear();
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.sync();
+        let hash = (self.content_hash)(&value);
+        if self
+            .hashes
+            .get(&key)
+            .is_some_and(|&previous| previous != hash)
+        {
+            self.release(&key);
+        }
+        self.hashes.insert(key.clone(), hash);
+        let shared = match self.payloads.get_mut(&hash) {
+            Some(payload) => {
+                payload.holders.insert(key.clone());
+                Shared {
+                    payload: payload.value.clone(),
+                    weight: if payload.owner == key {
+                        payload.weight
+                    } else {
+                        0
+                    },
+                }
+            }
+            None => {
+                let weight = (self.weigher)(&value);
+                let value = Arc::new(value);
+                let mut holders = HashSet::new();
+                holders.insert(key.clone());
+                self.payloads.insert(
+                    hash,
+                    Payload {
+                        value: value.clone(),
+                        weight,
+                        owner: key.clone(),
+                        holders,
+                    },
+                );
+                Shared {
+                    payload: value,
+                    weight,
+                }
+            }
+        };
+        let updated = self.cache.insert(key, shared);
+        self.sync();
+        updated
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.sync();
+        self.cache.get(key).map(|shared| &shared.payload)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key = self.hashes.get_key_value(key)?.0.clone();
+        let shared = self.cache.remove(&key)?;
+        self.release(&key);
+        self.sync();
+        Some(shared.payload)
+    }
+
+    // Drops `key` from the holders of a payload, handing its weight over to
+    // another holder if `key` was the owner.
+    fn release(&mut self, key: &K) {
+        let hash = match self.hashes.remove(key) {
+            Some(hash) => hash,
+            None => return,
+        };
+        let new_owner = {
+            let payload = match self.payloads.get_mut(&hash) {
+                Some(payload) => payload,
+                None => return,
+            };
+            payload.holders.remove(key);
+            if payload.owner != *key {
+                return;
+            }
+            match payload.holders.iter().next() {
+                Some(holder) => {
+                    payload.owner = holder.clone();
+                    (holder.clone(), payload.weight)
+                }
+                None => {
+                    self.payloads.remove(&hash);
+                    return;
+                }
+            }
+        };
+        let (owner, weight) = new_owner;
+        if let Some(&token) = self.cache.map.get(&owner) {
+            self.cache.slab[token].value.weight = weight;
+            self.cache.finish_mutation(&owner);
+        }
+    }
+
+    // Handing weight over can evict more entries, so this runs until the
+    // queue is empty.
+    fn sync(&mut self) {
+        while self.cache.pending_evictions() > 0 {
+            for event in self.cache.drain_evictions() {
+                // The key may have been inserted again since it was evicted.
+                if !self.cache.contains_key(&event.key) {
+                    self.release(&event.key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dedup::DedupCache;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use CartCacheBuilder;
+
+    #[test]
+    fn shared_payloads() {
+        let mut cache: DedupCache<u32, Vec<u8>> =
+            DedupCache::new(64, 100, |value: &Vec<u8>| value[0] as u64, Vec::len).unwrap();
+        cache.insert(1, vec![1; 10]);
+        cache.insert(2, vec![1; 10]);
+        cache.insert(3, vec![2; 10]);
+        assert_eq!(cache.payloads(), 2);
+        assert_eq!(cache.weight(), 20);
+        let first = cache.get(&1).cloned().unwrap();
+        assert!(Arc::ptr_eq(&first, cache.get(&2).unwrap()));
+        assert_eq!(cache.remove(&1).map(|value| value.len()), Some(10));
+        assert_eq!(cache.weight(), 20);
+        cache.insert(2, vec![3; 10]);
+        assert_eq!(cache.payloads(), 2);
+        assert_eq!(cache.weight(), 20);
+        for i in 10..100 {
+            cache.insert(i, vec![i as u8; 10]);
+        }
+        assert!(cache.weight() <= 100);
+        assert_eq!(cache.payloads(), cache.len());
+    }
+
+    #[test]
+    fn stale_evictions() {
+        let builder = CartCacheBuilder::new(8).ttl(Duration::from_secs(1));
+        let mut cache: DedupCache<u32, Vec<u8>> =
+            DedupCache::from_builder(builder, |value: &Vec<u8>| value[0] as u64, Vec::len).unwrap();
+        cache.insert(1, vec![1; 10]);
+        cache.cache.advance_time(Duration::from_secs(2));
+        assert!(cache.get(&1).is_none());
+        cache.insert(1, vec![2; 10]);
+        assert_eq!(cache.payloads(), 1);
+        assert_eq!(cache.hashes.get(&1), Some(&2));
+        assert_eq!(cache.remove(&1).map(|value| value[0]), Some(2));
+        assert_eq!(cache.payloads(), 0);
+    }
+}
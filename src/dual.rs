@@ -0,0 +1,195 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use {CartCache, CartCacheBuilder, NotificationMode, Stats};
+
+/// A cache whose entries can also be looked up by a second unique key, such
+/// as an id and a slug. The secondary index follows inserts, evictions,
+/// expirations and removals.
+///
+/// Eviction notifications are used to keep the index in sync, so they are
+/// not available to callers.
+pub struct DualKeyCache<K, S, V>
+where
+    K: Eq + Hash,
+{
+    cache: CartCache<K, V>,
+    primary: HashMap<K, S>,
+    secondary: HashMap<S, K>,
+}
+
+impl<K, S, V> DualKeyCache<K, S, V>
+where
+    K: Eq + Hash + Clone,
+    S: Eq + Hash + Clone,
+{
+    pub fn new(capacity: usize) -> Result<Self, &'static str> {
+        Self::from_builder(CartCacheBuilder::new(capacity))
+    }
+
+    pub fn from_builder(builder: CartCacheBuilder<K, V>) -> Result<Self, &'static str> {
+        Ok(DualKeyCache {
+            cache: builder
+                .eviction_notifications(NotificationMode::Queue)
+                .build()?,
+            primary: HashMap::new(),
+            secondary: HashMap::new(),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.cache.stats()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.primary.clear();
+        self.secondary.clear();
+    }
+
+    /// Inserts an entry under both keys. An entry already using `secondary`
+    /// under another primary key is removed first.
+    pub fn insert(&mut self, key: K, secondary: S, value: V) -> bool {
+        self.sync();
+        match self.secondary.get(&secondary) {
+            Some(previous) if *previous != key => {
+                let previous = previous.clone();
+                self.remove(&previous);
+            }
+            _ => {}
+        }
+        if let Some(old) = self.primary.insert(key.clone(), secondary.clone()) {
+            if old != secondary {
+                self.secondary.remove(&old);
+            }
+        }
+        self.secondary.insert(secondary, key.clone());
+        let updated = self.cache.insert(key, value);
+        self.sync();
+        updated
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.sync();
+        self.cache.get(key)
+    }
+
+    pub fn get_by_secondary<Q>(&mut self, secondary: &Q) -> Option<&V>
+    where
+        S: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.sync();
+        let key = self.secondary.get(secondary)?;
+        self.cache.get(key)
+    }
+
+    pub fn secondary_key<Q>(&self, key: &Q) -> Option<&S>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.primary.get(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(secondary) = self.primary.remove(key) {
+            self.secondary.remove(&secondary);
+        }
+        let removed = self.cache.remove(key.borrow());
+        self.sync();
+        removed
+    }
+
+    // Expired entries are only dropped on access, so this also runs before
+    // lookups.
+    fn sync(&mut self) {
+        if self.cache.pending_evictions() == 0 {
+            return;
+        }
+        for event in self.cache.drain_evictions() {
+            // The key may have been inserted again since it was evicted.
+            if self.cache.contains_key(&event.key) {
+                continue;
+            }
+            if let Some(secondary) = self.primary.remove(&event.key) {
+                self.secondary.remove(&secondary);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dual::DualKeyCache;
+    use std::time::Duration;
+    use CartCacheBuilder;
+
+    #[test]
+    fn secondary_index() {
+        let mut cache: DualKeyCache<u32, String, &str> = DualKeyCache::new(4).unwrap();
+        cache.insert(1, "one".to_string(), "a");
+        cache.insert(2, "two".to_string(), "b");
+        assert_eq!(cache.get_by_secondary("one"), Some(&"a"));
+        cache.insert(1, "uno".to_string(), "c");
+        assert_eq!(cache.get_by_secondary("one"), None);
+        assert_eq!(cache.get_by_secondary("uno"), Some(&"c"));
+        cache.insert(3, "uno".to_string(), "d");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.remove(&2), Some("b"));
+        assert_eq!(cache.get_by_secondary("two"), None);
+        for i in 10..100 {
+            cache.insert(i, i.to_string(), "e");
+        }
+        assert_eq!(cache.primary.len(), cache.len());
+        assert_eq!(cache.secondary.len(), cache.len());
+    }
+
+    #[test]
+    fn stale_evictions() {
+        let builder = CartCacheBuilder::new(8).ttl(Duration::from_secs(1));
+        let mut cache: DualKeyCache<u32, String, &str> =
+            DualKeyCache::from_builder(builder).unwrap();
+        cache.insert(1, "one".to_string(), "a");
+        cache.cache.advance_time(Duration::from_secs(2));
+        assert_eq!(cache.get(&1), None);
+        cache.insert(1, "uno".to_string(), "b");
+        assert_eq!(cache.get_by_secondary("uno"), Some(&"b"));
+        assert_eq!(cache.secondary_key(&1), Some(&"uno".to_string()));
+    }
+
+    #[test]
+    fn corrupt_entries() {
+        let builder = CartCacheBuilder::new(8).checksum(|value: &u32| *value as u64);
+        let mut cache: DualKeyCache<u32, String, u32> =
+            DualKeyCache::from_builder(builder).unwrap();
+        cache.insert(1, "one".to_string(), 1);
+        let token = cache.cache.peek_token(&1).unwrap();
+        cache.cache.slab[token].value = 2;
+        assert_eq!(cache.get_by_secondary("one"), None);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.secondary_key(&1), None);
+        assert!(cache.secondary.is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use sharded::BufferedRead;
+use Equivalent;
+
+// Epoch-based reclamation of items read without locking. Readers pin the
+// current epoch while they dereference items. The epoch only advances once
+// no reader is pinned in the previous one, and an item retired in an epoch
+// is freed once the epoch is two steps further, as no reader can reach it
+// anymore.
+pub(crate) struct Collector<T> {
+    epoch: AtomicUsize,
+    // Readers pinned in even and odd epochs.
+    pinned: [AtomicUsize; 2],
+    garbage: Mutex<Vec<(usize, Box<T>)>>,
+}
+
+pub(crate) struct Guard<'a> {
+    pinned: &'a AtomicUsize,
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.pinned.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T> Collector<T> {
+    pub fn new() -> Self {
+        Collector {
+            epoch: AtomicUsize::new(0),
+            pinned: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            garbage: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn pin(&self) -> Guard<'_> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let pinned = &self.pinned[epoch % 2];
+            pinned.fetch_add(1, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                return Guard { pinned };
+            }
+            pinned.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // Frees `item`, already unreachable for new readers, once the readers
+    // that may still hold it are gone.
+    pub fn retire(&self, item: Box<T>) {
+        let mut garbage = self
+            .garbage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        garbage.push((epoch, item));
+        if self.pinned[(epoch + 1) % 2].load(Ordering::SeqCst) == 0 {
+            self.epoch.store(epoch + 1, Ordering::SeqCst);
+            garbage.retain(|&(retired, _)| retired >= epoch);
+        }
+    }
+}
+
+struct EntryCopy<K, V> {
+    version: u64,
+    hash: u64,
+    expires_at: Option<Instant>,
+    read: BufferedRead,
+    key: K,
+    value: V,
+}
+
+// Copies of entries of a shard, found by the hash of their key, so that hits
+// are served without locking. Copies are only valid at the version of the
+// shard they were made at.
+pub(crate) struct Copies<K, V> {
+    slots: Box<[AtomicPtr<EntryCopy<K, V>>]>,
+    collector: Collector<EntryCopy<K, V>>,
+    _marker: PhantomData<Box<EntryCopy<K, V>>>,
+}
+
+impl<K, V> Copies<K, V> {
+    pub fn new(slots: usize) -> Self {
+        Copies {
+            slots: (0..slots.next_power_of_two())
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+            collector: Collector::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn slot(&self, hash: u64) -> &AtomicPtr<EntryCopy<K, V>> {
+        &self.slots[hash as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn get<Q>(&self, hash: u64, key: &Q, version: u64) -> Option<(V, BufferedRead)>
+    where
+        Q: Equivalent<K> + ?Sized,
+        V: Clone,
+    {
+        let _guard = self.collector.pin();
+        let copy = self.slot(hash).load(Ordering::SeqCst);
+        // Safe: copies are only freed once no pinned reader may hold them.
+        let copy = unsafe { copy.as_ref()? };
+        if copy.version != version
+            || copy.hash != hash
+            || !key.equivalent(&copy.key)
+            || copy
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= Instant::now())
+        {
+            return None;
+        }
+        Some((copy.value.clone(), copy.read))
+    }
+
+    pub fn publish(
+        &self,
+        version: u64,
+        hash: u64,
+        expires_at: Option<Instant>,
+        read: BufferedRead,
+        key: K,
+        value: V,
+    ) {
+        let copy = Box::new(EntryCopy {
+            version,
+            hash,
+            expires_at,
+            read,
+            key,
+            value,
+        });
+        let replaced = self.slot(hash).swap(Box::into_raw(copy), Ordering::SeqCst);
+        if !replaced.is_null() {
+            // Safe: the copy was unlinked, and came from `Box::into_raw()`.
+            self.collector.retire(unsafe { Box::from_raw(replaced) });
+        }
+    }
+}
+
+impl<K, V> Drop for Copies<K, V> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            let copy = *slot.get_mut();
+            if !copy.is_null() {
+                // Safe: no reader is left, and copies came from `Box::into_raw()`.
+                drop(unsafe { Box::from_raw(copy) });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use epoch::{Collector, Copies};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn collector() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let collector = Collector::new();
+        let guard = collector.pin();
+        for _ in 0..4 {
+            collector.retire(Box::new(Counted(dropped.clone())));
+        }
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+        drop(guard);
+        collector.retire(Box::new(Counted(dropped.clone())));
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        collector.retire(Box::new(Counted(dropped.clone())));
+        assert_eq!(dropped.load(Ordering::SeqCst), 5);
+        drop(collector);
+        assert_eq!(dropped.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn copies() {
+        let copies: Copies<u32, String> = Copies::new(6);
+        assert_eq!(copies.slots.len(), 8);
+        assert_eq!(copies.get(3, &1, 0), None);
+        copies.publish(0, 3, None, (5, 3), 1, "one".to_string());
+        assert_eq!(copies.get(3, &1, 0), Some(("one".to_string(), (5, 3))));
+        assert_eq!(copies.get(3, &1, 1), None);
+        assert_eq!(copies.get(3, &2, 0), None);
+        copies.publish(1, 11, None, (6, 11), 2, "two".to_string());
+        assert_eq!(copies.get(3, &1, 1), None);
+        assert_eq!(copies.get(11, &2, 1), Some(("two".to_string(), (6, 11))));
+    }
+}
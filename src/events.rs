@@ -0,0 +1,176 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::mem;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionCause {
+    Capacity,
+    Weight,
+    Quota,
+    Expired,
+    Corrupt,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvictionEvent<K> {
+    pub key: K,
+    pub cause: EvictionCause,
+    pub count: u64,
+    /// Context of the `insert_with_ctx()` call that caused the eviction.
+    pub ctx: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationMode {
+    /// Every eviction is queued, in order.
+    Queue,
+    /// Only the latest eviction of each key is kept, along with the number
+    /// of times that key was evicted since the queue was last drained.
+    Coalesce,
+}
+
+/// A step in the life of an entry. An entry is `Admitted` as resident, may
+/// be `Demoted` to the history and `Promoted` back any number of times, and
+/// is eventually `Evicted`, whatever the cause, including `remove()` and
+/// `clear()`. Dropping the cache reports nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transition {
+    Admitted,
+    Demoted,
+    Promoted,
+    Evicted,
+}
+
+/// Called exactly once per transition, after it happened, e.g. to maintain
+/// reference counts in an external store. Values are the ones the entry
+/// holds in its new state.
+pub trait LifecycleHandler<K, V> {
+    fn transition(&self, key: &K, value: &V, transition: Transition);
+}
+
+impl<K, V, F: Fn(&K, &V, Transition)> LifecycleHandler<K, V> for F {
+    fn transition(&self, key: &K, value: &V, transition: Transition) {
+        self(key, value, transition)
+    }
+}
+
+/// A key entering or leaving the resident set, identified by a hash that
+/// does not depend on the cache's hash keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyChange {
+    Inserted(u64),
+    Evicted(u64),
+}
+
+/// The latest changes, numbered by a counter that keeps increasing when old
+/// changes are dropped or the log is cleared.
+#[derive(Clone)]
+pub(crate) struct ChangeLog {
+    changes: VecDeque<KeyChange>,
+    capacity: usize,
+    counter: u64,
+}
+
+impl ChangeLog {
+    pub fn new(capacity: usize) -> Self {
+        ChangeLog {
+            changes: VecDeque::with_capacity(capacity),
+            capacity,
+            counter: 0,
+        }
+    }
+
+    pub fn push(&mut self, change: KeyChange) {
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+        self.counter += 1;
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Changes after `counter`, or `None` if some of them were dropped.
+    pub fn since(&self, counter: u64) -> Option<Vec<KeyChange>> {
+        let behind = self.counter.checked_sub(counter)? as usize;
+        if behind > self.changes.len() {
+            return None;
+        }
+        Some(
+            self.changes
+                .range(self.changes.len() - behind..)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum EvictionQueue<K>
+where
+    K: Eq + Hash,
+{
+    Queue(Vec<EvictionEvent<K>>),
+    Coalesce(HashMap<K, (EvictionCause, u64, Option<u64>)>),
+}
+
+impl<K: Eq + Hash> EvictionQueue<K> {
+    pub fn new(mode: NotificationMode) -> Self {
+        match mode {
+            NotificationMode::Queue => EvictionQueue::Queue(Vec::new()),
+            NotificationMode::Coalesce => EvictionQueue::Coalesce(HashMap::new()),
+        }
+    }
+
+    pub fn push(&mut self, key: K, cause: EvictionCause, ctx: Option<u64>) {
+        match *self {
+            EvictionQueue::Queue(ref mut events) => events.push(EvictionEvent {
+                key,
+                cause,
+                count: 1,
+                ctx,
+            }),
+            EvictionQueue::Coalesce(ref mut events) => {
+                let event = events.entry(key).or_insert((cause, 0, ctx));
+                event.0 = cause;
+                event.1 += 1;
+                event.2 = ctx;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            EvictionQueue::Queue(ref events) => events.len(),
+            EvictionQueue::Coalesce(ref events) => events.len(),
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<EvictionEvent<K>> {
+        match *self {
+            EvictionQueue::Queue(ref mut events) => mem::take(events),
+            EvictionQueue::Coalesce(ref mut events) => events
+                .drain()
+                .map(|(key, (cause, count, ctx))| EvictionEvent {
+                    key,
+                    cause,
+                    count,
+                    ctx,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match *self {
+            EvictionQueue::Queue(ref mut events) => events.clear(),
+            EvictionQueue::Coalesce(ref mut events) => events.clear(),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// C interface over byte-string keys and opaque pointer values. Build a shared
+// library with `cargo rustc --release --features ffi --crate-type cdylib`.
+
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::slice;
+
+use CartCache;
+
+/// Called with the user data pointer and a value whenever the cache lets go
+/// of it: on eviction, overwrite, `cart_cache_clear()` and `cart_cache_free()`.
+pub type CartCacheReleaseFn =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, value: *mut c_void)>;
+
+struct Value {
+    ptr: *mut c_void,
+    release: CartCacheReleaseFn,
+    user_data: *mut c_void,
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self.user_data, self.ptr) }
+        }
+    }
+}
+
+pub struct FfiCartCache {
+    cache: CartCache<Box<[u8]>, Value>,
+    release: CartCacheReleaseFn,
+    user_data: *mut c_void,
+}
+
+unsafe fn key<'a>(key: *const u8, key_len: usize) -> &'a [u8] {
+    if key_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(key, key_len)
+    }
+}
+
+/// Returns a new cache with `capacity` slots, or NULL if `capacity` is
+/// invalid. `release` may be NULL.
+#[no_mangle]
+pub extern "C" fn cart_cache_new(
+    capacity: usize,
+    release: CartCacheReleaseFn,
+    user_data: *mut c_void,
+) -> *mut FfiCartCache {
+    match CartCache::new(capacity) {
+        Ok(cache) => Box::into_raw(Box::new(FfiCartCache {
+            cache,
+            release,
+            user_data,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Returns the value stored for the key, or NULL, also if the cache is in
+/// an inconsistent state.
+///
+/// # Safety
+///
+/// `cache` must come from `cart_cache_new()`, and `key` must point to
+/// `key_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cart_cache_get(
+    cache: *mut FfiCartCache,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> *mut c_void {
+    match (*cache).cache.get_checked(key(key_ptr, key_len)) {
+        Ok(Some(value)) => value.ptr,
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Stores `value` for the key, copying the key. Returns 1 if a value was
+/// already stored for it, in which case that value is released, and -1 if
+/// the cache is in an inconsistent state and must be cleared.
+///
+/// # Safety
+///
+/// Same as `cart_cache_get()`.
+#[no_mangle]
+pub unsafe extern "C" fn cart_cache_insert(
+    cache: *mut FfiCartCache,
+    key_ptr: *const u8,
+    key_len: usize,
+    value: *mut c_void,
+) -> c_int {
+    let cache = &mut *cache;
+    let value = Value {
+        ptr: value,
+        release: cache.release,
+        user_data: cache.user_data,
+    };
+    match cache
+        .cache
+        .insert_checked(key(key_ptr, key_len).into(), value)
+    {
+        Ok(updated) => updated as c_int,
+        Err(_) => -1,
+    }
+}
+
+/// Returns the number of entries, including history entries.
+///
+/// # Safety
+///
+/// `cache` must come from `cart_cache_new()`.
+#[no_mangle]
+pub unsafe extern "C" fn cart_cache_len(cache: *const FfiCartCache) -> usize {
+    (*cache).cache.len()
+}
+
+/// Releases every value and empties the cache.
+///
+/// # Safety
+///
+/// `cache` must come from `cart_cache_new()`.
+#[no_mangle]
+pub unsafe extern "C" fn cart_cache_clear(cache: *mut FfiCartCache) {
+    (*cache).cache.clear()
+}
+
+/// Releases every value and frees the cache. `cache` may be NULL.
+///
+/// # Safety
+///
+/// `cache` must come from `cart_cache_new()` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cart_cache_free(cache: *mut FfiCartCache) {
+    if !cache.is_null() {
+        drop(Box::from_raw(cache));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn release(user_data: *mut c_void, value: *mut c_void) {
+        *(user_data as *mut usize) += value as usize;
+    }
+
+    #[test]
+    fn c_api() {
+        let mut released = 0usize;
+        unsafe {
+            let cache =
+                cart_cache_new(4, Some(release), &mut released as *mut usize as *mut c_void);
+            assert!(!cache.is_null());
+            assert_eq!(
+                cart_cache_insert(cache, b"a".as_ptr(), 1, 8 as *mut c_void),
+                0
+            );
+            assert_eq!(
+                cart_cache_insert(cache, b"a".as_ptr(), 1, 2 as *mut c_void),
+                1
+            );
+            assert_eq!(cart_cache_get(cache, b"a".as_ptr(), 1), 2 as *mut c_void);
+            assert!(cart_cache_get(cache, b"b".as_ptr(), 1).is_null());
+            assert_eq!(
+                cart_cache_insert(cache, ptr::null(), 0, 4 as *mut c_void),
+                0
+            );
+            assert_eq!(cart_cache_len(cache), 2);
+            cart_cache_free(cache);
+        }
+        assert_eq!(released, 8 + 2 + 4);
+        assert!(cart_cache_new(0, None, ptr::null_mut()).is_null());
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Counting Bloom filter over key fingerprints, with two probes per key.
+// Saturated counters are never decremented, so the filter may report false
+// positives but never false negatives.
+pub struct KeyFilter {
+    counters: Box<[AtomicU8]>,
+    mask: usize,
+}
+
+impl Clone for KeyFilter {
+    fn clone(&self) -> Self {
+        KeyFilter {
+            counters: self
+                .counters
+                .iter()
+                .map(|counter| AtomicU8::new(counter.load(Ordering::Relaxed)))
+                .collect(),
+            mask: self.mask,
+        }
+    }
+}
+
+impl KeyFilter {
+    pub fn new(capacity: usize) -> Self {
+        let len = (capacity.max(1) * 4).next_power_of_two();
+        let counters = (0..len).map(|_| AtomicU8::new(0)).collect();
+        KeyFilter {
+            counters,
+            mask: len - 1,
+        }
+    }
+
+    #[inline]
+    fn positions(&self, hash: u64) -> [usize; 2] {
+        [
+            hash as usize & self.mask,
+            hash.rotate_left(32) as usize & self.mask,
+        ]
+    }
+
+    #[inline]
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .iter()
+            .all(|&i| self.counters[i].load(Ordering::Relaxed) != 0)
+    }
+
+    pub fn add(&self, hash: u64) {
+        for &i in &self.positions(hash) {
+            let _ = self.counters[i]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_add(1));
+        }
+    }
+
+    pub fn remove(&self, hash: u64) {
+        for &i in &self.positions(hash) {
+            let _ = self.counters[i].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| {
+                if c == 0 || c == u8::MAX {
+                    None
+                } else {
+                    Some(c - 1)
+                }
+            });
+        }
+    }
+
+    pub fn clear(&self) {
+        for counter in self.counters.iter() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
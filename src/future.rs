@@ -0,0 +1,636 @@
+use futures_core::Stream;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use {CartCache, Equivalent};
+
+type Loader<V> = Pin<Box<dyn Future<Output = V> + Send>>;
+
+struct LoadState<V> {
+    loader: Option<Loader<V>>,
+    value: Option<Arc<V>>,
+    wakers: Vec<Waker>,
+}
+
+// A load shared by every `get_with()` call for the same key. Whichever waiter
+// is polled drives the loader; the loader is dropped once no waiter is left.
+struct InFlight<V> {
+    state: Mutex<LoadState<V>>,
+    waiters: AtomicUsize,
+}
+
+struct WatchState<V> {
+    latest: Option<Arc<V>>,
+    waker: Option<Waker>,
+}
+
+struct Inner<K, V>
+where
+    K: Eq + Hash,
+{
+    cache: CartCache<K, Arc<V>>,
+    in_flight: HashMap<K, Arc<InFlight<V>>>,
+    watchers: HashMap<K, Vec<Weak<Mutex<WatchState<V>>>>>,
+    cancelled_loads: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> Inner<K, V> {
+    fn watch(&mut self, key: K, latest: Option<Arc<V>>) -> Watch<V> {
+        let state = Arc::new(Mutex::new(WatchState {
+            latest,
+            waker: None,
+        }));
+        self.watchers
+            .entry(key)
+            .or_default()
+            .push(Arc::downgrade(&state));
+        Watch { state }
+    }
+
+    fn store(&mut self, key: K, value: Arc<V>) -> bool {
+        if let Some(watchers) = self.watchers.get_mut(&key) {
+            watchers.retain(|watcher| match watcher.upgrade() {
+                Some(watcher) => {
+                    let mut watcher = lock(&watcher);
+                    watcher.latest = Some(value.clone());
+                    if let Some(waker) = watcher.waker.take() {
+                        waker.wake();
+                    }
+                    true
+                }
+                None => false,
+            });
+            if watchers.is_empty() {
+                self.watchers.remove(&key);
+            }
+        }
+        self.cache.insert(key, value)
+    }
+}
+
+/// A thread-safe cache whose misses are filled by futures. Concurrent misses
+/// for the same key share a single load.
+pub struct AsyncCartCache<K, V>
+where
+    K: Eq + Hash,
+{
+    inner: Arc<Mutex<Inner<K, V>>>,
+}
+
+impl<K: Eq + Hash, V> Clone for AsyncCartCache<K, V> {
+    fn clone(&self) -> Self {
+        AsyncCartCache {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("Cache lock poisoned")
+}
+
+impl<K: Eq + Hash + Clone, V> AsyncCartCache<K, V> {
+    pub fn new(capacity: usize) -> Result<Self, &'static str> {
+        Ok(Self::from_cache(CartCache::new(capacity)?))
+    }
+
+    pub fn from_cache(cache: CartCache<K, Arc<V>>) -> Self {
+        AsyncCartCache {
+            inner: Arc::new(Mutex::new(Inner {
+                cache,
+                in_flight: HashMap::new(),
+                watchers: HashMap::new(),
+                cancelled_loads: 0,
+            })),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.inner).cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn in_flight_len(&self) -> usize {
+        lock(&self.inner).in_flight.len()
+    }
+
+    /// Number of loads dropped because every caller waiting for them was.
+    pub fn cancelled_loads(&self) -> u64 {
+        lock(&self.inner).cancelled_loads
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        lock(&self.inner).cache.get(key).cloned()
+    }
+
+    /// Stores `value` for the key. A load of the key still in flight is
+    /// detached: its waiters get the loaded value, but it isn't stored.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let mut inner = lock(&self.inner);
+        inner.in_flight.remove(&key);
+        inner.store(key, Arc::new(value))
+    }
+
+    /// Removes the key, and detaches a load of it still in flight like
+    /// `insert()` does.
+    pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = lock(&self.inner);
+        inner.in_flight.remove(key);
+        inner.cache.remove(key)
+    }
+
+    /// Starts a dedicated OS thread calling `run_maintenance()` every
+    /// `interval`, until the returned handle is shut down or dropped. This
+    /// doesn't depend on any async runtime, and doesn't spawn a task on one:
+    /// the thread only takes the cache lock between sleeps.
+    pub fn spawn_maintenance_thread(&self, interval: Duration) -> MaintenanceHandle
+    where
+        K: Send + 'static,
+        V: Send + Sync + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let state = Arc::new(Mutex::new(MaintenanceState {
+            result: None,
+            waker: None,
+        }));
+        let (inner, thread_state) = (self.inner.clone(), state.clone());
+        thread::spawn(move || {
+            let result = loop {
+                if let Err(RecvTimeoutError::Disconnected) | Ok(()) = stopped.recv_timeout(interval)
+                {
+                    break Ok(());
+                }
+                let mut inner = match inner.lock() {
+                    Ok(inner) => inner,
+                    Err(_) => break Err("Cache lock poisoned"),
+                };
+                // A panic, e.g. in the destructor of an expired value, may
+                // leave the cache inconsistent. It is caught while the lock
+                // is held, so that the cache can be cleared instead of the
+                // lock being poisoned.
+                if panic::catch_unwind(AssertUnwindSafe(|| inner.cache.run_maintenance())).is_err()
+                {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| inner.cache.clear()));
+                    break Err("Maintenance thread panicked");
+                }
+            };
+            let mut state = lock(&thread_state);
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        MaintenanceHandle {
+            stop: Some(stop),
+            state,
+        }
+    }
+
+    /// Returns a stream yielding the current value of `key`, if any, then
+    /// every value subsequently stored for it. Intermediate values are
+    /// skipped if the stream is not polled often enough.
+    pub fn watch(&self, key: K) -> Watch<V> {
+        let mut inner = lock(&self.inner);
+        let latest = inner.cache.get(&key).cloned();
+        inner.watch(key, latest)
+    }
+
+    /// Resolves with the next value stored for `key`, whether inserted or
+    /// loaded by another task.
+    pub fn subscribe(&self, key: K) -> Subscription<V> {
+        Subscription {
+            watch: lock(&self.inner).watch(key, None),
+        }
+    }
+
+    pub fn get_with<F>(&self, key: K, loader: F) -> GetWith<K, V>
+    where
+        F: Future<Output = V> + Send + 'static,
+    {
+        GetWith {
+            inner: self.inner.clone(),
+            key: Some(key),
+            loader: Some(Box::pin(loader)),
+            in_flight: None,
+        }
+    }
+}
+
+pub struct GetWith<K, V>
+where
+    K: Eq + Hash,
+{
+    inner: Arc<Mutex<Inner<K, V>>>,
+    key: Option<K>,
+    loader: Option<Loader<V>>,
+    in_flight: Option<Arc<InFlight<V>>>,
+}
+
+// The loader is boxed, so nothing is ever pinned in place.
+impl<K: Eq + Hash, V> Unpin for GetWith<K, V> {}
+
+impl<K: Eq + Hash + Clone, V> GetWith<K, V> {
+    fn join(&mut self) -> Option<Arc<V>> {
+        let mut inner = lock(&self.inner);
+        let key = self.key.as_ref().expect("Polled after completion");
+        if let Some(value) = inner.cache.get(key) {
+            return Some(value.clone());
+        }
+        let loader = &mut self.loader;
+        let in_flight = inner
+            .in_flight
+            .entry(key.clone())
+            .or_insert_with(|| {
+                Arc::new(InFlight {
+                    state: Mutex::new(LoadState {
+                        loader: loader.take(),
+                        value: None,
+                        wakers: Vec::new(),
+                    }),
+                    waiters: AtomicUsize::new(0),
+                })
+            })
+            .clone();
+        in_flight.waiters.fetch_add(1, Ordering::SeqCst);
+        self.in_flight = Some(in_flight);
+        None
+    }
+
+    fn complete(&mut self, in_flight: &Arc<InFlight<V>>, value: &Arc<V>) {
+        let key = self.key.take().expect("Polled after completion");
+        let mut inner = lock(&self.inner);
+        let owned = inner
+            .in_flight
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, in_flight));
+        if owned {
+            inner.in_flight.remove(&key);
+            inner.store(key, value.clone());
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> Future for GetWith<K, V> {
+    type Output = Arc<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Arc<V>> {
+        let this = self.get_mut();
+        if this.in_flight.is_none() {
+            if let Some(value) = this.join() {
+                this.key = None;
+                return Poll::Ready(value);
+            }
+        }
+        let in_flight = this.in_flight.clone().expect("Polled after completion");
+        let value = {
+            let mut state = lock(&in_flight.state);
+            if state.value.is_none() {
+                let polled = match state.loader {
+                    Some(ref mut loader) => loader.as_mut().poll(cx),
+                    None => Poll::Pending,
+                };
+                match polled {
+                    Poll::Ready(value) => {
+                        state.loader = None;
+                        state.value = Some(Arc::new(value));
+                        for waker in mem::take(&mut state.wakers) {
+                            waker.wake();
+                        }
+                    }
+                    Poll::Pending => {
+                        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                            state.wakers.push(cx.waker().clone());
+                        }
+                        return Poll::Pending;
+                    }
+                }
+            }
+            state.value.clone().expect("Missing loaded value")
+        };
+        this.complete(&in_flight, &value);
+        this.in_flight = None;
+        in_flight.waiters.fetch_sub(1, Ordering::SeqCst);
+        Poll::Ready(value)
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for GetWith<K, V> {
+    fn drop(&mut self) {
+        let in_flight = match self.in_flight.take() {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+        if in_flight.waiters.fetch_sub(1, Ordering::SeqCst) > 1 {
+            // Another waiter has to take over driving the loader.
+            for waker in mem::take(&mut lock(&in_flight.state).wakers) {
+                waker.wake();
+            }
+            return;
+        }
+        let cancelled = {
+            let mut inner = lock(&self.inner);
+            let key = self.key.as_ref().expect("Missing key");
+            let abandoned = in_flight.waiters.load(Ordering::SeqCst) == 0
+                && inner
+                    .in_flight
+                    .get(key)
+                    .is_some_and(|current| Arc::ptr_eq(current, &in_flight));
+            if abandoned {
+                inner.in_flight.remove(key);
+                inner.cancelled_loads += 1;
+            }
+            abandoned
+        };
+        if cancelled {
+            let loader = lock(&in_flight.state).loader.take();
+            drop(loader);
+        }
+    }
+}
+
+pub struct Watch<V> {
+    state: Arc<Mutex<WatchState<V>>>,
+}
+
+impl<V> Stream for Watch<V> {
+    type Item = Arc<V>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Arc<V>>> {
+        let mut state = lock(&self.state);
+        match state.latest.take() {
+            Some(value) => Poll::Ready(Some(value)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct Subscription<V> {
+    watch: Watch<V>,
+}
+
+impl<V> Future for Subscription<V> {
+    type Output = Arc<V>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Arc<V>> {
+        match Pin::new(&mut self.watch).poll_next(cx) {
+            Poll::Ready(Some(value)) => Poll::Ready(value),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+struct MaintenanceState {
+    result: Option<Result<(), &'static str>>,
+    waker: Option<Waker>,
+}
+
+/// Handle on a maintenance thread. Dropping it stops the thread without
+/// waiting for it.
+pub struct MaintenanceHandle {
+    stop: Option<Sender<()>>,
+    state: Arc<Mutex<MaintenanceState>>,
+}
+
+impl MaintenanceHandle {
+    /// Returns `true` if the thread has exited, which before `shutdown()`
+    /// means that it panicked.
+    pub fn is_finished(&self) -> bool {
+        lock(&self.state).result.is_some()
+    }
+
+    /// Stops the thread. The returned future resolves once it has exited,
+    /// with an error if it panicked.
+    pub fn shutdown(mut self) -> MaintenanceShutdown {
+        self.stop.take();
+        MaintenanceShutdown {
+            state: self.state.clone(),
+        }
+    }
+}
+
+pub struct MaintenanceShutdown {
+    state: Arc<Mutex<MaintenanceState>>,
+}
+
+impl Future for MaintenanceShutdown {
+    type Output = Result<(), &'static str>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.state);
+        match state.result {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use future::AsyncCartCache;
+    use futures_core::Stream;
+    use std::future::{pending, ready, Future};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+    use CartCacheBuilder;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn poll_once<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        loop {
+            match Pin::new(&mut future).poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    struct Counted<F>(Arc<AtomicUsize>, F);
+
+    impl<F: Future + Unpin> Future for Counted<F> {
+        type Output = F::Output;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Pin::new(&mut self.1).poll(cx)
+        }
+    }
+
+    #[test]
+    fn maintenance_thread() {
+        let cache: AsyncCartCache<u32, u32> = AsyncCartCache::from_cache(
+            CartCacheBuilder::new(16)
+                .ttl(Duration::from_millis(1))
+                .build()
+                .unwrap(),
+        );
+        cache.insert(1, 1);
+        let handle = cache.spawn_maintenance_thread(Duration::from_millis(1));
+        while !cache.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!handle.is_finished());
+        assert_eq!(block_on(handle.shutdown()), Ok(()));
+
+        struct Bomb;
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("Boom");
+            }
+        }
+
+        let cache: AsyncCartCache<u32, Bomb> = AsyncCartCache::from_cache(
+            CartCacheBuilder::new(16)
+                .ttl(Duration::from_millis(1))
+                .build()
+                .unwrap(),
+        );
+        cache.insert(1, Bomb);
+        let handle = cache.spawn_maintenance_thread(Duration::from_millis(1));
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            block_on(handle.shutdown()),
+            Err("Maintenance thread panicked")
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn shared_load() {
+        let cache: AsyncCartCache<u32, u32> = AsyncCartCache::new(16).unwrap();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let mut first = cache.get_with(1, Counted(polls.clone(), ready(1)));
+        let mut second = cache.get_with(1, Counted(polls.clone(), ready(2)));
+        assert_eq!(poll_once(&mut first), Poll::Ready(Arc::new(1)));
+        assert_eq!(poll_once(&mut second), Poll::Ready(Arc::new(1)));
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get(&1), Some(Arc::new(1)));
+    }
+
+    #[test]
+    fn watch() {
+        let cache: AsyncCartCache<u32, u32> = AsyncCartCache::new(16).unwrap();
+        cache.insert(1, 1);
+        let mut watch = cache.watch(1);
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(
+            Pin::new(&mut watch).poll_next(&mut cx),
+            Poll::Ready(Some(Arc::new(1)))
+        );
+        assert!(Pin::new(&mut watch).poll_next(&mut cx).is_pending());
+        cache.insert(1, 2);
+        cache.insert(2, 2);
+        assert_eq!(
+            Pin::new(&mut watch).poll_next(&mut cx),
+            Poll::Ready(Some(Arc::new(2)))
+        );
+        drop(watch);
+        cache.insert(1, 3);
+        assert!(cache.inner.lock().unwrap().watchers.is_empty());
+    }
+
+    #[test]
+    fn subscribe() {
+        let cache: AsyncCartCache<u32, u32> = AsyncCartCache::new(16).unwrap();
+        cache.insert(1, 1);
+        let mut subscription = cache.subscribe(1);
+        assert!(poll_once(&mut subscription).is_pending());
+        let mut load = cache.get_with(2, ready(2));
+        assert!(poll_once(&mut load).is_ready());
+        cache.insert(1, 2);
+        assert_eq!(poll_once(&mut subscription), Poll::Ready(Arc::new(2)));
+    }
+
+    struct Delayed<T>(Option<T>, bool);
+
+    impl<T: Unpin> Future for Delayed<T> {
+        type Output = T;
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<T> {
+            if !self.1 {
+                self.1 = true;
+                return Poll::Pending;
+            }
+            Poll::Ready(self.0.take().expect("Polled after completion"))
+        }
+    }
+
+    #[test]
+    fn detached_load() {
+        let cache: AsyncCartCache<u32, u32> = AsyncCartCache::new(16).unwrap();
+        let mut load = cache.get_with(1, Delayed(Some(1), false));
+        assert!(poll_once(&mut load).is_pending());
+        cache.insert(1, 2);
+        assert_eq!(poll_once(&mut load), Poll::Ready(Arc::new(1)));
+        assert_eq!(cache.get(&1), Some(Arc::new(2)));
+
+        let mut load = cache.get_with(3, Delayed(Some(3), false));
+        assert!(poll_once(&mut load).is_pending());
+        assert_eq!(cache.remove(&3), None);
+        assert_eq!(poll_once(&mut load), Poll::Ready(Arc::new(3)));
+        assert_eq!((cache.get(&3), cache.in_flight_len()), (None, 0));
+    }
+
+    #[test]
+    fn cancelled_load() {
+        let cache: AsyncCartCache<u32, u32> = AsyncCartCache::new(16).unwrap();
+        let mut first = cache.get_with(1, pending());
+        let mut second = cache.get_with(1, pending());
+        assert!(poll_once(&mut first).is_pending());
+        assert!(poll_once(&mut second).is_pending());
+        drop(first);
+        assert_eq!(cache.in_flight_len(), 1);
+        assert!(poll_once(&mut second).is_pending());
+        drop(second);
+        assert_eq!(cache.in_flight_len(), 0);
+        assert_eq!(cache.cancelled_loads(), 1);
+        assert!(cache.get(&1).is_none());
+    }
+}
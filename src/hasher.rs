@@ -0,0 +1,264 @@
+#[cfg(feature = "foldhash")]
+use foldhash::fast::{FoldHasher as RandomHasher, RandomState};
+#[cfg(not(feature = "foldhash"))]
+use std::collections::hash_map::{DefaultHasher as RandomHasher, RandomState};
+use std::hash::{BuildHasher, Hasher};
+
+/// SipHash-1-3, with integers hashed as little-endian 64-bit words, so that
+/// hashes only depend on the keys and on the `Hash` implementation of the
+/// hashed value, not on the platform or the compiler.
+#[derive(Clone, Debug)]
+pub(crate) struct SipHasher13 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+impl SipHasher13 {
+    pub fn new_with_keys(k0: u64, k1: u64) -> Self {
+        SipHasher13 {
+            v0: k0 ^ 0x736f6d6570736575,
+            v1: k1 ^ 0x646f72616e646f6d,
+            v2: k0 ^ 0x6c7967656e657261,
+            v3: k1 ^ 0x7465646279746573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    #[inline]
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    #[inline]
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        self.round();
+        self.v0 ^= m;
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.length += bytes.len();
+        if self.ntail > 0 {
+            let fill = (8 - self.ntail).min(bytes.len());
+            for (i, &byte) in bytes[..fill].iter().enumerate() {
+                self.tail |= (byte as u64) << (8 * (self.ntail + i));
+            }
+            self.ntail += fill;
+            bytes = &bytes[fill..];
+            if self.ntail < 8 {
+                return;
+            }
+            let m = self.tail;
+            self.compress(m);
+            self.tail = 0;
+            self.ntail = 0;
+        }
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            self.compress(u64::from_le_bytes(word));
+        }
+        for (i, &byte) in chunks.remainder().iter().enumerate() {
+            self.tail |= (byte as u64) << (8 * i);
+        }
+        self.ntail = chunks.remainder().len();
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16)
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32)
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64)
+    }
+
+    #[inline]
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128)
+    }
+
+    #[inline]
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as i64 as u64)
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let b = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(b);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// Hash state of a cache's index and key filter. Randomly keyed by default,
+/// with SipHash or, with the `foldhash` feature, with the much faster
+/// foldhash. `with_keys()` sets SipHash-1-3 keys explicitly, e.g. to share
+/// them between processes without letting clients predict them. Any other
+/// `BuildHasher` can be used with `CartCacheBuilder::build_with_hasher()`.
+#[derive(Clone, Debug)]
+pub struct KeyedState {
+    keys: Option<(u64, u64)>,
+    random: RandomState,
+}
+
+impl KeyedState {
+    pub fn new() -> Self {
+        KeyedState {
+            keys: None,
+            random: RandomState::default(),
+        }
+    }
+
+    pub fn with_keys(k0: u64, k1: u64) -> Self {
+        KeyedState {
+            keys: Some((k0, k1)),
+            random: RandomState::default(),
+        }
+    }
+}
+
+impl Default for KeyedState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuildHasher for KeyedState {
+    type Hasher = KeyedHasher;
+
+    fn build_hasher(&self) -> KeyedHasher {
+        match self.keys {
+            Some((k0, k1)) => KeyedHasher(Inner::Keyed(SipHasher13::new_with_keys(k0, k1))),
+            None => KeyedHasher(Inner::Random(self.random.build_hasher())),
+        }
+    }
+}
+
+/// Hasher built by `KeyedState`. Its variants are private, so that the type
+/// is the same with and without the `foldhash` feature.
+pub struct KeyedHasher(Inner);
+
+enum Inner {
+    Random(RandomHasher),
+    Keyed(SipHasher13),
+}
+
+// Forwards every method, so that integer fast paths of the inner hashers
+// are not replaced with the default byte-oriented ones.
+macro_rules! forward {
+    ($($method:ident($ty:ty)),*) => {
+        $(
+            #[inline]
+            fn $method(&mut self, i: $ty) {
+                match self.0 {
+                    Inner::Random(ref mut hasher) => hasher.$method(i),
+                    Inner::Keyed(ref mut hasher) => hasher.$method(i),
+                }
+            }
+        )*
+    };
+}
+
+impl Hasher for KeyedHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        match self.0 {
+            Inner::Random(ref hasher) => hasher.finish(),
+            Inner::Keyed(ref hasher) => hasher.finish(),
+        }
+    }
+
+    forward!(
+        write(&[u8]),
+        write_u8(u8),
+        write_u16(u16),
+        write_u32(u32),
+        write_u64(u64),
+        write_u128(u128),
+        write_usize(usize),
+        write_i8(i8),
+        write_i16(i16),
+        write_i32(i32),
+        write_i64(i64),
+        write_i128(i128),
+        write_isize(isize)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use hasher::SipHasher13;
+    use std::hash::Hasher;
+
+    #[test]
+    fn siphash13() {
+        let hash = |chunks: &[&[u8]]| {
+            let mut hasher = SipHasher13::new_with_keys(0, 0);
+            for chunk in chunks {
+                hasher.write(chunk);
+            }
+            hasher.finish()
+        };
+        assert_eq!(hash(&[b"abc"]), -4594863902769663758i64 as u64);
+        assert_eq!(hash(&[b"0123456789abcdefXYZ"]), 2899885535711926331);
+        assert_eq!(
+            hash(&[b"0123", b"456789abcde", b"fXYZ"]),
+            2899885535711926331
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::mem::{size_of, size_of_val};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Bytes a value owns on the heap, not counting its inline size. For unsized
+/// types such as `str`, the inline size is `size_of_val()`.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+
+    /// Inline plus heap size.
+    fn total_size(&self) -> usize
+    where
+        Self: Sized,
+    {
+        size_of::<Self>() + self.heap_size()
+    }
+}
+
+macro_rules! no_heap {
+    ($($ty:ty),*) => {
+        $(impl HeapSize for $ty {
+            #[inline]
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+}
+
+no_heap!(
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    ()
+);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for str {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize> HeapSize for [T] {
+    fn heap_size(&self) -> usize {
+        self.iter().map(HeapSize::heap_size).sum()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self[..].heap_size()
+    }
+}
+
+impl<T: HeapSize + ?Sized> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        size_of_val(&**self) + (**self).heap_size()
+    }
+}
+
+// Shared allocations are counted in full by every owner, along with their
+// two reference counts.
+impl<T: HeapSize + ?Sized> HeapSize for Arc<T> {
+    fn heap_size(&self) -> usize {
+        2 * size_of::<usize>() + size_of_val(&**self) + (**self).heap_size()
+    }
+}
+
+impl<T: HeapSize + ?Sized> HeapSize for Rc<T> {
+    fn heap_size(&self) -> usize {
+        2 * size_of::<usize>() + size_of_val(&**self) + (**self).heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize, C: HeapSize> HeapSize for (A, B, C) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size() + self.2.heap_size()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl HeapSize for ::bytes::Bytes {
+    fn heap_size(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_sizes() {
+        assert_eq!(7u32.heap_size(), 0);
+        assert_eq!(String::with_capacity(10).heap_size(), 10);
+        let arc: Arc<str> = Arc::from("abc");
+        assert_eq!(arc.heap_size(), 2 * size_of::<usize>() + 3);
+        let mut vec = Vec::with_capacity(4);
+        vec.push("ab".to_string());
+        assert_eq!(vec.heap_size(), 4 * size_of::<String>() + 2);
+        let boxed: Box<[u8]> = vec![0u8; 5].into_boxed_slice();
+        assert_eq!((boxed, 1u8).heap_size(), 5);
+    }
+}
@@ -0,0 +1,334 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use CartCache;
+
+const MAX_HEURISTIC_LIFETIME: Duration = Duration::from_secs(86400);
+
+/// Directives of a `Cache-Control` response header that affect storage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub max_age: Option<Duration>,
+    pub s_maxage: Option<Duration>,
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub must_revalidate: bool,
+    pub private: bool,
+}
+
+impl CacheControl {
+    /// Parses a header value, ignoring unknown and malformed directives.
+    pub fn parse(header: &str) -> Self {
+        let mut cache_control = CacheControl::default();
+        for directive in header.split(',') {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let seconds = parts
+                .next()
+                .and_then(|value| value.trim().trim_matches('"').parse().ok())
+                .map(Duration::from_secs);
+            match name.as_str() {
+                "max-age" => cache_control.max_age = seconds,
+                "s-maxage" => cache_control.s_maxage = seconds,
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                "must-revalidate" | "proxy-revalidate" => cache_control.must_revalidate = true,
+                "private" => cache_control.private = true,
+                _ => {}
+            }
+        }
+        cache_control
+    }
+}
+
+/// Response headers used to decide whether and how long to store a
+/// response. Dates are left to the caller to parse.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseMeta {
+    pub cache_control: CacheControl,
+    pub age: Option<Duration>,
+    pub date: Option<SystemTime>,
+    pub expires: Option<SystemTime>,
+    pub last_modified: Option<SystemTime>,
+    pub etag: Option<String>,
+    pub vary: Option<String>,
+}
+
+impl ResponseMeta {
+    /// Remaining freshness lifetime, after subtracting the `Age` header.
+    /// Without explicit expiration, this is a tenth of the time since the
+    /// resource was last modified, capped at a day.
+    pub fn freshness_lifetime(&self, shared: bool) -> Duration {
+        let cache_control = &self.cache_control;
+        let date = self.date.unwrap_or_else(SystemTime::now);
+        let since = |earlier: SystemTime| date.duration_since(earlier).unwrap_or_default();
+        let lifetime = match (shared, cache_control.s_maxage, cache_control.max_age) {
+            (true, Some(s_maxage), _) => s_maxage,
+            (_, _, Some(max_age)) => max_age,
+            _ => match (self.expires, self.last_modified) {
+                (Some(expires), _) => expires.duration_since(date).unwrap_or_default(),
+                (None, Some(last_modified)) => {
+                    (since(last_modified) / 10).min(MAX_HEURISTIC_LIFETIME)
+                }
+                (None, None) => Duration::default(),
+            },
+        };
+        lifetime.saturating_sub(self.age.unwrap_or_default())
+    }
+
+    fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+/// A stored response. Once stale, it can be revalidated with its `etag` or
+/// `last_modified` validators.
+pub struct StoredResponse<V> {
+    pub body: V,
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    fresh_until: Instant,
+}
+
+impl<V> StoredResponse<V> {
+    pub fn is_fresh(&self) -> bool {
+        Instant::now() < self.fresh_until
+    }
+}
+
+pub enum Lookup<'a, V> {
+    Fresh(&'a V),
+    /// Must be revalidated before use, then passed to `revalidated()` if the
+    /// origin replied with `304 Not Modified`.
+    Stale(&'a StoredResponse<V>),
+    Miss,
+}
+
+/// Storage logic for an HTTP cache, keyed by URI and by the request headers
+/// listed in `Vary`.
+///
+/// Responses without validators expire from the cache along with their
+/// freshness; responses with validators stay until evicted, and are then
+/// reported as stale.
+pub struct HttpCache<V> {
+    responses: CartCache<String, StoredResponse<V>>,
+    vary: CartCache<String, Variants>,
+    shared: bool,
+}
+
+// The request headers a URI varies on, and the keys of its stored variants.
+struct Variants {
+    vary: Vec<String>,
+    keys: Vec<String>,
+}
+
+impl<V> HttpCache<V> {
+    /// `shared` caches, such as proxies, honor `s-maxage` and do not store
+    /// private responses.
+    pub fn new(capacity: usize, shared: bool) -> Result<Self, &'static str> {
+        Ok(HttpCache {
+            responses: CartCache::new(capacity)?,
+            vary: CartCache::new(capacity)?,
+            shared,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+
+    /// Stores a response to a `GET` request. Returns `false` if it is not
+    /// storable.
+    pub fn store(
+        &mut self,
+        uri: &str,
+        request_headers: &[(&str, &str)],
+        meta: &ResponseMeta,
+        body: V,
+    ) -> bool {
+        let cache_control = &meta.cache_control;
+        if cache_control.no_store || (self.shared && cache_control.private) {
+            return false;
+        }
+        let vary: Vec<String> = match meta.vary {
+            Some(ref vary) if vary.trim() == "*" => return false,
+            Some(ref vary) => vary
+                .split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            None => Vec::new(),
+        };
+        let lifetime = if cache_control.no_cache {
+            Duration::default()
+        } else {
+            meta.freshness_lifetime(self.shared)
+        };
+        if lifetime == Duration::default() && !meta.has_validators() {
+            return false;
+        }
+        let key = variant_key(uri, &vary, request_headers);
+        let response = StoredResponse {
+            body,
+            etag: meta.etag.clone(),
+            last_modified: meta.last_modified,
+            fresh_until: Instant::now() + lifetime,
+        };
+        match self.vary.get_mut(uri) {
+            Some(variants) => {
+                variants.vary = vary;
+                if !variants.keys.contains(&key) {
+                    variants.keys.push(key.clone());
+                }
+            }
+            None => {
+                let keys = vec![key.clone()];
+                self.vary.insert(uri.to_string(), Variants { vary, keys });
+            }
+        }
+        if meta.has_validators() {
+            self.responses.insert(key, response);
+        } else {
+            self.responses.insert_with_ttl(key, response, lifetime);
+        }
+        true
+    }
+
+    pub fn lookup(&mut self, uri: &str, request_headers: &[(&str, &str)]) -> Lookup<'_, V> {
+        let key = match self.vary.get(uri) {
+            Some(variants) => variant_key(uri, &variants.vary, request_headers),
+            None => return Lookup::Miss,
+        };
+        match self.responses.get(&key) {
+            Some(response) if response.is_fresh() => Lookup::Fresh(&response.body),
+            Some(response) => Lookup::Stale(response),
+            None => Lookup::Miss,
+        }
+    }
+
+    /// Refreshes a stale response after the origin confirmed it with `meta`.
+    /// Returns `false` if the response is no longer stored.
+    pub fn revalidated(
+        &mut self,
+        uri: &str,
+        request_headers: &[(&str, &str)],
+        meta: &ResponseMeta,
+    ) -> bool {
+        let key = match self.vary.get(uri) {
+            Some(variants) => variant_key(uri, &variants.vary, request_headers),
+            None => return false,
+        };
+        let lifetime = if meta.cache_control.no_cache {
+            Duration::default()
+        } else {
+            meta.freshness_lifetime(self.shared)
+        };
+        match self.responses.get_mut(&key) {
+            Some(response) => {
+                response.fresh_until = Instant::now() + lifetime;
+                if meta.etag.is_some() {
+                    response.etag = meta.etag.clone();
+                }
+                if meta.last_modified.is_some() {
+                    response.last_modified = meta.last_modified;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets all variants of `uri`, e.g. after an unsafe request.
+    pub fn invalidate(&mut self, uri: &str) {
+        if let Some(variants) = self.vary.remove(uri) {
+            self.responses.remove_many(&variants.keys);
+        }
+    }
+}
+
+fn variant_key(uri: &str, vary: &[String], request_headers: &[(&str, &str)]) -> String {
+    let mut key = uri.to_string();
+    for name in vary {
+        key.push('\n');
+        key.push_str(name);
+        key.push(':');
+        let values = request_headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim());
+        for (i, value) in values.enumerate() {
+            if i > 0 {
+                key.push(',');
+            }
+            key.push_str(value);
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{CacheControl, HttpCache, Lookup, ResponseMeta};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn http_cache() {
+        let cache_control = CacheControl::parse("public, max-age=60, s-maxage=\"600\"");
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(cache_control.s_maxage, Some(Duration::from_secs(600)));
+        let meta = ResponseMeta {
+            cache_control,
+            age: Some(Duration::from_secs(10)),
+            vary: Some("Accept-Encoding".to_string()),
+            ..ResponseMeta::default()
+        };
+        assert_eq!(meta.freshness_lifetime(false), Duration::from_secs(50));
+        assert_eq!(meta.freshness_lifetime(true), Duration::from_secs(590));
+
+        let mut cache = HttpCache::new(16, true).unwrap();
+        let gzip = [("accept-encoding", "gzip")];
+        assert!(cache.store("/a", &gzip, &meta, "gzipped"));
+        match cache.lookup("/a", &[("Accept-Encoding", "gzip")]) {
+            Lookup::Fresh(&body) => assert_eq!(body, "gzipped"),
+            _ => panic!("Response not found"),
+        }
+        assert!(matches!(cache.lookup("/a", &[]), Lookup::Miss));
+
+        let private = ResponseMeta {
+            cache_control: CacheControl::parse("private, max-age=60"),
+            ..ResponseMeta::default()
+        };
+        assert!(!cache.store("/b", &[], &private, "private"));
+
+        let validated = ResponseMeta {
+            cache_control: CacheControl::parse("no-cache"),
+            etag: Some("\"v1\"".to_string()),
+            ..ResponseMeta::default()
+        };
+        assert!(cache.store("/c", &[], &validated, "validated"));
+        match cache.lookup("/c", &[]) {
+            Lookup::Stale(response) => assert_eq!(response.etag.as_deref(), Some("\"v1\"")),
+            _ => panic!("Response not stale"),
+        }
+        let now = SystemTime::now();
+        let not_modified = ResponseMeta {
+            date: Some(now),
+            expires: Some(now + Duration::from_secs(60)),
+            ..ResponseMeta::default()
+        };
+        assert!(cache.revalidated("/c", &[], &not_modified));
+        assert!(matches!(cache.lookup("/c", &[]), Lookup::Fresh(_)));
+        cache.invalidate("/c");
+        assert!(matches!(cache.lookup("/c", &[]), Lookup::Miss));
+
+        let br = [("accept-encoding", "br")];
+        assert!(cache.store("/a", &br, &meta, "brotli"));
+        cache.invalidate("/a");
+        assert!(cache.store("/a", &br, &meta, "brotli"));
+        assert!(matches!(cache.lookup("/a", &br), Lookup::Fresh(_)));
+        assert!(matches!(cache.lookup("/a", &gzip), Lookup::Miss));
+    }
+}
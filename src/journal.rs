@@ -0,0 +1,229 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::borrow::Borrow;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use {CartCache, CartCacheBuilder};
+
+#[derive(Serialize, Deserialize)]
+enum Record<K, V> {
+    Insert(K, Option<V>),
+    Remove(K),
+}
+
+/// A cache whose inserts and removals are appended to a journal, one JSON
+/// record per line, so that its content survives a restart.
+///
+/// Records are flushed to the OS as they are written; a crash only loses
+/// the records written since the last `sync()`, and a torn final record is
+/// ignored on replay. The journal is rewritten with the resident entries
+/// once it holds more than twice as many records as the cache capacity.
+///
+/// Replaying only restores the resident entries, not their replacement
+/// state.
+pub struct JournaledCache<K, V>
+where
+    K: Eq + Hash,
+{
+    cache: CartCache<K, V>,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    records: usize,
+    with_values: bool,
+}
+
+impl<K, V> JournaledCache<K, V>
+where
+    K: Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Opens or creates the journal at `path` and replays it. Without
+    /// `with_values`, only keys are recorded, and `load` is called for each
+    /// replayed key; keys it returns `None` for are skipped.
+    pub fn open<P, F>(
+        path: P,
+        builder: CartCacheBuilder<K, V>,
+        with_values: bool,
+        mut load: F,
+    ) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&K) -> Option<V>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut cache = builder
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                let record = match serde_json::from_str(&line?) {
+                    Ok(record) => record,
+                    Err(_) => break,
+                };
+                match record {
+                    Record::Insert(key, Some(value)) => {
+                        cache.insert(key, value);
+                    }
+                    Record::Insert(key, None) => {
+                        if let Some(value) = load(&key) {
+                            cache.insert(key, value);
+                        }
+                    }
+                    Record::Remove(key) => {
+                        cache.remove(&key);
+                    }
+                }
+            }
+        }
+        let mut journaled_cache = JournaledCache {
+            cache,
+            writer: BufWriter::new(OpenOptions::new().append(true).create(true).open(&path)?),
+            path,
+            records: 0,
+            with_values,
+        };
+        journaled_cache.compact()?;
+        Ok(journaled_cache)
+    }
+
+    pub fn cache(&self) -> &CartCache<K, V> {
+        &self.cache
+    }
+
+    /// Records written since the last compaction.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.get(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<bool> {
+        {
+            let value = if self.with_values { Some(&value) } else { None };
+            self.append(&Record::Insert(&key, value))?;
+        }
+        let updated = self.cache.insert(key, value);
+        self.compact_if_needed()?;
+        Ok(updated)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> io::Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key = match self.cache.map.get_key_value(key) {
+            Some((key, _)) => key.clone(),
+            None => return Ok(None),
+        };
+        let removed = self.cache.remove(&key);
+        self.append(&Record::Remove::<_, &V>(&key))?;
+        self.compact_if_needed()?;
+        Ok(removed)
+    }
+
+    /// Waits until the journal is on disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+
+    /// Replaces the journal with the resident entries, recent ones first, so
+    /// that frequently used entries are replayed last.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            let scan = self.cache.scan();
+            for (key, value) in scan.recent().chain(scan.frequent()) {
+                let value = if self.with_values { Some(value) } else { None };
+                write_record(&mut writer, &Record::Insert(key, value))?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        self.writer = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        self.records = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record<&K, &V>) -> io::Result<()> {
+        write_record(&mut self.writer, record)?;
+        self.writer.flush()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn compact_if_needed(&mut self) -> io::Result<()> {
+        if self.records > 2 * self.cache.capacity() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_record<W, K, V>(writer: &mut W, record: &Record<K, V>) -> io::Result<()>
+where
+    W: Write,
+    K: Serialize,
+    V: Serialize,
+{
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use journal::JournaledCache;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use CartCacheBuilder;
+
+    #[test]
+    fn replay() {
+        let path = std::env::temp_dir().join(format!("cart-cache-journal-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        {
+            let mut cache =
+                JournaledCache::open(&path, CartCacheBuilder::new(16), true, |_| None).unwrap();
+            for i in 0..20u32 {
+                cache.insert(i, i * 2).unwrap();
+            }
+            assert_eq!(cache.remove(&19).unwrap(), Some(38));
+            assert!(cache.records() < 40);
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"Insert\":[42,")
+            .unwrap();
+        let mut cache: JournaledCache<u32, u32> =
+            JournaledCache::open(&path, CartCacheBuilder::new(16), false, |&key| Some(key))
+                .unwrap();
+        assert_eq!(cache.get(&18), Some(&36));
+        assert_eq!(cache.get(&19), None);
+        assert_eq!(cache.get(&42), None);
+        cache.insert(7, 0).unwrap();
+        drop(cache);
+        let mut cache: JournaledCache<u32, u32> =
+            JournaledCache::open(&path, CartCacheBuilder::new(16), false, |&key| {
+                Some(key + 1)
+            })
+            .unwrap();
+        assert_eq!(cache.get(&7), Some(&8));
+        assert_eq!(cache.get(&18), Some(&19));
+        fs::remove_file(&path).unwrap();
+    }
+}
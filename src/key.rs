@@ -0,0 +1,82 @@
+/// Composite key types with a compile-time namespace, usually implemented
+/// with `#[derive(CacheKey)]` (`derive` feature).
+///
+/// Derived `Hash` implementations start with `NAMESPACE_ID`, so that keys
+/// of different types hash differently even if their fields are identical.
+pub trait CacheKey {
+    const NAMESPACE: &'static str;
+    const NAMESPACE_ID: u64 = namespace_id(Self::NAMESPACE);
+}
+
+/// FNV-1a hash of a namespace, usable in constants.
+pub const fn namespace_id(namespace: &str) -> u64 {
+    let bytes = namespace.as_bytes();
+    let mut h = 0xcbf29ce484222325u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        h = (h ^ bytes[i] as u64).wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    h
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use {CacheKey, CartCache};
+
+    #[derive(CacheKey, Clone, Debug)]
+    struct UserKey {
+        name: String,
+        id: u32,
+    }
+
+    #[derive(CacheKey, Clone, Debug)]
+    #[cache_key(namespace = "sessions")]
+    struct SessionKey {
+        #[cache_key(borrow = "str")]
+        name: String,
+        id: u32,
+    }
+
+    #[derive(CacheKey)]
+    struct Pair(u32, u32);
+
+    fn hash<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn derive() {
+        assert_eq!(UserKey::NAMESPACE, "cart_cache::key::tests::UserKey");
+        assert_eq!(SessionKey::NAMESPACE, "sessions");
+        let user = UserKey {
+            name: "a".to_string(),
+            id: 1,
+        };
+        let session = SessionKey {
+            name: "a".to_string(),
+            id: 1,
+        };
+        assert_ne!(hash(&user), hash(&session));
+        assert!(Pair(1, 2) == Pair(1, 2) && Pair(1, 2) != Pair(2, 1));
+
+        let mut cache = CartCache::new(16).unwrap();
+        cache.insert(session, 1);
+        assert_eq!(cache.get(&SessionKeyRef { name: "a", id: &1 }), Some(&1));
+        assert_eq!(cache.get(&SessionKeyRef { name: "b", id: &1 }), None);
+        let mut cache = CartCache::new(16).unwrap();
+        cache.insert(user, 2);
+        let name = "a".to_string();
+        assert_eq!(
+            cache.get(&UserKeyRef {
+                name: &name,
+                id: &1
+            }),
+            Some(&2)
+        );
+    }
+}
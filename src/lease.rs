@@ -0,0 +1,132 @@
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+use {CartCache, Equivalent, Token};
+
+/// Permission to revalidate an entry, granted by `get_lease()`. It must be
+/// given back with `confirm_lease()` or `revoke_lease()` before its deadline,
+/// or the entry becomes suspect.
+#[must_use]
+#[derive(Debug)]
+pub struct Lease {
+    token: Token,
+    id: u64,
+    deadline: Instant,
+}
+
+impl Lease {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> CartCache<K, V, S> {
+    /// Like `get()`, but also grants a lease on the entry if no other lease
+    /// is outstanding. Suspect entries are reported as misses, so that the
+    /// next `get_or_insert_with()` refreshes them.
+    pub fn get_lease<Q>(&mut self, key: &Q, timeout: Duration) -> Option<(&V, Option<Lease>)>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = match self.live_token(key, true) {
+            Some(token) => token,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        self.record_hit(token);
+        let lease = if self.leases.contains_key(&token) {
+            None
+        } else {
+            self.next_lease = self.next_lease.wrapping_add(1);
+            let lease = Lease {
+                token,
+                id: self.next_lease,
+                deadline: Instant::now() + timeout,
+            };
+            self.leases.insert(token, (lease.id, lease.deadline));
+            Some(lease)
+        };
+        Some((&self.slab[token].value, lease))
+    }
+
+    /// Reports that the leased value is still valid. Returns `false` if the
+    /// lease was lost, because it expired or the entry was replaced.
+    pub fn confirm_lease(&mut self, lease: Lease) -> bool {
+        if !self.take_lease(&lease) {
+            return false;
+        }
+        if Instant::now() > lease.deadline {
+            self.nodes[lease.token].set_suspect(true);
+            return false;
+        }
+        true
+    }
+
+    /// Reports that the leased value is stale, making the entry suspect.
+    pub fn revoke_lease(&mut self, lease: Lease) {
+        if self.take_lease(&lease) {
+            self.nodes[lease.token].set_suspect(true);
+        }
+    }
+
+    pub fn outstanding_leases(&self) -> usize {
+        self.leases.len()
+    }
+
+    fn take_lease(&mut self, lease: &Lease) -> bool {
+        match self.leases.get(&lease.token) {
+            Some(&(id, _)) if id == lease.id => {
+                self.leases.remove(&lease.token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn is_suspect(&mut self, token: Token) -> bool {
+        if let Some(&(_, deadline)) = self.leases.get(&token) {
+            if Instant::now() > deadline {
+                self.leases.remove(&token);
+                self.nodes[token].set_suspect(true);
+            }
+        }
+        self.nodes[token].is_suspect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use CartCache;
+
+    #[test]
+    fn leases() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        cache.insert(1, 1);
+        let (_, lease) = cache.get_lease(&1, Duration::from_secs(60)).unwrap();
+        let lease = lease.unwrap();
+        assert!(cache
+            .get_lease(&1, Duration::from_secs(60))
+            .unwrap()
+            .1
+            .is_none());
+        assert!(cache.confirm_lease(lease));
+        assert_eq!(cache.outstanding_leases(), 0);
+
+        let lease = cache.get_lease(&1, Duration::from_secs(60)).unwrap().1;
+        cache.revoke_lease(lease.unwrap());
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(*cache.get_or_insert_with(1, || 2), 2);
+        assert_eq!(cache.get(&1), Some(&2));
+
+        let lease = cache.get_lease(&1, Duration::from_millis(1)).unwrap().1;
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.get(&1), None);
+        assert!(!cache.confirm_lease(lease.unwrap()));
+        cache.insert(1, 3);
+        assert_eq!(cache.get(&1), Some(&3));
+    }
+}
@@ -1,506 +1,5370 @@
-extern crate slab;
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
-use slab::Slab;
-use std::borrow::Borrow;
-use std::cmp::{max, min};
-use std::collections::HashMap;
-use std::collections::VecDeque;
-use std::hash::Hash;
-use std::marker::PhantomData;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "derive")]
+extern crate cart_cache_derive;
+#[cfg(feature = "foldhash")]
+extern crate foldhash;
+#[cfg(feature = "async")]
+extern crate futures_core;
+extern crate hashbrown;
+#[cfg(feature = "parking_lot")]
+extern crate parking_lot;
+#[cfg(all(test, feature = "derive"))]
+extern crate self as cart_cache;
+// The pyo3 macros expand to `::core` paths.
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+#[macro_export]
+macro_rules! memoize {
+    ($cache:expr, $key:expr, $value:expr) => {
+        $cache.get_or_insert_with($key, || $value)
+    };
+}
+
+#[macro_export]
+macro_rules! try_memoize {
+    ($cache:expr, $key:expr, $value:expr) => {
+        $cache.try_get_or_insert_with($key, || $value)
+    };
+}
+
+#[cfg(feature = "allocator_api")]
+pub mod allocator;
+pub mod any;
+pub mod budget;
+pub mod clock;
+pub mod codec;
+pub mod compact;
+mod config;
+#[cfg(feature = "test-suite")]
+pub mod conformance;
+#[cfg(feature = "serde")]
+pub mod debug;
+pub mod dedup;
+pub mod dual;
+mod epoch;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+#[cfg(feature = "async")]
+pub mod future;
+mod hasher;
+mod heap;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "serde")]
+pub mod journal;
+mod key;
+mod lease;
+pub mod local;
+mod ops;
+pub mod pressure;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "registry")]
+pub mod registry;
+mod rng;
+pub mod sharded;
+mod slots;
+pub mod small;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
+
+#[cfg(feature = "allocator_api")]
+pub use allocator::CacheAlloc;
+pub use any::AnyCache;
+pub use budget::WeightBudget;
+#[cfg(feature = "derive")]
+pub use cart_cache_derive::CacheKey;
+pub use clock::{ClockHandle, ClockList};
+pub use codec::{KeyCodec, PrefixCodec};
+pub use compact::CompactCartCache;
+pub use config::CartCacheConfig;
+#[cfg(feature = "test-suite")]
+pub use conformance::{conformance, CacheBehavior};
+#[cfg(feature = "serde")]
+pub use debug::{DebugDump, DebugNode};
+pub use dedup::DedupCache;
+pub use dual::DualKeyCache;
+pub use events::{
+    EvictionCause, EvictionEvent, KeyChange, LifecycleHandler, NotificationMode, Transition,
+};
+#[cfg(feature = "async")]
+pub use future::AsyncCartCache;
+pub use hashbrown::Equivalent;
+pub use hasher::{KeyedHasher, KeyedState};
+pub use heap::HeapSize;
+#[cfg(feature = "serde")]
+pub use journal::JournaledCache;
+pub use key::{namespace_id, CacheKey};
+pub use lease::Lease;
+pub use local::FrontCache;
+pub use ops::{apply_ops, Op};
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+pub use pressure::CgroupMemory;
+pub use pressure::{MemoryPressure, PressureGovernor};
+#[cfg(feature = "python")]
+pub use python::PyCartCache;
+pub use rng::{CacheRng, SplitMix64};
+pub use sharded::{PoisonPolicy, ShardedCartCache};
+pub use small::{SmallKey, SmallKeyCache};
+pub use trace::TraceReport;
+
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use std::borrow::Cow;
+use std::cmp::{max, min, Reverse};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use budget::BudgetShare;
+use codec::GhostKeys;
+use events::{ChangeLog, EvictionQueue};
+use filter::KeyFilter;
+use hasher::SipHasher13;
+#[cfg(feature = "registry")]
+use registry::Registration;
+use slots::Slots;
+use trace::Trace;
 
 type Token = usize;
 
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+type Sizer<K, V> = fn(&K, &V) -> usize;
+
+type Checksum<V> = Arc<dyn Fn(&V) -> u64 + Send + Sync>;
+
+// Storage of the list nodes, in the allocator of the cache with the
+// `allocator_api` feature.
+#[cfg(feature = "allocator_api")]
+type Nodes = Vec<Node, CacheAlloc>;
+#[cfg(not(feature = "allocator_api"))]
+type Nodes = Vec<Node>;
+
+type HashListener = Arc<dyn Fn(u64, Option<usize>, EvictionCause, Option<u64>) + Send + Sync>;
+
+type Lifecycle<K, V> = Arc<dyn LifecycleHandler<K, V> + Send + Sync>;
+
+type EvictMapper<K, V> = Arc<dyn Fn(&K, &mut V) + Send + Sync>;
+
+type Grouper<K> = Arc<dyn Fn(&K) -> usize + Send + Sync>;
+
+// Merge closure, cloned along with the cache so that clones don't share its
+// state.
+trait Merger<V>: Send + Sync {
+    fn merge(&mut self, current: &mut V, value: V);
+
+    fn clone_merger(&self) -> Box<dyn Merger<V>>;
+}
+
+impl<V, F: FnMut(&mut V, V) + Clone + Send + Sync + 'static> Merger<V> for F {
+    fn merge(&mut self, current: &mut V, value: V) {
+        self(current, value)
+    }
+
+    fn clone_merger(&self) -> Box<dyn Merger<V>> {
+        Box::new(self.clone())
+    }
+}
+
+// What `insert()` does with the value of a key that is already resident.
+enum ResidentInsert<V> {
+    Overwrite,
+    KeepOld,
+    Merge(Box<dyn Merger<V>>),
+}
+
+impl<V> Clone for ResidentInsert<V> {
+    fn clone(&self) -> Self {
+        match *self {
+            ResidentInsert::Overwrite => ResidentInsert::Overwrite,
+            ResidentInsert::KeepOld => ResidentInsert::KeepOld,
+            ResidentInsert::Merge(ref merge) => ResidentInsert::Merge(merge.clone_merger()),
+        }
+    }
+}
+
+#[derive(Clone)]
 struct Entry<K, V>
 where
     K: Eq + Hash,
 {
     key: K,
     value: V,
-    prev: Option<Token>,
-    next: Option<Token>,
-    is_history: bool,
-    is_reference: bool,
-    is_longterm: bool,
+    weight: usize,
+    group: usize,
+    expires_at: Option<Instant>,
+    recompute_cost: Option<Duration>,
+    // `None` after `get_mut()`, until the next lookup checksums the value.
+    checksum: Option<u64>,
+}
+
+const FLAG_HISTORY: u8 = 1;
+const FLAG_SUSPECT: u8 = 2;
+const FLAG_LONGTERM: u8 = 4;
+const FLAG_IN_T2: u8 = 8;
+const FLAG_LOADED: u8 = 16;
+const REFERENCE_SHIFT: u32 = 5;
+const REFERENCE_MASK: u8 = 0b1110_0000;
+const MAX_REFERENCES: u8 = REFERENCE_MASK >> REFERENCE_SHIFT;
+const WARMUP_ADAPTATION: usize = 4;
+// Expired entries removed by each insertion, so that inserts stay cheap
+// after many entries expire at once, while removing them faster than they
+// can be inserted.
+const EXPIRED_PER_INSERT: usize = 2;
+
+// Links are stored as 32-bit indices so that the clock sweeps, which only
+// look at nodes, fit several of them per cache line.
+const NIL: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+struct Node {
+    prev: u32,
+    next: u32,
+    flags: u8,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node {
+            prev: NIL,
+            next: NIL,
+            flags: 0,
+        }
+    }
+}
+
+#[inline]
+fn link(token: Option<Token>) -> u32 {
+    token.map_or(NIL, |token| token as u32)
+}
+
+#[inline]
+fn unlink_index(index: u32) -> Option<Token> {
+    if index == NIL {
+        None
+    } else {
+        Some(index as Token)
+    }
+}
+
+impl Node {
+    #[inline]
+    fn flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    #[inline]
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        self.flags = (self.flags & !flag) | (flag * on as u8);
+    }
+
+    #[inline]
+    fn is_history(&self) -> bool {
+        self.flag(FLAG_HISTORY)
+    }
+
+    #[inline]
+    fn set_history(&mut self, on: bool) {
+        self.set_flag(FLAG_HISTORY, on)
+    }
+
+    #[inline]
+    fn is_suspect(&self) -> bool {
+        self.flag(FLAG_SUSPECT)
+    }
+
+    #[inline]
+    fn set_suspect(&mut self, on: bool) {
+        self.set_flag(FLAG_SUSPECT, on)
+    }
+
+    #[inline]
+    fn is_reference(&self) -> bool {
+        self.flags & REFERENCE_MASK != 0
+    }
+
+    #[inline]
+    fn references(&self) -> u8 {
+        (self.flags & REFERENCE_MASK) >> REFERENCE_SHIFT
+    }
+
+    #[inline]
+    fn set_references(&mut self, references: u8) {
+        self.flags = (self.flags & !REFERENCE_MASK) | (references << REFERENCE_SHIFT);
+    }
+
+    #[inline]
+    fn reference(&mut self, max_references: u8) {
+        let references = self.references();
+        if references < max_references {
+            self.set_references(references + 1);
+        }
+    }
+
+    #[inline]
+    fn decay_references(&mut self, decrement: u8) {
+        let references = self.references().saturating_sub(decrement);
+        self.set_references(references);
+    }
+
+    #[inline]
+    fn is_longterm(&self) -> bool {
+        self.flag(FLAG_LONGTERM)
+    }
+
+    #[inline]
+    fn set_longterm(&mut self, on: bool) {
+        self.set_flag(FLAG_LONGTERM, on)
+    }
+
+    #[inline]
+    fn is_in_t2(&self) -> bool {
+        self.flag(FLAG_IN_T2)
+    }
+
+    #[inline]
+    fn set_in_t2(&mut self, on: bool) {
+        self.set_flag(FLAG_IN_T2, on)
+    }
+
+    #[inline]
+    fn is_loaded(&self) -> bool {
+        self.flag(FLAG_LOADED)
+    }
+
+    #[inline]
+    fn set_loaded(&mut self, on: bool) {
+        self.set_flag(FLAG_LOADED, on)
+    }
 }
 
-pub struct CartCache<K, V>
+/// Lookups accept any type the key borrows as, so caches keyed by shared
+/// strings, paths or byte strings are queried without allocating a key:
+///
+/// ```
+/// use cart_cache::CartCache;
+/// use std::path::Path;
+/// use std::rc::Rc;
+/// use std::sync::Arc;
+///
+/// let mut names: CartCache<Arc<str>, u32> = CartCache::new(16).unwrap();
+/// names.insert(Arc::from("alice"), 1);
+/// assert_eq!(names.get("alice"), Some(&1));
+///
+/// let mut files: CartCache<Arc<Path>, u64> = CartCache::new(16).unwrap();
+/// files.insert(Arc::from(Path::new("/etc/hosts")), 42);
+/// assert!(files.contains_key(Path::new("/etc/hosts")));
+///
+/// let mut blobs: CartCache<Rc<[u8]>, ()> = CartCache::new(16).unwrap();
+/// blobs.insert(Rc::from(&b"key"[..]), ());
+/// assert!(blobs.get(&b"key"[..]).is_some());
+/// ```
+///
+/// Other borrowed forms implement `Equivalent`, hashing like the key:
+///
+/// ```
+/// use cart_cache::{CartCache, Equivalent};
+/// use std::hash::{Hash, Hasher};
+///
+/// struct Borrowed<'a>(&'a str, u32);
+///
+/// impl<'a> Hash for Borrowed<'a> {
+///     fn hash<H: Hasher>(&self, state: &mut H) {
+///         (self.0, self.1).hash(state)
+///     }
+/// }
+///
+/// impl<'a> Equivalent<(String, u32)> for Borrowed<'a> {
+///     fn equivalent(&self, key: &(String, u32)) -> bool {
+///         self.0 == key.0 && self.1 == key.1
+///     }
+/// }
+///
+/// let mut cache: CartCache<(String, u32), u32> = CartCache::new(16).unwrap();
+/// cache.insert(("a".to_string(), 1), 10);
+/// assert_eq!(cache.get(&Borrowed("a", 1)), Some(&10));
+/// ```
+pub struct CartCache<K, V, S = KeyedState>
 where
     K: Eq + Hash,
 {
-    slab: Slab<Entry<K, V>>,
-    map: HashMap<K, Token>,
-    t1: VecDeque<Token>,
-    t2: VecDeque<Token>,
-    b1: XLinkedList<K, V>,
-    b2: XLinkedList<K, V>,
+    slab: Slots<Entry<K, V>>,
+    nodes: Nodes,
+    map: hashbrown::HashMap<K, Token, S>,
+    t1: XLinkedList,
+    t2: XLinkedList,
+    b1: XLinkedList,
+    b2: XLinkedList,
     c: usize,
     capacity: usize,
     p: usize,
+    p_min: usize,
+    p_max: usize,
     q: usize,
+    adaptive: bool,
+    max_references: u8,
+    reference_decrement: u8,
+    promotion_threshold: f64,
     shortterm_count: usize,
     longterm_count: usize,
+    max_weight: Option<usize>,
+    budget: Option<BudgetShare>,
+    weigher: Option<Weigher<K, V>>,
+    sizer: Option<Sizer<K, V>>,
+    checksum: Option<Checksum<V>>,
+    map_on_evict: Option<EvictMapper<K, V>>,
+    weight: usize,
+    grouper: Option<Grouper<K>>,
+    groups: HashMap<usize, Group>,
+    remembered_ghosts: HashMap<u64, bool>,
+    ghost_keys: Option<GhostKeys<K>>,
+    dependents: hashbrown::HashMap<K, HashSet<K>, RandomState>,
+    dependencies: HashMap<K, Vec<K>>,
+    subscribers: HashMap<K, Vec<Sender<()>>>,
+    leases: HashMap<Token, (u64, Instant)>,
+    next_lease: u64,
+    last_hit: Option<Token>,
+    ctx: Option<u64>,
+    key_filter: Option<Arc<KeyFilter>>,
+    eviction_queue: Option<EvictionQueue<K>>,
+    evicted_hash_listener: Option<HashListener>,
+    lifecycle: Option<Lifecycle<K, V>>,
+    change_log: Option<ChangeLog>,
+    resident_insert: ResidentInsert<V>,
+    on_exhausted: Exhausted,
+    trace: Option<Trace>,
+    warmup: Option<Warmup>,
+    maintenance: Maintenance,
+    ttl: Option<Duration>,
+    ttl_jitter: u8,
+    early_expiration: Option<f64>,
+    rng: Box<dyn CacheRng>,
+    timers: BTreeSet<(Instant, Token)>,
+    loaded_count: usize,
     inserted: u64,
+    loaded: u64,
     evicted: u64,
+    evicted_by_count: u64,
+    evicted_by_weight: u64,
+    evicted_by_quota: u64,
+    exhausted: u64,
+    resets: u64,
+    hits: u64,
+    misses: u64,
+    expired: u64,
+    #[cfg(feature = "log")]
+    report_interval: Option<u64>,
+    #[cfg(feature = "log")]
+    operations: u64,
+    #[cfg(feature = "perf_counters")]
+    perf: PerfCounters,
+    #[cfg(feature = "registry")]
+    registration: Option<Registration>,
 }
 
-impl<K: Eq + Hash, V> CartCache<K, V> {
-    pub fn new(capacity: usize) -> Result<CartCache<K, V>, &'static str> {
-        if capacity == 0 {
+/// How often each branch of the replacement algorithm was taken
+/// (`perf_counters` feature). Unlike `Stats`, these are never reset.
+#[cfg(feature = "perf_counters")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerfCounters {
+    /// Lookups returning a resident entry.
+    pub resident_hits: u64,
+    /// Inserts of resident keys, which only update the value.
+    pub resident_updates: u64,
+    /// Inserts of keys found in B1.
+    pub b1_promotions: u64,
+    /// Inserts of keys found in B2.
+    pub b2_promotions: u64,
+    /// Inserts of keys that were not tracked.
+    pub new_inserts: u64,
+    /// Entries skipped by the T1 clock hand.
+    pub t1_sweeps: u64,
+    /// Entries skipped by the T2 clock hand.
+    pub t2_sweeps: u64,
+}
+
+/// An allocation failed, or its size would overflow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CartCacheError {
+    AllocError,
+}
+
+/// A value no longer matches the checksum taken when it was stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CorruptEntry;
+
+/// Counters since the cache was built or last cleared. `resets` counts
+/// `clear()` calls and is never reset itself. `evicted_by_count` only counts
+/// replacements that actually demoted a resident entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub inserted: u64,
+    pub loaded: u64,
+    pub evicted: u64,
+    pub evicted_by_count: u64,
+    pub evicted_by_weight: u64,
+    pub evicted_by_quota: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub expired: u64,
+    pub resets: u64,
+    /// Replacements that found no entry to demote, see `on_exhausted()`.
+    pub exhausted: u64,
+}
+
+impl Stats {
+    /// Counts between the `earlier` snapshot and this one. Deltas wrap
+    /// around like the counters and are never negative; if the cache was
+    /// cleared in between, they are the counts since the last clear.
+    pub fn delta_since(&self, earlier: &Stats) -> Stats {
+        let base = if self.resets == earlier.resets {
+            *earlier
+        } else {
+            Stats::default()
+        };
+        Stats {
+            inserted: self.inserted.wrapping_sub(base.inserted),
+            loaded: self.loaded.wrapping_sub(base.loaded),
+            evicted: self.evicted.wrapping_sub(base.evicted),
+            evicted_by_count: self.evicted_by_count.wrapping_sub(base.evicted_by_count),
+            evicted_by_weight: self.evicted_by_weight.wrapping_sub(base.evicted_by_weight),
+            evicted_by_quota: self.evicted_by_quota.wrapping_sub(base.evicted_by_quota),
+            hits: self.hits.wrapping_sub(base.hits),
+            misses: self.misses.wrapping_sub(base.misses),
+            expired: self.expired.wrapping_sub(base.expired),
+            resets: self.resets.wrapping_sub(earlier.resets),
+            exhausted: self.exhausted.wrapping_sub(base.exhausted),
+        }
+    }
+}
+
+/// Per-group counters. Misses are only attributed to a group when the key is
+/// known, i.e. from the `get_or_insert` family and rejected `get_if` lookups.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    pub len: usize,
+    pub weight: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evicted: u64,
+}
+
+/// While warming up, residents may use every slot and adaptation steps are
+/// scaled by `WARMUP_ADAPTATION`.
+#[derive(Clone, Copy)]
+struct Warmup {
+    remaining: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+// Expired entries removed per `run_maintenance()` call. The batch doubles
+// after quiet periods and halves after busy ones.
+#[derive(Clone, Copy)]
+struct Maintenance {
+    min_batch: usize,
+    max_batch: usize,
+    batch: usize,
+    operations: u64,
+}
+
+#[derive(Clone, Default)]
+struct Group {
+    stats: GroupStats,
+    max_weight: Option<usize>,
+    // Resident entries of a group with a quota, which only applies to them,
+    // and their weight.
+    residents: BTreeSet<Token>,
+    resident_weight: usize,
+}
+
+/// What to do when a cache over its limits finds no entry to demote,
+/// because `T1` is below its target size and `T2` is empty, e.g. with a few
+/// heavy entries under `max_weight()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Exhausted {
+    /// Demote the oldest entry of `T1` anyway.
+    ForceEvict,
+    /// Drop the new entry before anything is inserted, under the count and
+    /// the weight limits. `insert_or_return()` hands it back; loaders of the
+    /// `get_or_insert` family over-provision instead.
+    Reject,
+    /// Let the cache grow past its capacity until a later replacement
+    /// finds a victim.
+    #[default]
+    OverProvision,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryClass {
+    /// Seen once recently.
+    Recent,
+    /// Seen at least twice recently.
+    Frequent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntrySource {
+    Inserted,
+    Loaded,
+}
+
+/// Learned replacement parameters, without any keys or values.
+///
+/// Ghost entries are identified by the `fingerprint()` of their key, so a
+/// state can be restored into any cache with the same key type. After
+/// `set_policy_state()`, a newly inserted key matching a ghost hash is
+/// admitted as if it had been found in the corresponding history list.
+/// The entry counts are informational and are not restored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PolicyState {
+    pub p: usize,
+    pub q: usize,
+    pub shortterm_count: usize,
+    pub longterm_count: usize,
+    pub recent_ghosts: Vec<u64>,
+    pub frequent_ghosts: Vec<u64>,
+}
+
+/// Hash of a key that does not depend on the hash keys of any cache, as used
+/// by `KeyChange`, `state_digest()`, `on_evicted_hash()` and `PolicyState`.
+/// This is SipHash-1-3 with all-zero keys, integers being hashed as
+/// little-endian 64-bit words, so it is the same on every platform and
+/// compiler as long as the `Hash` implementation of the key doesn't change.
+pub fn fingerprint<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    let mut hasher = SipHasher13::new_with_keys(0, 0);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub struct CartCacheBuilder<K, V> {
+    capacity: usize,
+    max_weight: Option<usize>,
+    budget: Option<Arc<WeightBudget>>,
+    weigher: Option<Weigher<K, V>>,
+    sizer: Option<Sizer<K, V>>,
+    checksum: Option<Checksum<V>>,
+    map_on_evict: Option<EvictMapper<K, V>>,
+    ghost_key_codec: Option<Arc<dyn KeyCodec<K>>>,
+    grouper: Option<Grouper<K>>,
+    group_quotas: HashMap<usize, usize>,
+    ttl: Option<Duration>,
+    ttl_jitter: u8,
+    early_expiration: Option<f64>,
+    rng: Option<Box<dyn CacheRng>>,
+    target_bounds: (f64, f64),
+    static_parameters: Option<(usize, usize)>,
+    reference_counter: (u8, u8),
+    promotion_threshold: f64,
+    key_filter: bool,
+    eviction_notifications: Option<NotificationMode>,
+    evicted_hash_listener: Option<HashListener>,
+    lifecycle: Option<Lifecycle<K, V>>,
+    change_log: Option<usize>,
+    resident_insert: ResidentInsert<V>,
+    on_exhausted: Exhausted,
+    trace_window: Option<usize>,
+    hasher: KeyedState,
+    warmup_operations: Option<u64>,
+    warmup_duration: Option<Duration>,
+    maintenance_batch: (usize, usize),
+    #[cfg(feature = "log")]
+    report_interval: Option<u64>,
+    #[cfg(feature = "registry")]
+    registration: Option<(String, u64)>,
+    #[cfg(feature = "allocator_api")]
+    allocator: Option<CacheAlloc>,
+}
+
+impl<K: Eq + Hash, V> CartCacheBuilder<K, V> {
+    /// `capacity` is the total number of slots, shared between resident
+    /// entries and history entries: at most `capacity / 2` entries are resident.
+    pub fn new(capacity: usize) -> Self {
+        CartCacheBuilder {
+            capacity,
+            max_weight: None,
+            budget: None,
+            weigher: None,
+            sizer: None,
+            checksum: None,
+            map_on_evict: None,
+            ghost_key_codec: None,
+            grouper: None,
+            group_quotas: HashMap::new(),
+            ttl: None,
+            ttl_jitter: 0,
+            early_expiration: None,
+            rng: None,
+            target_bounds: (0.0, 1.0),
+            static_parameters: None,
+            reference_counter: (1, 1),
+            promotion_threshold: 1.0,
+            key_filter: false,
+            eviction_notifications: None,
+            evicted_hash_listener: None,
+            lifecycle: None,
+            change_log: None,
+            resident_insert: ResidentInsert::Overwrite,
+            on_exhausted: Exhausted::default(),
+            trace_window: None,
+            hasher: KeyedState::new(),
+            warmup_operations: None,
+            warmup_duration: None,
+            maintenance_batch: (16, 1024),
+            #[cfg(feature = "log")]
+            report_interval: None,
+            #[cfg(feature = "registry")]
+            registration: None,
+            #[cfg(feature = "allocator_api")]
+            allocator: None,
+        }
+    }
+
+    pub fn with_resident_capacity(resident_capacity: usize) -> Self {
+        Self::new(resident_capacity.saturating_mul(2))
+    }
+
+    /// Limits the total weight of the entries. History entries keep their
+    /// values, so their weight counts too; `map_on_evict()` can shrink it.
+    pub fn max_weight(mut self, max_weight: usize) -> Self {
+        self.max_weight = Some(max_weight);
+        self
+    }
+
+    /// Counts the weight of the cache against a `WeightBudget` shared with
+    /// other caches, in addition to its own `max_weight()`.
+    pub fn shared_budget(mut self, budget: Arc<WeightBudget>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn weigher<F>(mut self, weigher: F) -> Self
+    where
+        F: Fn(&K, &V) -> usize + Send + Sync + 'static,
+    {
+        self.weigher = Some(Arc::new(weigher));
+        self
+    }
+
+    /// Weighs entries by the memory their key and value occupy, inline and
+    /// on the heap, so that `max_weight()` is a number of bytes.
+    pub fn weigh_by_size(self) -> Self
+    where
+        K: HeapSize,
+        V: HeapSize,
+    {
+        self.weigher(|key: &K, value: &V| key.total_size() + value.total_size())
+    }
+
+    /// Enables `heap_bytes()`, an estimate of the memory used by the cache:
+    /// entries and their heap allocations, plus the capacity of the internal
+    /// tables. Allocator overhead is not included.
+    pub fn measure_memory(mut self) -> Self
+    where
+        K: HeapSize,
+        V: HeapSize,
+    {
+        // Keys are stored twice, in the entries and in the index.
+        self.sizer = Some(|key: &K, value: &V| 2 * key.heap_size() + value.heap_size());
+        self
+    }
+
+    /// Allocates the entries and the list nodes with `allocator`. The index,
+    /// other bookkeeping such as timers, and the heap data of keys and
+    /// values still use the global allocator: the allocator support of the
+    /// hash table requires crates this one doesn't depend on.
+    #[cfg(feature = "allocator_api")]
+    pub fn allocator(mut self, allocator: CacheAlloc) -> Self {
+        self.allocator = Some(allocator);
+        self
+    }
+
+    /// Weighs `Bytes` values by their length, so that `max_weight()` is a
+    /// number of bytes.
+    #[cfg(feature = "bytes")]
+    pub fn weigh_by_len(self) -> Self
+    where
+        V: AsRef<[u8]>,
+    {
+        self.weigher(|_: &K, value: &V| value.as_ref().len())
+    }
+
+    /// Stores a checksum of every value, and verifies it on lookups, to
+    /// detect values overwritten by memory corruption elsewhere in the
+    /// process. Corrupt entries are removed, and reported by
+    /// `get_verified()`.
+    ///
+    /// Values returned by `get_mut()` are checksummed again on their next
+    /// lookup, or by `finish_mutation()`.
+    pub fn checksum<F>(mut self, checksum: F) -> Self
+    where
+        F: Fn(&V) -> u64 + Send + Sync + 'static,
+    {
+        self.checksum = Some(Arc::new(checksum));
+        self
+    }
+
+    /// Transforms values in place as their entries are demoted to history,
+    /// e.g. to keep a smaller version of them in ghost entries.
+    pub fn map_on_evict<F>(mut self, map_on_evict: F) -> Self
+    where
+        F: Fn(&K, &mut V) + Send + Sync + 'static,
+    {
+        self.map_on_evict = Some(Arc::new(map_on_evict));
+        self
+    }
+
+    /// Keeps the keys of history entries encoded with `codec`, e.g. a
+    /// `PrefixCodec` for URLs sharing a few prefixes, and drops their values.
+    /// History entries are then out of the index: lookups miss them, and
+    /// they are only found again by insertions, that promote them. They are
+    /// left out of debug dumps. Caches limited by weight can't encode keys,
+    /// as dropping history entries would not make room for new ones.
+    pub fn ghost_key_codec<C>(mut self, codec: C) -> Self
+    where
+        C: KeyCodec<K> + 'static,
+    {
+        self.ghost_key_codec = Some(Arc::new(codec));
+        self
+    }
+
+    pub fn quota_group_by<F>(mut self, grouper: F) -> Self
+    where
+        F: Fn(&K) -> usize + Send + Sync + 'static,
+    {
+        self.grouper = Some(Arc::new(grouper));
+        self
+    }
+
+    /// Limits the weight of the resident entries of `group`. Entries over
+    /// the quota are demoted to the history, where they no longer count.
+    pub fn group_quota(mut self, group: usize, max_weight: usize) -> Self {
+        self.group_quotas.insert(group, max_weight);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Randomly spreads the default TTL by up to `percent`% in either
+    /// direction, so that entries inserted together don't expire together.
+    pub fn ttl_jitter(mut self, percent: u8) -> Self {
+        self.ttl_jitter = percent;
+        self
+    }
+
+    /// Bounds for the number of expired entries `run_maintenance()` removes
+    /// per call.
+    pub fn maintenance_batch(mut self, min_batch: usize, max_batch: usize) -> Self {
+        self.maintenance_batch = (min_batch, max_batch);
+        self
+    }
+
+    /// Probabilistic early expiration (X-Fetch): lookups of entries close to
+    /// their deadline miss with a probability that grows with the time it
+    /// took to load them, scaled by `beta` (1.0 is a good default). This
+    /// only applies to reads and loads; `get_mut()`, `set_ttl()` and
+    /// `persist()` use the actual deadline.
+    pub fn early_expiration(mut self, beta: f64) -> Self {
+        self.early_expiration = Some(beta);
+        self
+    }
+
+    pub fn rng<R>(mut self, rng: R) -> Self
+    where
+        R: CacheRng + 'static,
+    {
+        self.rng = Some(Box::new(rng));
+        self
+    }
+
+    pub fn target_bounds(mut self, p_min: f64, p_max: f64) -> Self {
+        self.target_bounds = (p_min, p_max);
+        self
+    }
+
+    /// Replaces the reference bit with a counter saturating at
+    /// `max_references` (up to 7), decremented by `decrement` on each pass of
+    /// the clock hand. `(1, 1)` is the regular CART behavior.
+    pub fn reference_counter(mut self, max_references: u8, decrement: u8) -> Self {
+        self.reference_counter = (max_references, decrement);
+        self
+    }
+
+    /// Scales the `T1` length above which referenced entries become
+    /// long-term, `min(p + 1, |B1|)`. Factors above 1 make long-term status
+    /// harder to earn, factors below 1 make it easier.
+    pub fn promotion_threshold(mut self, factor: f64) -> Self {
+        self.promotion_threshold = factor;
+        self
+    }
+
+    pub fn static_parameters(mut self, p: usize, q: usize) -> Self {
+        self.static_parameters = Some((p, q));
+        self
+    }
+
+    pub fn key_filter(mut self, enabled: bool) -> Self {
+        self.key_filter = enabled;
+        self
+    }
+
+    /// Keeps the current value when inserting a key that is already resident.
+    /// `insert_or_return()` hands the rejected value back.
+    pub fn keep_resident_values(mut self) -> Self {
+        self.resident_insert = ResidentInsert::KeepOld;
+        self
+    }
+
+    /// Combines the current and the new value when inserting a key that is
+    /// already resident. Clones of the cache get their own copy of `merge`.
+    pub fn merge_resident_values<F>(mut self, merge: F) -> Self
+    where
+        F: FnMut(&mut V, V) + Clone + Send + Sync + 'static,
+    {
+        self.resident_insert = ResidentInsert::Merge(Box::new(merge));
+        self
+    }
+
+    /// Sets what a full cache does when it finds no entry to demote.
+    /// Defaults to `Exhausted::OverProvision`.
+    pub fn on_exhausted(mut self, on_exhausted: Exhausted) -> Self {
+        self.on_exhausted = on_exhausted;
+        self
+    }
+
+    /// Records the fingerprints of the last `window` lookups, so that
+    /// `trace_report()` can compare the hit rate with LRU and Belady's optimum.
+    pub fn trace_window(mut self, window: usize) -> Self {
+        self.trace_window = Some(window);
+        self
+    }
+
+    /// Hashes keys with SipHash under the given keys instead of random ones.
+    pub fn hash_keys(mut self, k0: u64, k1: u64) -> Self {
+        self.hasher = KeyedState::with_keys(k0, k1);
+        self
+    }
+
+    /// Hashes keys under fresh random keys, so that clients choosing keys
+    /// cannot predict collisions in the index or the key filter. This is the
+    /// default, and undoes `hash_keys()`.
+    pub fn with_random_seed(mut self) -> Self {
+        self.hasher = KeyedState::new();
+        self
+    }
+
+    /// Admits every insert as a resident, without keeping room for history
+    /// entries, for the first `operations` inserts and loads. Adaptation is
+    /// accelerated meanwhile; excess residents are demoted once it ends.
+    pub fn warmup_operations(mut self, operations: u64) -> Self {
+        self.warmup_operations = Some(operations);
+        self
+    }
+
+    /// Like `warmup_operations()`, for a window starting when the cache is
+    /// built. If both are set, warmup ends with whichever elapses first.
+    pub fn warmup_duration(mut self, duration: Duration) -> Self {
+        self.warmup_duration = Some(duration);
+        self
+    }
+
+    pub fn eviction_notifications(mut self, mode: NotificationMode) -> Self {
+        self.eviction_notifications = Some(mode);
+        self
+    }
+
+    /// Calls `listener` as entries leave the cache, with the `fingerprint()`
+    /// of their key, their `quota_group_by()` group if set, the cause, and
+    /// the context of the `insert_with_ctx()` call that caused it. Unlike
+    /// `eviction_notifications()`, keys are neither cloned nor queued, e.g.
+    /// to send purge signals for high-churn caches. Clones of the cache don't
+    /// call it.
+    pub fn on_evicted_hash<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(u64, Option<usize>, EvictionCause, Option<u64>) + Send + Sync + 'static,
+    {
+        self.evicted_hash_listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Reports every `Transition` of every entry to `handler`. Clones of the
+    /// cache don't keep the handler, as their entries were never admitted.
+    pub fn lifecycle<H>(mut self, handler: H) -> Self
+    where
+        H: LifecycleHandler<K, V> + Send + Sync + 'static,
+    {
+        self.lifecycle = Some(Arc::new(handler));
+        self
+    }
+
+    /// Keeps the last `capacity` changes to the resident set, for
+    /// `changes_since()`.
+    pub fn change_log(mut self, capacity: usize) -> Self {
+        self.change_log = Some(capacity);
+        self
+    }
+
+    #[cfg(feature = "log")]
+    pub fn report_interval(mut self, operations: u64) -> Self {
+        self.report_interval = Some(operations);
+        self
+    }
+
+    /// Adds the cache to the process-wide `registry` under `name`, with its
+    /// statistics published every `operations` lookups and inserts, and on
+    /// every `run_maintenance()`.
+    #[cfg(feature = "registry")]
+    pub fn register(mut self, name: &str, operations: u64) -> Self {
+        self.registration = Some((name.to_string(), operations));
+        self
+    }
+
+    pub fn build(self) -> Result<CartCache<K, V>, &'static str> {
+        let hasher = self.hasher.clone();
+        self.build_with_hasher(hasher)
+    }
+
+    /// Builds a `CompactCartCache`, with small keys and values stored
+    /// inline. Only the capacity, the hasher, `target_bounds()` and
+    /// `reference_counter()` apply, and other options are rejected.
+    pub fn build_compact(self) -> Result<CompactCartCache<K, V>, &'static str>
+    where
+        K: Copy,
+        V: Copy,
+    {
+        let (p_min, p_max) = self.policy_bounds()?;
+        if self.max_weight.is_some()
+            || self.budget.is_some()
+            || self.weigher.is_some()
+            || self.sizer.is_some()
+            || self.checksum.is_some()
+            || self.map_on_evict.is_some()
+            || self.ghost_key_codec.is_some()
+            || self.grouper.is_some()
+            || self.ttl.is_some()
+            || self.static_parameters.is_some()
+            || self.promotion_threshold != 1.0
+            || self.key_filter
+            || self.eviction_notifications.is_some()
+            || self.evicted_hash_listener.is_some()
+            || self.lifecycle.is_some()
+            || self.change_log.is_some()
+            || !matches!(self.resident_insert, ResidentInsert::Overwrite)
+            || self.on_exhausted != Exhausted::default()
+            || self.trace_window.is_some()
+            || self.warmup_operations.is_some()
+            || self.warmup_duration.is_some()
+        {
+            return Err("Option not supported by compact caches");
+        }
+        Ok(CompactCartCache::with_parameters(
+            self.capacity,
+            self.hasher,
+            (p_min, p_max),
+            self.reference_counter,
+        ))
+    }
+
+    // Checks the capacity and the replacement parameters, and returns the
+    // bounds of the target size of T1.
+    fn policy_bounds(&self) -> Result<(usize, usize), &'static str> {
+        if self.capacity == 0 {
             return Err("Cache length cannot be zero");
         }
-        let c = capacity / 2;
-        let slab = Slab::with_capacity(capacity);
-        let map = HashMap::with_capacity(c);
-        let t1 = VecDeque::with_capacity(c);
-        let t2 = VecDeque::with_capacity(c);
-        let b1 = XLinkedList::new();
-        let b2 = XLinkedList::new();
+        if self.capacity >= NIL as usize {
+            return Err("Cache length is too large");
+        }
+        let (p_min, p_max) = self.target_bounds;
+        if !(0.0..=1.0).contains(&p_min) || !(0.0..=1.0).contains(&p_max) || p_min > p_max {
+            return Err("Invalid adaptive target bounds");
+        }
+        let (max_references, reference_decrement) = self.reference_counter;
+        if max_references == 0
+            || max_references > MAX_REFERENCES
+            || reference_decrement == 0
+            || reference_decrement > max_references
+        {
+            return Err("Invalid reference counter");
+        }
+        let c = self.capacity / 2;
+        Ok((
+            (p_min * c as f64).round() as usize,
+            (p_max * c as f64).round() as usize,
+        ))
+    }
+
+    /// Like `build()`, with keys hashed by `hasher` instead of the state set
+    /// by `hash_keys()` or `with_random_seed()`.
+    pub fn build_with_hasher<S: BuildHasher>(
+        self,
+        hasher: S,
+    ) -> Result<CartCache<K, V, S>, &'static str> {
+        let capacity = self.capacity;
+        let (p_min, p_max) = self.policy_bounds()?;
+        if self.max_weight == Some(0) {
+            return Err("Cache weight cannot be zero");
+        }
+        if self.ghost_key_codec.is_some() && (self.max_weight.is_some() || self.budget.is_some()) {
+            return Err("Ghost keys cannot be encoded with a weight limit");
+        }
+        if self
+            .group_quotas
+            .values()
+            .any(|&max_weight| max_weight == 0)
+        {
+            return Err("Group quota cannot be zero");
+        }
+        #[cfg(feature = "log")]
+        {
+            if self.report_interval == Some(0) {
+                return Err("Report interval cannot be zero");
+            }
+        }
+        #[cfg(feature = "registry")]
+        {
+            if matches!(self.registration, Some((_, 0))) {
+                return Err("Registry interval cannot be zero");
+            }
+        }
+        if !self.promotion_threshold.is_finite() || self.promotion_threshold < 0.0 {
+            return Err("Invalid promotion threshold");
+        }
+        if self.trace_window == Some(0) {
+            return Err("Trace window cannot be zero");
+        }
+        if self.change_log == Some(0) {
+            return Err("Change log capacity cannot be zero");
+        }
+        if self.warmup_operations == Some(0) || self.warmup_duration == Some(Duration::ZERO) {
+            return Err("Warmup window cannot be empty");
+        }
+        let (min_batch, max_batch) = self.maintenance_batch;
+        if min_batch == 0 || min_batch > max_batch {
+            return Err("Invalid maintenance batch sizes");
+        }
+        if self.ttl_jitter > 100 {
+            return Err("Invalid TTL jitter");
+        }
+        if let Some(beta) = self.early_expiration {
+            if !beta.is_finite() || beta <= 0.0 {
+                return Err("Invalid early expiration factor");
+            }
+        }
+        let c = capacity / 2;
+        let (max_references, reference_decrement) = self.reference_counter;
+        let (p, q) = match self.static_parameters {
+            Some((p, q)) => (min(p, c), min(q, c)),
+            None => (p_min, 0),
+        };
+        #[cfg(not(feature = "allocator_api"))]
+        let (slab, nodes) = (Slots::new(), Vec::new());
+        #[cfg(feature = "allocator_api")]
+        let (slab, nodes) = {
+            let allocator = self.allocator.unwrap_or_default();
+            (Slots::new_in(allocator.clone()), Vec::new_in(allocator))
+        };
+        let map = hashbrown::HashMap::with_hasher(hasher);
+        let t1 = XLinkedList::new();
+        let t2 = XLinkedList::new();
+        let b1 = XLinkedList::new();
+        let b2 = XLinkedList::new();
+
+        let mut cache = CartCache {
+            slab,
+            nodes,
+            map,
+            t1,
+            t2,
+            b1,
+            b2,
+            c,
+            capacity,
+            p,
+            p_min,
+            p_max,
+            q,
+            adaptive: self.static_parameters.is_none(),
+            max_references,
+            reference_decrement,
+            promotion_threshold: self.promotion_threshold,
+            shortterm_count: 0,
+            longterm_count: 0,
+            max_weight: self.max_weight,
+            budget: self.budget.map(|budget| BudgetShare::new(budget, 0)),
+            weigher: self.weigher,
+            sizer: self.sizer,
+            checksum: self.checksum,
+            map_on_evict: self.map_on_evict,
+            weight: 0,
+            grouper: self.grouper,
+            groups: self
+                .group_quotas
+                .into_iter()
+                .map(|(group, max_weight)| {
+                    (
+                        group,
+                        Group {
+                            max_weight: Some(max_weight),
+                            ..Group::default()
+                        },
+                    )
+                })
+                .collect(),
+            remembered_ghosts: HashMap::new(),
+            ghost_keys: self.ghost_key_codec.map(GhostKeys::new),
+            dependents: hashbrown::HashMap::with_hasher(RandomState::new()),
+            dependencies: HashMap::new(),
+            subscribers: HashMap::new(),
+            leases: HashMap::new(),
+            next_lease: 0,
+            last_hit: None,
+            ctx: None,
+            key_filter: if self.key_filter {
+                Some(Arc::new(KeyFilter::new(capacity)))
+            } else {
+                None
+            },
+            eviction_queue: self.eviction_notifications.map(EvictionQueue::new),
+            evicted_hash_listener: self.evicted_hash_listener,
+            lifecycle: self.lifecycle,
+            change_log: self.change_log.map(ChangeLog::new),
+            resident_insert: self.resident_insert,
+            on_exhausted: self.on_exhausted,
+            trace: self.trace_window.map(Trace::new),
+            warmup: if self.warmup_operations.is_some() || self.warmup_duration.is_some() {
+                Some(Warmup {
+                    remaining: self.warmup_operations,
+                    deadline: self
+                        .warmup_duration
+                        .map(|duration| Instant::now() + duration),
+                })
+            } else {
+                None
+            },
+            maintenance: Maintenance {
+                min_batch,
+                max_batch,
+                batch: min_batch,
+                operations: 0,
+            },
+            ttl: self.ttl,
+            ttl_jitter: self.ttl_jitter,
+            early_expiration: self.early_expiration,
+            rng: self
+                .rng
+                .unwrap_or_else(|| Box::new(SplitMix64::new()) as Box<dyn CacheRng>),
+            timers: BTreeSet::new(),
+            loaded_count: 0,
+            inserted: 0,
+            loaded: 0,
+            evicted: 0,
+            evicted_by_count: 0,
+            evicted_by_weight: 0,
+            evicted_by_quota: 0,
+            exhausted: 0,
+            resets: 0,
+            hits: 0,
+            misses: 0,
+            expired: 0,
+            #[cfg(feature = "log")]
+            report_interval: self.report_interval,
+            #[cfg(feature = "log")]
+            operations: 0,
+            #[cfg(feature = "perf_counters")]
+            perf: PerfCounters::default(),
+            #[cfg(feature = "registry")]
+            registration: None,
+        };
+        cache
+            .try_reserve(capacity)
+            .map_err(|_| "Cannot allocate the cache")?;
+        #[cfg(feature = "registry")]
+        {
+            if let Some((name, interval)) = self.registration {
+                cache.registration = Some(Registration::new(name, interval, cache.stats()));
+            }
+        }
+        Ok(cache)
+    }
+}
+
+impl<K: Eq + Hash, V> CartCache<K, V> {
+    /// Creates a cache with `capacity` slots, half of which hold resident
+    /// entries. Use `with_resident_capacity()` to size by resident entries.
+    pub fn new(capacity: usize) -> Result<CartCache<K, V>, &'static str> {
+        CartCacheBuilder::new(capacity).build()
+    }
+
+    pub fn with_resident_capacity(
+        resident_capacity: usize,
+    ) -> Result<CartCache<K, V>, &'static str> {
+        CartCacheBuilder::with_resident_capacity(resident_capacity).build()
+    }
+
+    pub fn from_map<S>(map: HashMap<K, V, S>, capacity: usize) -> Result<Self, &'static str>
+    where
+        K: Clone,
+    {
+        let mut cache = CartCache::new(capacity)?;
+        for (key, value) in map {
+            cache.insert(key, value);
+        }
+        Ok(cache)
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> CartCache<K, V, S> {
+    pub fn into_map(self) -> HashMap<K, V> {
+        let now = Instant::now();
+        let nodes = self.nodes;
+        self.slab
+            .into_items()
+            .filter(|&(token, ref entry)| {
+                !nodes[token].is_history() && entry.expires_at.is_none_or(|t| t > now)
+            })
+            .map(|(_, entry)| (entry.key, entry.value))
+            .collect()
+    }
+
+    /// Starts a read-only pass over the resident entries. The guard borrows
+    /// the cache, so the borrow checker rejects any modification, including
+    /// lookups, which update reference bits, until it is dropped.
+    pub fn scan(&self) -> Scan<'_, K, V, S> {
+        Scan {
+            cache: self,
+            now: Instant::now(),
+        }
+    }
+
+    /// Returns `true` if both caches hold the same resident keys with equal
+    /// values, regardless of their history, order or replacement state.
+    pub fn content_eq(&self, other: &Self) -> bool
+    where
+        V: PartialEq,
+    {
+        let (ours, theirs) = (self.scan(), other.scan());
+        ours.iter().count() == theirs.iter().count()
+            && ours.iter().all(|(key, value)| {
+                other
+                    .peek_token(key)
+                    .filter(|&token| !other.nodes[token].is_history())
+                    .map(|token| &other.slab[token].value)
+                    == Some(value)
+            })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn resident_capacity(&self) -> usize {
+        self.c
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len() + self.ghost_len()
+    }
+
+    // Number of history entries with an encoded key.
+    fn ghost_len(&self) -> usize {
+        self.ghost_keys.as_ref().map_or(0, GhostKeys::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn frequent_len(&self) -> usize {
+        self.longterm_count + self.b2.len()
+    }
+
+    pub fn recent_len(&self) -> usize {
+        self.shortterm_count + self.b1.len()
+    }
+
+    pub fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn max_weight(&self) -> Option<usize> {
+        self.max_weight
+    }
+
+    /// Total weight of the entries, history entries included.
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn shared_budget(&self) -> Option<&Arc<WeightBudget>> {
+        self.budget.as_ref().map(BudgetShare::budget)
+    }
+
+    /// Leaves the current `WeightBudget` if any, and joins `budget`, e.g. to
+    /// count a clone against the budget of the original.
+    pub fn set_shared_budget(&mut self, budget: Option<Arc<WeightBudget>>) {
+        self.budget = budget.map(|budget| BudgetShare::new(budget, self.weight));
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            inserted: self.inserted,
+            loaded: self.loaded,
+            evicted: self.evicted,
+            evicted_by_count: self.evicted_by_count,
+            evicted_by_weight: self.evicted_by_weight,
+            evicted_by_quota: self.evicted_by_quota,
+            hits: self.hits,
+            misses: self.misses,
+            expired: self.expired,
+            resets: self.resets,
+            exhausted: self.exhausted,
+        }
+    }
+
+    /// Estimated memory used by the cache, if `measure_memory()` was set.
+    /// This walks all the entries, and is not part of `stats()`.
+    pub fn heap_bytes(&self) -> Option<usize> {
+        let sizer = self.sizer?;
+        let entries: usize = self
+            .slab
+            .iter()
+            .map(|(_, entry)| sizer(&entry.key, &entry.value))
+            .sum();
+        Some(
+            entries
+                + self.slab.capacity() * mem::size_of::<Entry<K, V>>()
+                + self.nodes.capacity() * mem::size_of::<Node>()
+                + self.map.capacity() * (mem::size_of::<(K, Token)>() + 1)
+                + self.ghost_keys.as_ref().map_or(0, GhostKeys::heap_bytes),
+        )
+    }
+
+    #[cfg(feature = "perf_counters")]
+    pub fn perf_counters(&self) -> PerfCounters {
+        self.perf
+    }
+
+    /// Shorthand for `stats().delta_since(snapshot)`.
+    pub fn stats_delta_since(&self, snapshot: &Stats) -> Stats {
+        self.stats().delta_since(snapshot)
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(ref lifecycle) = self.lifecycle {
+            for (_, entry) in self.slab.iter() {
+                lifecycle.transition(&entry.key, &entry.value, Transition::Evicted);
+            }
+        }
+        self.slab.clear();
+        self.nodes.clear();
+        self.map.clear();
+        self.t1.clear();
+        self.t2.clear();
+        self.b1.clear();
+        self.b2.clear();
+        if self.adaptive {
+            self.p = self.p_min;
+            self.q = 0;
+        }
+        self.shortterm_count = 0;
+        self.longterm_count = 0;
+        self.weight = 0;
+        if let Some(ref mut budget) = self.budget {
+            budget.set(0);
+        }
+        for group in self.groups.values_mut() {
+            group.stats = GroupStats::default();
+            group.residents.clear();
+            group.resident_weight = 0;
+        }
+        self.remembered_ghosts.clear();
+        if let Some(ref mut ghost_keys) = self.ghost_keys {
+            ghost_keys.clear();
+        }
+        if let Some(ref mut trace) = self.trace {
+            trace.clear();
+        }
+        self.dependents.clear();
+        self.dependencies.clear();
+        self.subscribers.clear();
+        self.leases.clear();
+        self.last_hit = None;
+        if let Some(ref key_filter) = self.key_filter {
+            key_filter.clear();
+        }
+        self.timers.clear();
+        if let Some(ref mut eviction_queue) = self.eviction_queue {
+            eviction_queue.clear();
+        }
+        if let Some(ref mut change_log) = self.change_log {
+            change_log.clear();
+        }
+        self.loaded_count = 0;
+        self.inserted = 0;
+        self.loaded = 0;
+        self.evicted = 0;
+        self.evicted_by_count = 0;
+        self.evicted_by_weight = 0;
+        self.evicted_by_quota = 0;
+        self.exhausted = 0;
+        self.resets = self.resets.wrapping_add(1);
+        self.hits = 0;
+        self.misses = 0;
+        self.expired = 0;
+    }
+
+    pub fn policy_state(&self) -> PolicyState {
+        let ghost_fingerprint = |token| match self
+            .ghost_keys
+            .as_ref()
+            .and_then(|ghost_keys| ghost_keys.key(token))
+        {
+            Some(key) => fingerprint(&key),
+            None => fingerprint(&self.slab[token].key),
+        };
+        let mut recent_ghosts: Vec<u64> =
+            self.b1.iter(&self.nodes).map(ghost_fingerprint).collect();
+        let mut frequent_ghosts: Vec<u64> =
+            self.b2.iter(&self.nodes).map(ghost_fingerprint).collect();
+        for (&fp, &is_longterm) in &self.remembered_ghosts {
+            if is_longterm {
+                frequent_ghosts.push(fp);
+            } else {
+                recent_ghosts.push(fp);
+            }
+        }
+        PolicyState {
+            p: self.p,
+            q: self.q,
+            shortterm_count: self.shortterm_count,
+            longterm_count: self.longterm_count,
+            recent_ghosts,
+            frequent_ghosts,
+        }
+    }
+
+    /// Digest of the resident keys, their list, position from the clock
+    /// hand, reference count and long-term flag. Keys are hashed without the
+    /// cache's hash keys, so replicas reaching the same state get the same
+    /// digest.
+    pub fn state_digest(&self) -> u64 {
+        let mut hasher = SipHasher13::new_with_keys(0, 0);
+        for (list_id, list) in [&self.t1, &self.t2].iter().enumerate() {
+            hasher.write_usize(list_id);
+            hasher.write_usize(list.len());
+            for token in list.iter(&self.nodes) {
+                let node = &self.nodes[token];
+                hasher.write_u64(fingerprint(&self.slab[token].key));
+                hasher.write_u8(node.references());
+                hasher.write_u8(node.is_longterm() as u8);
+            }
+        }
+        hasher.finish()
+    }
+
+    pub fn set_policy_state(&mut self, state: &PolicyState) {
+        self.set_p(state.p);
+        self.set_q(state.q);
+        self.remembered_ghosts.clear();
+        for &fp in state.recent_ghosts.iter().rev().take(self.c) {
+            self.remembered_ghosts.insert(fp, false);
+        }
+        for &fp in state.frequent_ghosts.iter().rev().take(self.c) {
+            self.remembered_ghosts.insert(fp, true);
+        }
+    }
+
+    /// Resident entries with their weight and class, from the hottest to the
+    /// coldest, up to a total weight of `max_weight`. Entries seen more than
+    /// once come first, then referenced ones, then the most recently queued
+    /// ones. The result can be saved, and replayed with `warm_from()`.
+    pub fn export_by_priority(&self, max_weight: usize) -> Vec<(K, usize, EntryClass)>
+    where
+        K: Clone,
+    {
+        let mut tokens: Vec<(Token, usize)> = self
+            .t1
+            .iter(&self.nodes)
+            .enumerate()
+            .chain(self.t2.iter(&self.nodes).enumerate())
+            .filter(|&(_, token)| !self.is_expired(token))
+            .map(|(position, token)| (token, position))
+            .collect();
+        tokens.sort_by_key(|&(token, position)| {
+            let node = &self.nodes[token];
+            Reverse((node.is_longterm(), node.references(), position))
+        });
+        let mut total_weight = 0;
+        let mut exported = Vec::new();
+        for (token, _) in tokens {
+            let entry = &self.slab[token];
+            total_weight += entry.weight;
+            if total_weight > max_weight {
+                break;
+            }
+            let class = if self.nodes[token].is_longterm() {
+                EntryClass::Frequent
+            } else {
+                EntryClass::Recent
+            };
+            exported.push((entry.key.clone(), entry.weight, class));
+        }
+        exported
+    }
+
+    /// Inserts entries exported by `export_by_priority()`, coldest first,
+    /// with values returned by `load`; keys it returns `None` for are
+    /// skipped. Frequent entries are promoted. Returns the number of
+    /// inserted entries.
+    pub fn warm_from<I, F>(&mut self, entries: I, mut load: F) -> usize
+    where
+        I: IntoIterator<Item = (K, usize, EntryClass)>,
+        I::IntoIter: DoubleEndedIterator,
+        F: FnMut(&K) -> Option<V>,
+        K: Clone,
+    {
+        let mut inserted = 0;
+        for (key, _, class) in entries.into_iter().rev() {
+            let value = match load(&key) {
+                Some(value) => value,
+                None => continue,
+            };
+            if class == EntryClass::Frequent {
+                self.insert(key.clone(), value);
+                self.promote(&key);
+            } else {
+                self.insert(key, value);
+            }
+            inserted += 1;
+        }
+        inserted
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.map.get(key) {
+            Some(&token) => !self.is_expired(token),
+            None => false,
+        }
+    }
+
+    /// Hash of `key` under this cache's hash keys.
+    pub fn key_hash<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+    {
+        self.map.hasher().hash_one(key)
+    }
+
+    /// Returns `false` only if no key with this `key_hash()` is stored.
+    /// Always returns `true` when the key filter is disabled.
+    pub fn contains_hash(&self, hash: u64) -> bool {
+        match self.key_filter {
+            Some(ref key_filter) => key_filter.may_contain(hash),
+            None => true,
+        }
+    }
+
+    pub fn is_frequent<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.resident_node(key)
+            .is_some_and(|node| node.is_longterm())
+    }
+
+    pub fn is_recent<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.resident_node(key)
+            .is_some_and(|node| !node.is_longterm())
+    }
+
+    fn resident_node<Q>(&self, key: &Q) -> Option<&Node>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = *self.map.get(key)?;
+        let node = &self.nodes[token];
+        if node.is_history() || self.is_expired(token) {
+            return None;
+        }
+        Some(node)
+    }
+
+    pub fn source<Q>(&self, key: &Q) -> Option<EntrySource>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = *self.map.get(key)?;
+        if self.nodes[token].is_loaded() {
+            Some(EntrySource::Loaded)
+        } else {
+            Some(EntrySource::Inserted)
+        }
+    }
+
+    pub fn loaded_len(&self) -> usize {
+        self.loaded_count
+    }
+
+    fn set_source(&mut self, token: Token, source: EntrySource) {
+        let node = &mut self.nodes[token];
+        let loaded = source == EntrySource::Loaded;
+        if node.is_loaded() != loaded {
+            node.set_loaded(loaded);
+            if loaded {
+                self.loaded_count += 1;
+            } else {
+                self.loaded_count -= 1;
+            }
+        }
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    pub fn expires_in<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = *self.map.get(key)?;
+        let expires_at = self.slab[token].expires_at?;
+        Some(expires_at.saturating_duration_since(Instant::now()))
+    }
+
+    fn jittered_ttl(&mut self) -> Option<Duration> {
+        let ttl = self.ttl?;
+        if self.ttl_jitter == 0 {
+            return Some(ttl);
+        }
+        let nanos = ttl.as_nanos();
+        let spread = nanos * self.ttl_jitter as u128 / 100;
+        let offset = self.rng.next_u64() as u128 % (2 * spread + 1);
+        let nanos = (nanos - spread + offset).min(u64::MAX as u128) as u64;
+        Some(Duration::from_nanos(nanos))
+    }
+
+    pub fn set_ttl<Q>(&mut self, key: &Q, ttl: Duration) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.live_token(key, false) {
+            Some(token) => {
+                self.set_expiration(token, Some(Instant::now() + ttl));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn persist<Q>(&mut self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.live_token(key, false) {
+            Some(token) => {
+                self.set_expiration(token, None);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_expired(&mut self) -> usize {
+        self.remove_expired_batch(usize::MAX)
+    }
+
+    /// Removes a batch of expired entries, and returns how many were removed.
+    /// Meant to be called periodically; the batch grows when few lookups and
+    /// inserts happened since the previous call, and shrinks when many did.
+    ///
+    /// A member of an exceeded `WeightBudget` that is over its fair share
+    /// also evicts entries, as it would on insertion, even if it doesn't
+    /// insert anything itself.
+    pub fn run_maintenance(&mut self) -> usize {
+        if self.budget.is_some() {
+            self.evict_if_overweight(None, 0);
+        }
+        let operations = self.hits + self.misses + self.inserted;
+        let recent = operations.saturating_sub(self.maintenance.operations);
+        let maintenance = &mut self.maintenance;
+        maintenance.operations = operations;
+        maintenance.batch = if recent < maintenance.batch as u64 {
+            min(maintenance.batch.saturating_mul(2), maintenance.max_batch)
+        } else {
+            max(maintenance.batch / 2, maintenance.min_batch)
+        };
+        let batch = maintenance.batch;
+        let removed = self.remove_expired_batch(batch);
+        #[cfg(feature = "registry")]
+        self.publish_stats();
+        removed
+    }
+
+    pub fn maintenance_batch(&self) -> usize {
+        self.maintenance.batch
+    }
+
+    fn remove_expired_batch(&mut self, batch: usize) -> usize {
+        if self.timers.is_empty() {
+            return 0;
+        }
+        let now = Instant::now();
+        let mut count = 0;
+        while let Some(&(expires_at, token)) = self.timers.iter().next() {
+            if expires_at > now || count == batch {
+                break;
+            }
+            self.evict(token, EvictionCause::Expired);
+            self.expired += 1;
+            count += 1;
+        }
+        count
+    }
+
+    #[inline]
+    fn is_expired(&self, token: Token) -> bool {
+        if self.timers.is_empty() {
+            return false;
+        }
+        match self.slab[token].expires_at {
+            Some(expires_at) => expires_at <= Instant::now(),
+            None => false,
+        }
+    }
+
+    fn set_expiration(&mut self, token: Token, expires_at: Option<Instant>) {
+        let entry = &mut self.slab[token];
+        if let Some(previous) = entry.expires_at {
+            self.timers.remove(&(previous, token));
+        }
+        if let Some(expires_at) = expires_at {
+            self.timers.insert((expires_at, token));
+        }
+        entry.expires_at = expires_at;
+    }
+
+    /// Brings every expiration time `by` closer, as if that much time passed.
+    fn advance_time(&mut self, by: Duration) {
+        let now = Instant::now();
+        let timers = mem::take(&mut self.timers);
+        for (expires_at, token) in timers {
+            let expires_at = expires_at.checked_sub(by).unwrap_or(now);
+            self.slab[token].expires_at = Some(expires_at);
+            self.timers.insert((expires_at, token));
+        }
+    }
+
+    #[cfg(feature = "log")]
+    pub fn log_report(&self) {
+        let stats = self.stats();
+        info!(
+            "cart-cache: len={} weight={} p={} q={} recent={} frequent={} hits={} misses={} inserted={} evicted={} expired={}",
+            self.len(),
+            self.weight,
+            self.p,
+            self.q,
+            self.recent_len(),
+            self.frequent_len(),
+            stats.hits,
+            stats.misses,
+            stats.inserted,
+            stats.evicted,
+            stats.expired
+        );
+    }
+
+    #[inline]
+    fn tick(&mut self) {
+        #[cfg(feature = "log")]
+        {
+            if let Some(report_interval) = self.report_interval {
+                self.operations += 1;
+                if self.operations.is_multiple_of(report_interval) {
+                    self.log_report();
+                }
+            }
+        }
+        #[cfg(feature = "registry")]
+        {
+            if self.registration.as_mut().is_some_and(Registration::tick) {
+                self.publish_stats();
+            }
+        }
+    }
+
+    #[cfg(feature = "registry")]
+    fn publish_stats(&self) {
+        if let Some(ref registration) = self.registration {
+            registration.publish(self.stats());
+        }
+    }
+
+    fn peek_token<Q>(&self, key: &Q) -> Option<Token>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = *self.map.get(key)?;
+        if self.is_expired(token) {
+            return None;
+        }
+        Some(token)
+    }
+
+    // Reads are identified by a token and the `key_hash()` of the key that
+    // was found, as the slot may have been reused since.
+    fn apply_buffered_reads(&mut self, reads: &[(Token, u64)], misses: u64) {
+        for &(token, hash) in reads {
+            let found = match self.slab.get(token) {
+                Some(entry) => self.map.hasher().hash_one(&entry.key) == hash,
+                None => false,
+            };
+            if found && !self.nodes[token].is_history() {
+                self.record_hit(token);
+            }
+        }
+        self.misses += misses;
+    }
+
+    // Only reads and refreshes expire entries early, see
+    // `early_expiration()`. Other operations use the strict deadline.
+    #[inline]
+    fn live_token<Q>(&mut self, key: &Q, early: bool) -> Option<Token>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.tick();
+        let token = self.lookup_token(key);
+        if self.trace.is_some() {
+            self.record_trace(key, token);
+        }
+        let token = self.check_live(token?, early)?;
+        self.last_hit = Some(token);
+        Some(token)
+    }
+
+    // Repeated lookups of the same key skip hashing: the last hit is checked
+    // first, by comparing keys. A stale token is harmless, as its slot is
+    // either vacant or holds another key.
+    #[inline]
+    fn lookup_token<Q>(&self, key: &Q) -> Option<Token>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        if let Some(token) = self.last_hit {
+            if let Some(entry) = self.slab.get(token) {
+                if key.equivalent(&entry.key) {
+                    return Some(token);
+                }
+            }
+        }
+        self.map.get(key).cloned()
+    }
+
+    fn record_trace<Q>(&mut self, key: &Q, token: Option<Token>)
+    where
+        Q: Hash + ?Sized,
+    {
+        // Ghost hits are not counted, to compare like for like with the
+        // simulated policies.
+        let hit =
+            token.is_some_and(|token| !self.nodes[token].is_history() && !self.is_expired(token));
+        if let Some(ref mut trace) = self.trace {
+            trace.record(self.map.hasher().hash_one(key), hit);
+        }
+    }
+
+    /// Replays the captured lookups with LRU and Belady's algorithm, at the
+    /// resident capacity of this cache.
+    pub fn trace_report(&self) -> Option<TraceReport> {
+        self.trace.as_ref().map(|trace| trace.report(self.c))
+    }
+
+    #[inline]
+    fn check_live(&mut self, token: Token, early: bool) -> Option<Token> {
+        self.check_live_verified(token, early).unwrap_or(None)
+    }
+
+    // Like `check_live()`, but tells corrupt entries apart.
+    #[inline]
+    fn check_live_verified(
+        &mut self,
+        token: Token,
+        early: bool,
+    ) -> Result<Option<Token>, CorruptEntry> {
+        if self.is_expired(token) {
+            self.evict(token, EvictionCause::Expired);
+            self.expired += 1;
+            return Ok(None);
+        }
+        if early && self.expires_early(token) {
+            return Ok(None);
+        }
+        if (!self.leases.is_empty() || self.nodes[token].is_suspect()) && self.is_suspect(token) {
+            return Ok(None);
+        }
+        if self.is_corrupt(token) {
+            self.evict(token, EvictionCause::Corrupt);
+            return Err(CorruptEntry);
+        }
+        Ok(Some(token))
+    }
+
+    #[inline]
+    fn expires_early(&mut self, token: Token) -> bool {
+        let beta = match self.early_expiration {
+            Some(beta) => beta,
+            None => return false,
+        };
+        let entry = &self.slab[token];
+        let (expires_at, recompute_cost) = match (entry.expires_at, entry.recompute_cost) {
+            (Some(expires_at), Some(recompute_cost)) => (expires_at, recompute_cost),
+            _ => return false,
+        };
+        let remaining = expires_at.saturating_duration_since(Instant::now());
+        let u = 1.0 - (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        recompute_cost.as_secs_f64() * beta * -u.ln() >= remaining.as_secs_f64()
+    }
+
+    pub fn recompute_cost<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = *self.map.get(key)?;
+        self.slab[token].recompute_cost
+    }
+
+    pub fn set_recompute_cost<Q>(&mut self, key: &Q, recompute_cost: Duration) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.map.get(key) {
+            Some(&token) => {
+                self.slab[token].recompute_cost = Some(recompute_cost);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = self.lookup_token(key);
+        if self.trace.is_some() {
+            self.record_trace(key, token);
+        }
+        self.get_found(token)
+    }
+
+    #[inline]
+    fn get_found(&mut self, token: Option<Token>) -> Option<&V> {
+        let token = self.hit_token(token)?;
+        Some(&self.slab[token].value)
+    }
+
+    #[inline]
+    fn hit_token(&mut self, token: Option<Token>) -> Option<Token> {
+        self.hit_token_verified(token).unwrap_or(None)
+    }
+
+    #[inline]
+    fn hit_token_verified(&mut self, token: Option<Token>) -> Result<Option<Token>, CorruptEntry> {
+        self.tick();
+        let live = match token {
+            Some(token) => self.check_live_verified(token, true),
+            None => Ok(None),
+        };
+        match live {
+            Ok(Some(token)) => {
+                #[cfg(feature = "perf_counters")]
+                if !self.nodes[token].is_history() {
+                    self.perf.resident_hits += 1;
+                }
+                self.last_hit = Some(token);
+                self.record_hit(token);
+                Ok(Some(token))
+            }
+            _ => {
+                self.misses += 1;
+                live
+            }
+        }
+    }
+
+    /// Like `get()`, but checks the index, the list counts, the list ends and
+    /// the entry first, and returns an error if the cache is inconsistent,
+    /// e.g. before calling it across FFI.
+    ///
+    /// This is not a guarantee that nothing panics: the lookup itself is the
+    /// regular one, and only the inconsistencies these checks detect are
+    /// turned into errors. Callbacks, such as the weigher, must not panic.
+    pub fn get_checked<Q>(&mut self, key: &Q) -> Result<Option<&V>, &'static str>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.check_consistency()?;
+        let token = self.lookup_token(key);
+        if let Some(token) = token {
+            self.check_token(token)?;
+        }
+        if self.trace.is_some() {
+            self.record_trace(key, token);
+        }
+        let token = self.hit_token(token);
+        let slab = &self.slab;
+        Ok(token
+            .and_then(|token| slab.get(token))
+            .map(|entry| &entry.value))
+    }
+
+    /// Like `insert()`, but runs the checks of `get_checked()` first, and
+    /// returns an error instead of aborting if room for the entry cannot be
+    /// allocated. The same caveats apply.
+    pub fn insert_checked(&mut self, key: K, value: V) -> Result<bool, &'static str>
+    where
+        K: Clone,
+    {
+        self.check_consistency()?;
+        if let Some(&token) = self.map.get(&key) {
+            self.check_token(token)?;
+        }
+        self.try_reserve(1)
+            .map_err(|_| "Cannot allocate the entry")?;
+        Ok(self.insert(key, value))
+    }
+
+    // Constant-time subset of `check_invariants()`.
+    fn check_consistency(&self) -> Result<(), &'static str> {
+        let lists = [&self.t1, &self.t2, &self.b1, &self.b2];
+        if lists.iter().map(|list| list.len()).sum::<usize>() != self.slab.len() + self.ghost_len()
+        {
+            return Err("List lengths do not add up to the number of entries");
+        }
+        if self.map.len() != self.slab.len() {
+            return Err("Index and entries are out of sync");
+        }
+        if self.shortterm_count + self.longterm_count != self.t1.len() + self.t2.len() {
+            return Err("Short-term and long-term counts do not add up");
+        }
+        for list in &lists {
+            if let Some(token) = list.front() {
+                self.check_token(token)?;
+                match self.nodes[token].prev() {
+                    Some(tail) => self.check_token(tail)?,
+                    None => return Err("List is not linked"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_token(&self, token: Token) -> Result<(), &'static str> {
+        if !(self.slab.contains(token) || self.slab.is_held(token)) || token >= self.nodes.len() {
+            return Err("Index points to a missing entry");
+        }
+        Ok(())
+    }
+
+    /// Like `get()`, but tells corrupt entries apart from missing ones when
+    /// checksums are enabled. The corrupt entry is removed.
+    pub fn get_verified<Q>(&mut self, key: &Q) -> Result<Option<&V>, CorruptEntry>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = self.lookup_token(key);
+        if self.trace.is_some() {
+            self.record_trace(key, token);
+        }
+        let token = self.hit_token_verified(token)?;
+        match token {
+            Some(token) => Ok(Some(&self.slab[token].value)),
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    pub fn get_copied<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        V: Copy,
+    {
+        self.get(key).copied()
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = self.get_mut_token(key)?;
+        Some(&mut self.slab[token].value)
+    }
+
+    // Records the lookup of an entry about to be changed in place.
+    fn get_mut_token<Q>(&mut self, key: &Q) -> Option<Token>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.live_token(key, false) {
+            Some(token) => {
+                self.record_hit(token);
+                self.slab[token].checksum = None;
+                Some(token)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn get_if<Q, F>(&mut self, key: &Q, pred: F) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        F: FnOnce(&V) -> bool,
+    {
+        self.get_if_impl(key, pred, false)
+    }
+
+    pub fn get_if_or_remove<Q, F>(&mut self, key: &Q, pred: F) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        F: FnOnce(&V) -> bool,
+    {
+        self.get_if_impl(key, pred, true)
+    }
+
+    fn get_if_impl<Q, F>(&mut self, key: &Q, pred: F, remove_rejected: bool) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        F: FnOnce(&V) -> bool,
+    {
+        let token = match self.live_token(key, true) {
+            Some(token) => token,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        if !pred(&self.slab[token].value) {
+            self.misses += 1;
+            let group = self.slab[token].group;
+            self.record_group_miss(group);
+            if remove_rejected {
+                self.remove_entry(token);
+            }
+            return None;
+        }
+        self.record_hit(token);
+        Some(&self.slab[token].value)
+    }
+
+    /// Removes `key`, whether it is resident or only in the history, and
+    /// returns its value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = *self.map.get(key)?;
+        Some(self.remove_entry(token).value)
+    }
+
+    pub fn remove_many<'a, Q, I>(&mut self, keys: I) -> Vec<(K, V)>
+    where
+        I: IntoIterator<Item = &'a Q>,
+        Q: Hash + Equivalent<K> + ?Sized + 'a,
+    {
+        let mut tokens: Vec<Token> = keys
+            .into_iter()
+            .filter_map(|key| self.map.get(key).cloned())
+            .collect();
+        tokens.sort_unstable();
+        tokens.dedup();
+        let (mut shortterm_count, mut longterm_count) = (0, 0);
+        for &token in &tokens {
+            let node = self.unlink(token);
+            if !node.is_history() {
+                self.log_change(token, false);
+                if node.is_longterm() {
+                    longterm_count += 1;
+                } else {
+                    shortterm_count += 1;
+                }
+            }
+        }
+        self.shortterm_count -= shortterm_count;
+        self.longterm_count -= longterm_count;
+        tokens
+            .into_iter()
+            .map(|token| {
+                let entry = self.drop_entry(token);
+                (entry.key, entry.value)
+            })
+            .collect()
+    }
+
+    fn unlink(&mut self, token: Token) -> Node {
+        let node = self.nodes[token];
+        let list = if node.is_history() {
+            if node.is_longterm() {
+                &mut self.b2
+            } else {
+                &mut self.b1
+            }
+        } else if node.is_in_t2() {
+            &mut self.t2
+        } else {
+            &mut self.t1
+        };
+        list.remove(&mut self.nodes, token);
+        node
+    }
+
+    fn evict(&mut self, token: Token, cause: EvictionCause) {
+        let entry = self.remove_entry(token);
+        self.notify_eviction(entry.key, entry.group, cause);
+    }
+
+    fn notify_eviction(&mut self, key: K, group: usize, cause: EvictionCause) {
+        if let Some(stats) = self.group_stats_mut(group) {
+            stats.evicted += 1;
+        }
+        if let Some(ref listener) = self.evicted_hash_listener {
+            let group = self.grouper.as_ref().map(|_| group);
+            listener(fingerprint(&key), group, cause, self.ctx);
+        }
+        if let Some(ref mut eviction_queue) = self.eviction_queue {
+            eviction_queue.push(key, cause, self.ctx);
+        }
+    }
+
+    /// Removes the next victim and hands it back, e.g. to shed memory under
+    /// pressure, counting it as a capacity eviction. Once nothing is
+    /// resident, history entries are removed instead.
+    ///
+    /// A full cache first sweeps the clock hands like an insertion would.
+    /// The victim is then the one `peek_victim()` returns: it is removed
+    /// with its resident value, without being demoted first.
+    pub fn pop_victim(&mut self) -> Option<(K, V)> {
+        if self.t1.len() + self.t2.len() >= self.c {
+            self.replace_t2();
+            self.replace_t1();
+        }
+        let entry = match self.victim_token() {
+            Some(token) => {
+                self.evicted += 1;
+                self.evicted_by_count += 1;
+                self.remove_entry(token)
+            }
+            None => loop {
+                let token = self.pop_history(None)?;
+                if self.drop_ghost(token).is_none() {
+                    break self.drop_entry(token);
+                }
+            },
+        };
+        Some((entry.key, entry.value))
+    }
+
+    /// Returns the resident key most likely to be demoted by the next
+    /// insertion into a full cache, following the current clock hands.
+    /// Reference counters above one and target adaptation are not simulated.
+    pub fn peek_victim(&self) -> Option<&K> {
+        self.victim_token().map(|token| &self.slab[token].key)
+    }
+
+    fn victim_token(&self) -> Option<Token> {
+        let mut t1_len = self.t1.len();
+        let mut t2_victim = None;
+        for token in self.t2.iter(&self.nodes) {
+            if !self.nodes[token].is_reference() {
+                t2_victim = Some(token);
+                break;
+            }
+            t1_len += 1;
+        }
+        let (mut t1_victim, mut moved_to_t2) = (None, None);
+        for token in self.t1.iter(&self.nodes) {
+            let node = &self.nodes[token];
+            if node.is_reference() {
+                continue;
+            }
+            if node.is_longterm() {
+                t1_len -= 1;
+                moved_to_t2.get_or_insert(token);
+                continue;
+            }
+            t1_victim = Some(token);
+            break;
+        }
+        let victim = if t1_len >= max(1, self.p) {
+            t1_victim.or(t2_victim).or(moved_to_t2)
+        } else {
+            t2_victim.or(moved_to_t2).or(t1_victim)
+        };
+        victim
+            .or_else(|| self.t1.front())
+            .or_else(|| self.t2.front())
+    }
+
+    pub fn pending_evictions(&self) -> usize {
+        match self.eviction_queue {
+            Some(ref eviction_queue) => eviction_queue.len(),
+            None => 0,
+        }
+    }
+
+    pub fn drain_evictions(&mut self) -> Vec<EvictionEvent<K>> {
+        match self.eviction_queue {
+            Some(ref mut eviction_queue) => eviction_queue.drain(),
+            None => Vec::new(),
+        }
+    }
+
+    fn remove_entry(&mut self, token: Token) -> Entry<K, V> {
+        let node = self.unlink(token);
+        if !node.is_history() {
+            self.log_change(token, false);
+            if node.is_longterm() {
+                self.longterm_count -= 1;
+            } else {
+                self.shortterm_count -= 1;
+            }
+        }
+        self.drop_entry(token)
+    }
+
+    fn drop_entry(&mut self, token: Token) -> Entry<K, V> {
+        self.take_entry(token, false)
+    }
+
+    // Removes an entry from the index and from the accounting. Its slot is
+    // freed, unless `hold` is set.
+    fn take_entry(&mut self, token: Token, hold: bool) -> Entry<K, V> {
+        if self.nodes[token].is_loaded() {
+            self.nodes[token].set_loaded(false);
+            self.loaded_count -= 1;
+        }
+        self.track_resident(token, false);
+        let entry = if hold {
+            self.slab.hold(token)
+        } else {
+            self.slab.remove(token)
+        };
+        self.map.remove(&entry.key);
+        if let Some(ref lifecycle) = self.lifecycle {
+            lifecycle.transition(&entry.key, &entry.value, Transition::Evicted);
+        }
+        if let Some(ref key_filter) = self.key_filter {
+            key_filter.remove(self.map.hasher().hash_one(&entry.key));
+        }
+        self.reweigh(entry.group, entry.weight, 0);
+        if let Some(stats) = self.group_stats_mut(entry.group) {
+            stats.len -= 1;
+        }
+        if let Some(expires_at) = entry.expires_at {
+            self.timers.remove(&(expires_at, token));
+        }
+        if !self.dependencies.is_empty() {
+            self.forget_dependencies(&entry.key);
+        }
+        if !self.leases.is_empty() {
+            self.leases.remove(&token);
+        }
+        entry
+    }
+
+    fn forget_dependencies(&mut self, key: &K) {
+        for dependency in self.dependencies.remove(key).unwrap_or_default() {
+            if let Some(dependents) = self.dependents.get_mut(&dependency) {
+                dependents.remove(key);
+                if dependents.is_empty() {
+                    self.dependents.remove(&dependency);
+                }
+            }
+        }
+    }
+
+    /// Inserts an entry that is invalidated along with any of `dependencies`.
+    pub fn insert_with_deps<I>(&mut self, key: K, value: V, dependencies: I) -> bool
+    where
+        K: Clone,
+        I: IntoIterator<Item = K>,
+    {
+        let updated = self.insert(key.clone(), value);
+        self.forget_dependencies(&key);
+        let dependencies: Vec<K> = dependencies.into_iter().collect();
+        for dependency in &dependencies {
+            self.dependents
+                .entry(dependency.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        if !dependencies.is_empty() {
+            self.dependencies.insert(key, dependencies);
+        }
+        updated
+    }
+
+    /// Removes `key` and, transitively, every entry depending on it. Returns
+    /// the number of removed entries.
+    pub fn invalidate<Q>(&mut self, key: &Q) -> usize
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let mut removed = 0;
+        if let Some(&token) = self.map.get(key) {
+            self.remove_entry(token);
+            removed += 1;
+        }
+        // Dependents are unlinked before being visited, so cycles terminate.
+        let mut pending: Vec<K> = self
+            .dependents
+            .remove(key)
+            .map_or_else(Vec::new, |dependents| dependents.into_iter().collect());
+        while let Some(dependent) = pending.pop() {
+            if let Some(&token) = self.map.get(&dependent) {
+                self.remove_entry(token);
+                removed += 1;
+            }
+            if let Some(dependents) = self.dependents.remove(&dependent) {
+                pending.extend(dependents);
+            }
+        }
+        removed
+    }
+
+    fn group_stats_mut(&mut self, group: usize) -> Option<&mut GroupStats> {
+        self.grouper.as_ref()?;
+        Some(&mut self.groups.entry(group).or_default().stats)
+    }
+
+    fn reweigh(&mut self, group: usize, old_weight: usize, new_weight: usize) {
+        self.weight = self.weight - old_weight + new_weight;
+        if let Some(ref mut budget) = self.budget {
+            budget.set(self.weight);
+        }
+        if let Some(stats) = self.group_stats_mut(group) {
+            stats.weight = stats.weight - old_weight + new_weight;
+        }
+    }
+
+    #[inline]
+    fn record_hit(&mut self, token: Token) {
+        self.nodes[token].reference(self.max_references);
+        self.hits += 1;
+        if self.grouper.is_some() {
+            let group = self.slab[token].group;
+            self.groups.entry(group).or_default().stats.hits += 1;
+        }
+    }
+
+    fn record_group_miss(&mut self, group: usize) {
+        if let Some(stats) = self.group_stats_mut(group) {
+            stats.misses += 1;
+        }
+    }
+
+    pub fn group_weight(&self, group: usize) -> usize {
+        self.group_stats(group).weight
+    }
+
+    pub fn group_stats(&self, group: usize) -> GroupStats {
+        self.groups
+            .get(&group)
+            .map_or_else(GroupStats::default, |group| group.stats)
+    }
+
+    // Returns `false` if the weight limit could not be enforced, see
+    // `evict_if_overweight()`.
+    fn enforce_limits(&mut self, token: Token) -> bool {
+        let fits = self.evict_if_overweight(Some(token), 0);
+        if self.grouper.is_none() {
+            return fits;
+        }
+        let group = self.slab[token].group;
+        let max_weight = match self.groups.get(&group).and_then(|group| group.max_weight) {
+            Some(max_weight) => max_weight,
+            None => return fits,
+        };
+        while self.groups[&group].resident_weight > max_weight {
+            match self.quota_victim(group, token) {
+                Some(victim) => {
+                    self.demote_token(victim);
+                    if self.b1.len() + self.b2.len() > self.c {
+                        self.evict_history(Some(victim), EvictionCause::Capacity);
+                    }
+                    self.evicted += 1;
+                    self.evicted_by_quota += 1;
+                }
+                None => break,
+            }
+        }
+        fits
+    }
+
+    // The resident of `group` other than `protected` that the clock hands
+    // reach first, unreferenced entries first. The hands don't move, so
+    // reference bits are left alone.
+    fn quota_victim(&self, group: usize, protected: Token) -> Option<Token> {
+        let (first, second) = if self.t1.len() >= max(1, self.p) {
+            (&self.t1, &self.t2)
+        } else {
+            (&self.t2, &self.t1)
+        };
+        let mut remaining = self.groups[&group].residents.len();
+        let mut referenced = None;
+        for token in first.iter(&self.nodes).chain(second.iter(&self.nodes)) {
+            if remaining == 0 {
+                break;
+            }
+            if self.slab[token].group != group {
+                continue;
+            }
+            remaining -= 1;
+            if token == protected {
+                continue;
+            }
+            if !self.nodes[token].is_reference() {
+                return Some(token);
+            }
+            referenced.get_or_insert(token);
+        }
+        referenced
+    }
+
+    // Follows residents of groups with a quota as they enter and leave the
+    // resident set.
+    fn track_resident(&mut self, token: Token, resident: bool) {
+        if self.grouper.is_none() {
+            return;
+        }
+        let entry = &self.slab[token];
+        let group = match self.groups.get_mut(&entry.group) {
+            Some(group) if group.max_weight.is_some() => group,
+            _ => return,
+        };
+        if resident {
+            if group.residents.insert(token) {
+                group.resident_weight += entry.weight;
+            }
+        } else if group.residents.remove(&token) {
+            group.resident_weight -= entry.weight;
+        }
+    }
+
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        match self.weigher {
+            Some(ref weigher) => weigher(key, value),
+            None => 1,
+        }
+    }
+
+    fn value_checksum(&self, value: &V) -> Option<u64> {
+        self.checksum.as_ref().map(|checksum| checksum(value))
+    }
+
+    fn is_corrupt(&mut self, token: Token) -> bool {
+        let checksum = match self.checksum {
+            Some(ref checksum) => checksum,
+            None => return false,
+        };
+        let entry = &mut self.slab[token];
+        let actual = checksum(&entry.value);
+        match entry.checksum {
+            Some(expected) => actual != expected,
+            None => {
+                entry.checksum = Some(actual);
+                false
+            }
+        }
+    }
+
+    fn pop_history(&mut self, protected: Option<Token>) -> Option<Token> {
+        let from_b1 = self.b1.len() > self.q || self.b2.is_empty();
+        let (list, other) = if from_b1 {
+            (&mut self.b1, &mut self.b2)
+        } else {
+            (&mut self.b2, &mut self.b1)
+        };
+        if protected.is_some() && list.head == protected {
+            if other.head == protected {
+                return None;
+            }
+            return other.pop_front(&mut self.nodes);
+        }
+        list.pop_front(&mut self.nodes)
+    }
+
+    fn evict_history(&mut self, protected: Option<Token>, cause: EvictionCause) -> bool {
+        let token = match self.pop_history(protected) {
+            Some(token) => token,
+            None => return false,
+        };
+        match self.drop_ghost(token) {
+            Some(key) => {
+                let group = self.grouper.as_ref().map_or(0, |grouper| grouper(&key));
+                self.notify_eviction(key, group, cause);
+            }
+            None => {
+                let entry = self.drop_entry(token);
+                self.notify_eviction(entry.key, entry.group, cause);
+            }
+        }
+        true
+    }
+
+    // Frees the slot of an unlinked history entry with an encoded key, and
+    // returns its key.
+    fn drop_ghost(&mut self, token: Token) -> Option<K> {
+        let ghost_keys = self.ghost_keys.as_mut()?;
+        let encoded = ghost_keys.remove(token)?;
+        let key = ghost_keys.decode(&encoded);
+        self.slab.release(token);
+        Some(key)
+    }
+
+    // Drops the entry of a demoted entry, keeping its slot and its node, and
+    // encodes its key.
+    fn encode_ghost(&mut self, token: Token) {
+        let entry = self.take_entry(token, true);
+        if let Some(ref mut ghost_keys) = self.ghost_keys {
+            ghost_keys.insert(token, &entry.key);
+        }
+    }
+
+    // Brings back the entry of a history entry with an encoded key.
+    fn decode_ghost(&mut self, token: Token, key: K, value: V)
+    where
+        K: Clone,
+    {
+        if let Some(ref mut ghost_keys) = self.ghost_keys {
+            ghost_keys.remove(token);
+        }
+        let entry = self.new_entry(key.clone(), value);
+        self.slab.restore(token, entry);
+        self.map.insert(key, token);
+    }
+
+    fn evict_if_full(&mut self, is_history: bool) {
+        let (resident_limit, history_limit) = self.limits();
+        if self.t1.len() + self.t2.len() >= resident_limit {
+            let demoted = self.replace().is_some();
+            if !is_history && self.b1.len() + self.b2.len() > history_limit {
+                self.evict_history(None, EvictionCause::Capacity);
+            }
+            if demoted {
+                self.evicted += 1;
+                self.evicted_by_count += 1;
+            }
+        }
+    }
+
+    // With `Exhausted::Reject`, checks that room can be made for a new
+    // entry, or a promoted history entry, before anything is inserted.
+    // Sweeps the clock and makes room for the weight of the entry, but
+    // doesn't evict anything if the cache is exhausted from the start.
+    fn must_reject(&mut self, token: Option<Token>, key: &K, value: &V) -> bool {
+        let resident_limit = match self.warmup {
+            Some(_) => self.capacity,
+            None => self.c,
+        };
+        if self.t1.len() + self.t2.len() >= resident_limit {
+            self.replace_t2();
+            self.replace_t1();
+            if self.is_exhausted() {
+                self.exhausted += 1;
+                return true;
+            }
+        }
+        if self.max_weight.is_none() && self.budget.is_none() {
+            return false;
+        }
+        let weight = self.weigh(key, value);
+        let incoming = weight.saturating_sub(token.map_or(0, |token| self.slab[token].weight));
+        !self.evict_if_overweight(token, incoming)
+    }
+
+    // Whether the cache would be over its weight limits with `incoming` more.
+    fn is_overweight(&self, incoming: usize) -> bool {
+        self.max_weight
+            .is_some_and(|max_weight| self.weight + incoming > max_weight)
+            || self
+                .budget
+                .as_ref()
+                .is_some_and(|budget| budget.must_evict(incoming))
+    }
+
+    // Returns `false` if the cache is still overweight because no entry
+    // could be demoted.
+    fn evict_if_overweight(&mut self, protected: Option<Token>, incoming: usize) -> bool {
+        while self.is_overweight(incoming) {
+            if self.b1.is_empty() && self.b2.is_empty() && self.replace().is_none() {
+                return false;
+            }
+            if !self.evict_history(protected, EvictionCause::Weight) {
+                break;
+            }
+            self.evicted += 1;
+            self.evicted_by_weight += 1;
+        }
+        true
+    }
+
+    fn insert_new_entry(&mut self, key: K, value: V) -> Token
+    where
+        K: Clone,
+    {
+        let entry = self.new_entry(key.clone(), value);
+        let token = self.slab.insert(entry);
+        if token == self.nodes.len() {
+            self.nodes.push(Node::default());
+        } else {
+            self.nodes[token] = Node::default();
+        }
+        self.t1.push_back(&mut self.nodes, token);
+        self.track_resident(token, true);
+        match self.take_remembered_ghost(&key) {
+            None => self.shortterm_count += 1,
+            Some(was_longterm) => {
+                if was_longterm {
+                    let p = self.p.saturating_sub(1);
+                    self.set_p(p);
+                } else {
+                    let p = self.p + 1;
+                    self.set_p(p);
+                }
+                self.nodes[token].set_longterm(true);
+                self.longterm_count += 1;
+            }
+        }
+        self.map.insert(key, token);
+        self.inserted += 1;
+        token
+    }
+
+    // Builds the entry of a new or promoted key, and accounts for it.
+    fn new_entry(&mut self, key: K, value: V) -> Entry<K, V> {
+        let weight = self.weigh(&key, &value);
+        let checksum = self.value_checksum(&value);
+        let group = match self.grouper {
+            Some(ref grouper) => grouper(&key),
+            None => 0,
+        };
+        self.reweigh(group, 0, weight);
+        if let Some(stats) = self.group_stats_mut(group) {
+            stats.len += 1;
+        }
+        if let Some(ref key_filter) = self.key_filter {
+            key_filter.add(self.map.hasher().hash_one(&key));
+        }
+        Entry {
+            key,
+            value,
+            weight,
+            group,
+            expires_at: None,
+            recompute_cost: None,
+            checksum,
+        }
+    }
+
+    fn take_remembered_ghost(&mut self, key: &K) -> Option<bool> {
+        if self.remembered_ghosts.is_empty() {
+            return None;
+        }
+        self.remembered_ghosts.remove(&fingerprint(key))
+    }
+
+    pub fn is_warming_up(&self) -> bool {
+        self.warmup.is_some()
+    }
+
+    fn advance_warmup(&mut self) {
+        let warmup = match self.warmup {
+            Some(ref mut warmup) => warmup,
+            None => return,
+        };
+        if let Some(ref mut remaining) = warmup.remaining {
+            *remaining -= 1;
+        }
+        let done = warmup.remaining == Some(0)
+            || warmup
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        if done {
+            self.finish_warmup();
+        }
+    }
+
+    fn finish_warmup(&mut self) {
+        self.warmup = None;
+        self.shrink_to_capacity();
+    }
+
+    /// Changes the total number of slots, demoting and evicting entries
+    /// right away if the cache no longer fits. Adaptive target bounds are
+    /// rescaled; static parameters are clamped.
+    pub fn set_capacity(&mut self, capacity: usize) -> Result<(), &'static str> {
+        if capacity == 0 {
+            return Err("Cache length cannot be zero");
+        }
+        if capacity >= NIL as usize {
+            return Err("Cache length is too large");
+        }
+        self.try_reserve(capacity.saturating_sub(self.slab.len()))
+            .map_err(|_| "Cannot allocate the cache")?;
+        let (old_c, c) = (self.c, capacity / 2);
+        self.p_min = (self.p_min * c).checked_div(old_c).unwrap_or(0);
+        self.p_max = (self.p_max * c).checked_div(old_c).unwrap_or(c);
+        self.capacity = capacity;
+        self.c = c;
+        if self.adaptive {
+            self.p = min(max(self.p, self.p_min), self.p_max);
+        } else {
+            self.p = min(self.p, c);
+        }
+        self.q = min(self.q, c);
+        self.shrink_to_capacity();
+        Ok(())
+    }
+
+    /// Preallocates room for `additional` more entries, returning an error
+    /// instead of aborting if the allocation fails.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), CartCacheError> {
+        self.slab
+            .try_reserve(additional)
+            .map_err(|_| CartCacheError::AllocError)?;
+        self.nodes
+            .try_reserve(self.slab.capacity().saturating_sub(self.nodes.len()))
+            .map_err(|_| CartCacheError::AllocError)?;
+        self.map
+            .try_reserve(additional)
+            .map_err(|_| CartCacheError::AllocError)
+    }
+
+    /// Demotes resident entries until `n` new entries can be inserted
+    /// without replacement work, e.g. ahead of a latency-critical burst.
+    /// Returns the number of free resident slots, which is at most the
+    /// resident capacity.
+    pub fn reserve_headroom(&mut self, n: usize) -> usize {
+        let (resident_limit, history_limit) = self.limits();
+        let n = min(n, resident_limit);
+        self.shrink_to(resident_limit - n, history_limit);
+        resident_limit.saturating_sub(self.t1.len() + self.t2.len())
+    }
+
+    fn shrink_to_capacity(&mut self) {
+        let (resident_limit, history_limit) = self.limits();
+        self.shrink_to(resident_limit, history_limit);
+    }
+
+    // Maximum number of resident and history entries.
+    fn limits(&self) -> (usize, usize) {
+        match self.warmup {
+            Some(_) => (self.capacity, 0),
+            None => (self.c, self.c),
+        }
+    }
+
+    // Demotes resident entries, counting them as evictions like insertions
+    // do, then drops history entries, until both fit.
+    fn shrink_to(&mut self, resident_limit: usize, history_limit: usize) {
+        while self.t1.len() + self.t2.len() > resident_limit {
+            let resident = self.t1.len() + self.t2.len();
+            self.replace();
+            if self.t1.len() + self.t2.len() == resident {
+                break;
+            }
+            self.evicted += 1;
+            self.evicted_by_count += 1;
+        }
+        while self.b1.len() + self.b2.len() > history_limit {
+            if !self.evict_history(None, EvictionCause::Capacity) {
+                break;
+            }
+        }
+    }
+
+    fn adaptation_step(&self, step: usize) -> usize {
+        match self.warmup {
+            Some(_) => step * WARMUP_ADAPTATION,
+            None => step,
+        }
+    }
+
+    fn set_p(&mut self, p: usize) {
+        if self.adaptive {
+            self.p = min(max(p, self.p_min), self.p_max);
+        }
+    }
+
+    fn set_q(&mut self, q: usize) {
+        if self.adaptive {
+            self.q = q;
+        }
+    }
+
+    fn promote_from_b1(&mut self, token: Token) {
+        let p = self.p + self.adaptation_step(max(1, self.shortterm_count / self.b1.len()));
+        self.set_p(p);
+        {
+            let node = &mut self.nodes[token];
+            node.set_history(false);
+            node.set_references(0);
+            node.set_longterm(true);
+            self.longterm_count += 1;
+        }
+        self.b1.remove(&mut self.nodes, token);
+        self.t1.push_back(&mut self.nodes, token);
+        self.track_resident(token, true);
+    }
+
+    fn promote_from_b2(&mut self, token: Token) {
+        let t = self.adaptation_step(max(1, self.longterm_count / self.b2.len()));
+        let p = self.p.saturating_sub(t);
+        self.set_p(p);
+        {
+            let node = &mut self.nodes[token];
+            node.set_history(false);
+            node.set_references(0);
+            node.set_longterm(true);
+            self.longterm_count += 1;
+        }
+        self.b2.remove(&mut self.nodes, token);
+        self.t1.push_back(&mut self.nodes, token);
+        self.track_resident(token, true);
+        if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count >= self.c {
+            let q = min(self.q + 1, self.capacity.saturating_sub(self.t1.len()));
+            self.set_q(q);
+        }
+    }
+
+    fn set_value(&mut self, token: Token, value: V) {
+        self.nodes[token].set_suspect(false);
+        self.slab[token].value = value;
+        self.update_weight(token);
+    }
+
+    fn update_weight(&mut self, token: Token) {
+        let (weight, checksum) = {
+            let cached_entry = &self.slab[token];
+            (
+                self.weigh(&cached_entry.key, &cached_entry.value),
+                self.value_checksum(&cached_entry.value),
+            )
+        };
+        self.track_resident(token, false);
+        let (group, old_weight) = {
+            let cached_entry = &mut self.slab[token];
+            let old_weight = cached_entry.weight;
+            cached_entry.weight = weight;
+            cached_entry.checksum = checksum;
+            (cached_entry.group, old_weight)
+        };
+        self.reweigh(group, old_weight, weight);
+        if !self.nodes[token].is_history() {
+            self.track_resident(token, true);
+        }
+    }
+
+    /// Marks a resident entry as long-term, as if it had been found in the
+    /// history. Returns `false` if `key` is not resident.
+    pub fn promote<Q>(&mut self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = match self.peek_token(key) {
+            Some(token) if !self.nodes[token].is_history() => token,
+            _ => return false,
+        };
+        let node = &mut self.nodes[token];
+        if !node.is_longterm() {
+            node.set_longterm(true);
+            self.shortterm_count -= 1;
+            self.longterm_count += 1;
+        }
+        true
+    }
+
+    /// Makes a resident entry the next one to leave the cache, unless the
+    /// target sizes adapt first: it loses its references and moves to the
+    /// clock hand that replacement currently takes victims from, the front
+    /// of `T1` as a short-term entry, or the front of `T2` as a long-term
+    /// one. Returns `false` if `key` is not resident.
+    pub fn demote<Q>(&mut self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = match self.peek_token(key) {
+            Some(token) if !self.nodes[token].is_history() => token,
+            _ => return false,
+        };
+        if self.nodes[token].is_in_t2() {
+            self.t2.remove(&mut self.nodes, token);
+            self.nodes[token].set_in_t2(false);
+        } else {
+            self.t1.remove(&mut self.nodes, token);
+        }
+        let to_t2 = self.t1.len() < max(1, self.p) && !self.t2.is_empty();
+        let node = &mut self.nodes[token];
+        node.set_references(0);
+        if node.is_longterm() != to_t2 {
+            node.set_longterm(to_t2);
+            if to_t2 {
+                self.shortterm_count -= 1;
+                self.longterm_count += 1;
+            } else {
+                self.longterm_count -= 1;
+                self.shortterm_count += 1;
+            }
+        }
+        if to_t2 {
+            node.set_in_t2(true);
+            self.t2.push_front(&mut self.nodes, token);
+        } else {
+            self.t1.push_front(&mut self.nodes, token);
+        }
+        true
+    }
+
+    /// Recomputes the weight of `key` after it was mutated in place, evicting
+    /// other entries if the cache or its group is now over budget.
+    pub fn finish_mutation<Q>(&mut self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = match self.map.get(key) {
+            Some(&token) => token,
+            None => return false,
+        };
+        self.update_weight(token);
+        self.enforce_limits(token);
+        true
+    }
+
+    /// Like `get_mut()`, but the entry is reweighed when the guard is dropped.
+    pub fn get_mut_weighed<Q>(&mut self, key: &Q) -> Option<WeighedMut<'_, K, V, S>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let token = self.get_mut_token(key)?;
+        Some(WeighedMut { cache: self, token })
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool
+    where
+        K: Clone,
+    {
+        let ttl = self.jittered_ttl();
+        self.insert_with_expiration(key, value, ttl)
+    }
+
+    /// Like `insert()`, with an opaque context, such as a request id, passed
+    /// along with the evictions this insert causes to eviction events and
+    /// listeners.
+    pub fn insert_with_ctx(&mut self, key: K, value: V, ctx: u64) -> bool
+    where
+        K: Clone,
+    {
+        self.ctx = Some(ctx);
+        let updated = self.insert(key, value);
+        self.ctx = None;
+        updated
+    }
+
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> bool
+    where
+        K: Clone,
+    {
+        self.insert_with_expiration(key, value, Some(ttl))
+    }
+
+    /// Like `insert()`, but returns the value if it was not stored because
+    /// the key is resident and resident values are kept.
+    pub fn insert_or_return(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Clone,
+    {
+        let ttl = self.jittered_ttl();
+        self.insert_resolved(Cow::Owned(key), value, ttl)
+            .err()
+            .map(|(value, _)| value)
+    }
+
+    /// Like `insert()`, but a borrowed key is only cloned if it is not
+    /// already tracked, either resident or as a ghost.
+    pub fn insert_cow(&mut self, key: Cow<'_, K>, value: V) -> bool
+    where
+        K: Clone,
+    {
+        let ttl = self.jittered_ttl();
+        self.insert_resolved(key, value, ttl)
+            .unwrap_or_else(|(_, updated)| updated)
+    }
+
+    fn insert_with_expiration(&mut self, key: K, value: V, ttl: Option<Duration>) -> bool
+    where
+        K: Clone,
+    {
+        self.insert_resolved(Cow::Owned(key), value, ttl)
+            .unwrap_or_else(|(_, updated)| updated)
+    }
+
+    fn insert_resolved(
+        &mut self,
+        key: Cow<'_, K>,
+        value: V,
+        ttl: Option<Duration>,
+    ) -> Result<bool, (V, bool)>
+    where
+        K: Clone,
+    {
+        let resident = match self.resident_insert {
+            ResidentInsert::Overwrite => None,
+            _ => self
+                .map
+                .get(&*key)
+                .cloned()
+                .filter(|&token| !self.nodes[token].is_history() && !self.is_expired(token)),
+        };
+        let (token, updated) = match resident {
+            Some(token) => {
+                self.nodes[token].reference(self.max_references);
+                match self.resident_insert {
+                    ResidentInsert::Merge(ref mut merge) => {
+                        self.nodes[token].set_suspect(false);
+                        merge.merge(&mut self.slab[token].value, value)
+                    }
+                    _ => return Err((value, true)),
+                }
+                self.update_weight(token);
+                self.enforce_limits(token);
+                (token, true)
+            }
+            None => self
+                .insert_token(key, value)
+                .map_err(|value| (value, false))?,
+        };
+        self.set_source(token, EntrySource::Inserted);
+        self.set_expiration(token, ttl.map(|ttl| Instant::now() + ttl));
+        self.notify_subscribers(token);
+        Ok(updated)
+    }
+
+    /// Returns a receiver that gets a message the next time `key` is inserted
+    /// or loaded, or is disconnected if the cache is cleared or dropped first.
+    pub fn subscribe(&mut self, key: K) -> Receiver<()> {
+        let (sender, receiver) = channel();
+        self.subscribers.entry(key).or_default().push(sender);
+        receiver
+    }
+
+    fn notify_subscribers(&mut self, token: Token) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        if let Some(subscribers) = self.subscribers.remove(&self.slab[token].key) {
+            for subscriber in subscribers {
+                let _ = subscriber.send(());
+            }
+        }
+    }
+
+    pub fn get_or_insert_with<F>(&mut self, key: K, f: F) -> &V
+    where
+        K: Clone,
+        F: FnOnce() -> V,
+    {
+        self.get_or_insert_with_key(key, |_| f())
+    }
+
+    pub fn get_or_insert_with_key<F>(&mut self, key: K, f: F) -> &V
+    where
+        K: Clone,
+        F: FnOnce(&K) -> V,
+    {
+        match self.try_get_or_insert_with_key(key, |key| Ok::<V, Infallible>(f(key))) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    pub fn try_get_or_insert_with<F, E>(&mut self, key: K, f: F) -> Result<&V, E>
+    where
+        K: Clone,
+        F: FnOnce() -> Result<V, E>,
+    {
+        self.try_get_or_insert_with_key(key, |_| f())
+    }
+
+    pub fn try_get_or_insert_with_key<F, E>(&mut self, key: K, f: F) -> Result<&V, E>
+    where
+        K: Clone,
+        F: FnOnce(&K) -> Result<V, E>,
+    {
+        // History entries are loaded again, and promoted by the insertion.
+        let token = match self.live_token(&key, true) {
+            Some(token) if !self.nodes[token].is_history() => {
+                self.record_hit(token);
+                token
+            }
+            _ => {
+                self.misses += 1;
+                if let Some(group) = self.grouper.as_ref().map(|grouper| grouper(&key)) {
+                    self.record_group_miss(group);
+                }
+                let started_at = self.early_expiration.map(|_| Instant::now());
+                let value = f(&key)?;
+                let token = self.load_token(key, value);
+                if let Some(started_at) = started_at {
+                    self.slab[token].recompute_cost = Some(started_at.elapsed());
+                }
+                self.set_source(token, EntrySource::Loaded);
+                self.loaded += 1;
+                let expires_at = self.jittered_ttl().map(|ttl| Instant::now() + ttl);
+                self.set_expiration(token, expires_at);
+                self.notify_subscribers(token);
+                token
+            }
+        };
+        Ok(&self.slab[token].value)
+    }
+
+    // Hands `value` back if the cache is exhausted and rejects new entries.
+    fn insert_token(&mut self, key: Cow<'_, K>, value: V) -> Result<(Token, bool), V>
+    where
+        K: Clone,
+    {
+        let token = match self.prepare_insert(&key) {
+            Ok(token) => return Ok((self.update_resident(token, value), true)),
+            Err(token) => token,
+        };
+        if self.on_exhausted == Exhausted::Reject && self.must_reject(token, &key, &value) {
+            return Err(value);
+        }
+        Ok((self.admit_token(key, value, token), false))
+    }
+
+    // Like `insert_token()`, but never rejects the value.
+    fn load_token(&mut self, key: K, value: V) -> Token
+    where
+        K: Clone,
+    {
+        match self.prepare_insert(&key) {
+            Ok(token) => self.update_resident(token, value),
+            Err(token) => self.admit_token(Cow::Owned(key), value, token),
+        }
+    }
+
+    // Returns the token of a resident key, or else that of a history entry.
+    fn prepare_insert(&mut self, key: &K) -> Result<Token, Option<Token>> {
+        self.tick();
+        self.remove_expired_batch(EXPIRED_PER_INSERT);
+        self.advance_warmup();
+        match self.map.get(key) {
+            Some(&token) if !self.nodes[token].is_history() => Ok(token),
+            Some(&token) => Err(Some(token)),
+            None => Err(self
+                .ghost_keys
+                .as_ref()
+                .and_then(|ghost_keys| ghost_keys.find(key))),
+        }
+    }
+
+    fn update_resident(&mut self, token: Token, value: V) -> Token {
+        self.nodes[token].reference(self.max_references);
+        #[cfg(feature = "perf_counters")]
+        {
+            self.perf.resident_updates += 1;
+        }
+        self.set_value(token, value);
+        self.enforce_limits(token);
+        token
+    }
+
+    fn admit_token(&mut self, key: Cow<'_, K>, value: V, token: Option<Token>) -> Token
+    where
+        K: Clone,
+    {
+        let is_longterm = token.is_some_and(|token| self.nodes[token].is_longterm());
+        self.evict_if_full(token.is_some());
+        let token = match token {
+            None => {
+                #[cfg(feature = "perf_counters")]
+                {
+                    self.perf.new_inserts += 1;
+                }
+                let token = self.insert_new_entry(key.into_owned(), value);
+                self.notify_transition(token, Transition::Admitted);
+                token
+            }
+            Some(token) => {
+                // Promotion accounts for the entry, which must be back first.
+                let value = if self.slab.is_held(token) {
+                    self.decode_ghost(token, key.into_owned(), value);
+                    None
+                } else {
+                    Some(value)
+                };
+                if !is_longterm {
+                    #[cfg(feature = "perf_counters")]
+                    {
+                        self.perf.b1_promotions += 1;
+                    }
+                    self.promote_from_b1(token);
+                } else {
+                    #[cfg(feature = "perf_counters")]
+                    {
+                        self.perf.b2_promotions += 1;
+                    }
+                    self.promote_from_b2(token);
+                }
+                if let Some(value) = value {
+                    self.set_value(token, value);
+                }
+                self.notify_transition(token, Transition::Promoted);
+                token
+            }
+        };
+        self.log_change(token, true);
+        self.enforce_limits(token);
+        token
+    }
+
+    fn replace_t2(&mut self) {
+        while let Some(token) = self.t2.front() {
+            if !self.nodes[token].is_reference() {
+                break;
+            }
+            self.nodes[token].decay_references(self.reference_decrement);
+            if self.nodes[token].is_reference() {
+                #[cfg(feature = "perf_counters")]
+                {
+                    self.perf.t2_sweeps += 1;
+                }
+                self.t2.advance(&self.nodes);
+                continue;
+            }
+            self.t2.pop_front(&mut self.nodes);
+            self.nodes[token].set_in_t2(false);
+            self.t1.push_back(&mut self.nodes, token);
+            if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count >= self.c {
+                let q = min(self.q + 1, self.capacity.saturating_sub(self.t1.len()));
+                self.set_q(q);
+            }
+        }
+    }
+
+    fn replace_t1(&mut self) {
+        loop {
+            let token = match self.t1.front() {
+                None => break,
+                Some(token) => {
+                    let found = &self.nodes[token];
+                    if !(found.is_longterm() || found.is_reference()) {
+                        break;
+                    }
+                    token
+                }
+            };
+            if self.nodes[token].is_reference() {
+                #[cfg(feature = "perf_counters")]
+                {
+                    self.perf.t1_sweeps += 1;
+                }
+                self.t1.advance(&self.nodes);
+                let threshold = self.promotion_threshold(min(self.p + 1, self.b1.len()));
+                let found = &mut self.nodes[token];
+                found.decay_references(self.reference_decrement);
+                if self.t1.len() >= threshold && !found.is_longterm() {
+                    found.set_longterm(true);
+                    self.shortterm_count -= 1;
+                    self.longterm_count += 1;
+                }
+            } else {
+                self.t1.remove(&mut self.nodes, token);
+                self.nodes[token].set_in_t2(true);
+                self.t2.push_back(&mut self.nodes, token);
+                // T1 may exceed c while warming up or shrinking.
+                let q = if self.q > 0 {
+                    max(self.q - 1, self.c.saturating_sub(self.t1.len()))
+                } else {
+                    self.c.saturating_sub(self.t1.len())
+                };
+                self.set_q(q);
+            }
+        }
+    }
+
+    #[inline]
+    fn promotion_threshold(&self, threshold: usize) -> usize {
+        if self.promotion_threshold == 1.0 {
+            threshold
+        } else {
+            (threshold as f64 * self.promotion_threshold).ceil() as usize
+        }
+    }
+
+    fn demote_victim(&mut self) -> Option<Token> {
+        let from_t1 = if self.t1.len() >= max(1, self.p) {
+            true
+        } else if !self.is_exhausted() {
+            false
+        } else {
+            self.exhausted += 1;
+            self.on_exhausted == Exhausted::ForceEvict
+        };
+        let token = if from_t1 {
+            self.t1.front()?
+        } else {
+            self.t2.front()?
+        };
+        self.demote_token(token);
+        Some(token)
+    }
+
+    // Moves a resident entry to the history list matching its class.
+    fn demote_token(&mut self, token: Token) {
+        if self.nodes[token].is_in_t2() {
+            self.t2.remove(&mut self.nodes, token);
+        } else {
+            self.t1.remove(&mut self.nodes, token);
+        }
+        let longterm = {
+            let demoted = &mut self.nodes[token];
+            demoted.set_history(true);
+            demoted.set_in_t2(false);
+            demoted.is_longterm()
+        };
+        if longterm {
+            self.longterm_count -= 1;
+            self.b2.push_back(&mut self.nodes, token);
+        } else {
+            self.shortterm_count -= 1;
+            self.b1.push_back(&mut self.nodes, token);
+        }
+        self.track_resident(token, false);
+        self.log_change(token, false);
+        self.map_demoted(token);
+        self.notify_transition(token, Transition::Demoted);
+        if self.ghost_keys.is_some() {
+            self.encode_ghost(token);
+        }
+    }
+
+    // Whether T1 is below its target size and T2 is empty, so that
+    // `demote_victim()` has nothing to demote.
+    #[inline]
+    fn is_exhausted(&self) -> bool {
+        self.t2.is_empty() && !self.t1.is_empty() && self.t1.len() < max(1, self.p)
+    }
+
+    #[inline]
+    fn notify_transition(&self, token: Token, transition: Transition) {
+        if let Some(ref lifecycle) = self.lifecycle {
+            let entry = &self.slab[token];
+            lifecycle.transition(&entry.key, &entry.value, transition);
+        }
+    }
+
+    #[inline]
+    fn log_change(&mut self, token: Token, inserted: bool) {
+        if let Some(ref mut change_log) = self.change_log {
+            let hash = fingerprint(&self.slab[token].key);
+            change_log.push(if inserted {
+                KeyChange::Inserted(hash)
+            } else {
+                KeyChange::Evicted(hash)
+            });
+        }
+    }
+
+    /// Changes to the resident set after the `counter` returned by a previous
+    /// call, along with the current counter, so that peers can warm their
+    /// caches with the same keys. Returns `None` if the change log is
+    /// disabled, or if some changes were already dropped from it.
+    pub fn changes_since(&self, counter: u64) -> Option<(Vec<KeyChange>, u64)> {
+        let change_log = self.change_log.as_ref()?;
+        Some((change_log.since(counter)?, change_log.counter()))
+    }
+
+    fn map_demoted(&mut self, token: Token) {
+        let map_on_evict = match self.map_on_evict {
+            Some(ref map_on_evict) => map_on_evict.clone(),
+            None => return,
+        };
+        let entry = &mut self.slab[token];
+        map_on_evict(&entry.key, &mut entry.value);
+        let (group, weight) = (entry.group, entry.weight);
+        let entry = &self.slab[token];
+        let new_weight = self.weigh(&entry.key, &entry.value);
+        let checksum = self.value_checksum(&entry.value);
+        self.reweigh(group, weight, new_weight);
+        let entry = &mut self.slab[token];
+        entry.weight = new_weight;
+        entry.checksum = checksum;
+    }
+
+    fn replace(&mut self) -> Option<Token> {
+        self.replace_t2();
+        self.replace_t1();
+        self.demote_victim()
+    }
+}
+
+pub struct Scan<'a, K, V, S = KeyedState>
+where
+    K: Eq + Hash,
+{
+    cache: &'a CartCache<K, V, S>,
+    now: Instant,
+}
+
+impl<'a, K: Eq + Hash, V, S> Scan<'a, K, V, S> {
+    fn entries(&self, list: &'a XLinkedList) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        let (cache, now) = (self.cache, self.now);
+        list.iter(&cache.nodes).filter_map(move |token| {
+            let entry = &cache.slab[token];
+            if entry.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                return None;
+            }
+            Some((&entry.key, &entry.value))
+        })
+    }
+
+    /// Entries seen once recently, from the clock hand onwards.
+    pub fn recent(&self) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        self.entries(&self.cache.t1)
+    }
+
+    /// Entries seen at least twice recently, from the clock hand onwards.
+    pub fn frequent(&self) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        self.entries(&self.cache.t2)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        self.recent().chain(self.frequent())
+    }
+}
+
+pub struct WeighedMut<'a, K, V, S = KeyedState>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    cache: &'a mut CartCache<K, V, S>,
+    token: Token,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> Deref for WeighedMut<'a, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.cache.slab[self.token].value
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> DerefMut for WeighedMut<'a, K, V, S> {
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self.cache.slab[self.token].value
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> Drop for WeighedMut<'a, K, V, S> {
+    fn drop(&mut self) {
+        self.cache.update_weight(self.token);
+        self.cache.enforce_limits(self.token);
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: ?Sized, S: BuildHasher> CartCache<K, Box<T>, S> {
+    pub fn insert_boxed<U>(&mut self, key: K, value: U) -> bool
+    where
+        U: Into<Box<T>>,
+    {
+        self.insert(key, value.into())
+    }
+
+    pub fn get_unboxed<Q>(&mut self, key: &Q) -> Option<&T>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).map(|value| &**value)
+    }
+
+    pub fn get_unboxed_mut<Q>(&mut self, key: &Q) -> Option<&mut T>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get_mut(key).map(|value| &mut **value)
+    }
+
+    /// Returns a pointer to the boxed value, for FFI consumers holding on to
+    /// it across calls. Boxed values do not move when the cache grows, so
+    /// the pointer stays valid until the entry is overwritten, removed,
+    /// evicted, or mapped by `map_on_evict()`, whichever comes first.
+    pub fn get_unboxed_ptr<Q>(&mut self, key: &Q) -> Option<NonNull<T>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get_mut(key).map(|value| NonNull::from(&mut **value))
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: ?Sized, S: BuildHasher> CartCache<K, Arc<T>, S> {
+    pub fn insert_arc<U>(&mut self, key: K, value: U) -> bool
+    where
+        U: Into<Arc<T>>,
+    {
+        self.insert(key, value.into())
+    }
+
+    pub fn get_arc<Q>(&mut self, key: &Q) -> Option<Arc<T>>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).cloned()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<K: Eq + Hash + Clone> CartCache<K, Bytes> {
+    /// Cache of byte buffers weighed by length, holding at most `capacity`
+    /// entries and `max_bytes` bytes.
+    pub fn with_max_bytes(capacity: usize, max_bytes: usize) -> Result<Self, &'static str> {
+        CartCacheBuilder::new(capacity)
+            .max_weight(max_bytes)
+            .weigh_by_len()
+            .build()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<K: Eq + Hash + Clone, S: BuildHasher> CartCache<K, Bytes, S> {
+    /// Returns a handle on the buffer, without copying it.
+    pub fn get_bytes<Q>(&mut self, key: &Q) -> Option<Bytes>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.get(key).cloned()
+    }
+}
+
+/// Deep copy of the entries, of the replacement state, of the random number
+/// generator and of the merge closure. Other closures are shared with the
+/// original; the clone gets no subscribers, eviction listeners or lifecycle
+/// handler. The clone is not a member of the `WeightBudget` of the original
+/// unless it joins it with `set_shared_budget()`.
+impl<K: Eq + Hash + Clone, V: Clone, S: BuildHasher + Clone> Clone for CartCache<K, V, S> {
+    fn clone(&self) -> Self {
+        CartCache {
+            slab: self.slab.clone(),
+            nodes: self.nodes.clone(),
+            map: self.map.clone(),
+            t1: self.t1.clone(),
+            t2: self.t2.clone(),
+            b1: self.b1.clone(),
+            b2: self.b2.clone(),
+            c: self.c,
+            capacity: self.capacity,
+            p: self.p,
+            p_min: self.p_min,
+            p_max: self.p_max,
+            q: self.q,
+            adaptive: self.adaptive,
+            max_references: self.max_references,
+            reference_decrement: self.reference_decrement,
+            promotion_threshold: self.promotion_threshold,
+            shortterm_count: self.shortterm_count,
+            longterm_count: self.longterm_count,
+            max_weight: self.max_weight,
+            budget: None,
+            weigher: self.weigher.clone(),
+            sizer: self.sizer,
+            checksum: self.checksum.clone(),
+            map_on_evict: self.map_on_evict.clone(),
+            weight: self.weight,
+            grouper: self.grouper.clone(),
+            groups: self.groups.clone(),
+            remembered_ghosts: self.remembered_ghosts.clone(),
+            ghost_keys: self.ghost_keys.clone(),
+            dependents: self.dependents.clone(),
+            dependencies: self.dependencies.clone(),
+            subscribers: HashMap::new(),
+            leases: self.leases.clone(),
+            next_lease: self.next_lease,
+            last_hit: self.last_hit,
+            ctx: None,
+            key_filter: self
+                .key_filter
+                .as_ref()
+                .map(|key_filter| Arc::new(KeyFilter::clone(key_filter))),
+            eviction_queue: self.eviction_queue.clone(),
+            evicted_hash_listener: None,
+            lifecycle: None,
+            change_log: self.change_log.clone(),
+            resident_insert: self.resident_insert.clone(),
+            on_exhausted: self.on_exhausted,
+            trace: self.trace.clone(),
+            warmup: self.warmup,
+            maintenance: self.maintenance,
+            ttl: self.ttl,
+            ttl_jitter: self.ttl_jitter,
+            early_expiration: self.early_expiration,
+            rng: self.rng.clone_rng(),
+            timers: self.timers.clone(),
+            loaded_count: self.loaded_count,
+            inserted: self.inserted,
+            loaded: self.loaded,
+            evicted: self.evicted,
+            evicted_by_count: self.evicted_by_count,
+            evicted_by_weight: self.evicted_by_weight,
+            evicted_by_quota: self.evicted_by_quota,
+            exhausted: self.exhausted,
+            resets: self.resets,
+            hits: self.hits,
+            misses: self.misses,
+            expired: self.expired,
+            #[cfg(feature = "log")]
+            report_interval: self.report_interval,
+            #[cfg(feature = "log")]
+            operations: self.operations,
+            #[cfg(feature = "perf_counters")]
+            perf: self.perf,
+            #[cfg(feature = "registry")]
+            registration: None,
+        }
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for CartCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn from(map: HashMap<K, V, S>) -> Self {
+        let capacity = max(1, map.len()) * 2;
+        CartCache::from_map(map, capacity).expect("Capacity cannot be zero")
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> From<CartCache<K, V, S>> for HashMap<K, V> {
+    fn from(cache: CartCache<K, V, S>) -> Self {
+        cache.into_map()
+    }
+}
+
+trait XLinkedNode {
+    fn prev(&self) -> Option<Token>;
+    fn next(&self) -> Option<Token>;
+    fn set_prev(&mut self, prev: Option<Token>);
+    fn set_next(&mut self, next: Option<Token>);
+}
+
+impl XLinkedNode for Node {
+    #[inline]
+    fn prev(&self) -> Option<Token> {
+        unlink_index(self.prev)
+    }
+
+    #[inline]
+    fn next(&self) -> Option<Token> {
+        unlink_index(self.next)
+    }
+
+    #[inline]
+    fn set_prev(&mut self, prev: Option<Token>) {
+        self.prev = link(prev);
+    }
+
+    #[inline]
+    fn set_next(&mut self, next: Option<Token>) {
+        self.next = link(next);
+    }
+}
+
+#[derive(Clone)]
+struct XLinkedList {
+    head: Option<Token>,
+    len: usize,
+}
+
+impl XLinkedList {
+    fn new() -> Self {
+        XLinkedList { head: None, len: 0 }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.len = 0;
+        self.head = None;
+    }
+
+    #[inline]
+    fn front(&self) -> Option<Token> {
+        self.head
+    }
+
+    #[inline]
+    fn advance(&mut self, nodes: &[Node]) {
+        if let Some(head_token) = self.head {
+            self.head = nodes[head_token].next();
+        }
+    }
+
+    fn remove(&mut self, nodes: &mut [Node], token: Token) {
+        let (prev_token, next_token) = {
+            let elt = &mut nodes[token];
+            let (prev_token, next_token) = match (elt.prev(), elt.next()) {
+                (Some(prev_token), Some(next_token)) => (prev_token, next_token),
+                _ => return,
+            };
+            elt.set_prev(None);
+            elt.set_next(None);
+            (prev_token, next_token)
+        };
+        self.len -= 1;
+        if self.len == 0 {
+            self.head = None;
+            return;
+        }
+        nodes[prev_token].set_next(Some(next_token));
+        nodes[next_token].set_prev(Some(prev_token));
+        if self.head == Some(token) {
+            self.head = Some(next_token);
+        }
+    }
+
+    fn push_back(&mut self, nodes: &mut [Node], token: Token) {
+        match self.head {
+            None => {
+                let elt = &mut nodes[token];
+                elt.set_prev(Some(token));
+                elt.set_next(Some(token));
+                self.head = Some(token);
+            }
+            Some(head_token) => {
+                let tail_token = nodes[head_token].prev().unwrap_or(head_token);
+                {
+                    let elt = &mut nodes[token];
+                    elt.set_prev(Some(tail_token));
+                    elt.set_next(Some(head_token));
+                }
+                nodes[tail_token].set_next(Some(token));
+                nodes[head_token].set_prev(Some(token));
+            }
+        }
+        self.len += 1;
+    }
+
+    fn push_front(&mut self, nodes: &mut [Node], token: Token) {
+        self.push_back(nodes, token);
+        self.head = Some(token);
+    }
+
+    fn pop_front(&mut self, nodes: &mut [Node]) -> Option<Token> {
+        let head_token = self.head;
+        if let Some(head_token) = head_token {
+            self.remove(nodes, head_token);
+        }
+        head_token
+    }
+
+    fn iter<'a>(&self, nodes: &'a [Node]) -> XLinkedListIter<'a> {
+        XLinkedListIter {
+            nodes,
+            next: self.head,
+            remaining: self.len,
+        }
+    }
+}
+
+struct XLinkedListIter<'a> {
+    nodes: &'a [Node],
+    next: Option<Token>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for XLinkedListIter<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let token = self.next?;
+        self.next = self.nodes[token].next();
+        self.remaining -= 1;
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+    use self::rand::prelude::*;
+    use crate::{
+        fingerprint, CacheRng, CartCache, CartCacheBuilder, CartCacheError, CorruptEntry,
+        EntryClass, EntrySource, Equivalent, EvictionCause, Exhausted, HeapSize, KeyChange,
+        NotificationMode, SplitMix64, Transition, EXPIRED_PER_INSERT,
+    };
+    use std::collections::hash_map::RandomState;
+    use std::collections::HashMap;
+    use std::hash::BuildHasher;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn random_inserts() {
+        let count = 1_000_000;
+        let mut cached: u64 = 0;
+        let mut cache: CartCache<u8, u8> = CartCache::new(128).unwrap();
+        let mut rng = thread_rng();
+
+        for _ in 0..count {
+            let key: u8 = rng.gen();
+            cache.insert(key, key);
+            let key: u8 = rng.gen();
+            if cache.get(&key).is_some() {
+                cached += 1;
+            }
+        }
+        assert!(cached > count / 3);
+    }
+
+    #[test]
+    fn get_or_insert_with_key() {
+        let mut cache: CartCache<String, usize> = CartCache::new(16).unwrap();
+        assert_eq!(
+            *cache.get_or_insert_with_key("abc".to_string(), |k| k.len()),
+            3
+        );
+        assert_eq!(*cache.get_or_insert_with("abc".to_string(), || 0), 3);
+        assert_eq!(cache.inserted(), 1);
+
+        let mut cache: CartCache<u32, u32> = CartCache::new(4).unwrap();
+        for i in 0..3 {
+            cache.insert(i, i);
+        }
+        assert!(cache.nodes[cache.map[&0]].is_history());
+        let p = cache.p;
+        assert_eq!(*cache.get_or_insert_with(0, || 10), 10);
+        assert!(!cache.nodes[cache.map[&0]].is_history());
+        assert!(cache.p > p);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (0, 1));
+    }
+
+    #[test]
+    fn policy_state_roundtrip() {
+        let mut cache: CartCache<u32, u32> =
+            CartCacheBuilder::new(16).hash_keys(1, 2).build().unwrap();
+        for i in 0..64 {
+            cache.insert(i % 20, i);
+            cache.get(&(i % 5));
+        }
+        let state = cache.policy_state();
+        assert!(!state.recent_ghosts.is_empty() || !state.frequent_ghosts.is_empty());
+        let mut restored: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        restored.set_policy_state(&state);
+        assert_eq!(restored.policy_state().p, state.p);
+        for i in 0..20 {
+            restored.insert(i, i);
+        }
+        assert!(restored.frequent_len() > 0);
+    }
+
+    #[test]
+    fn get_if() {
+        let mut cache: CartCache<&str, u32> = CartCache::new(16).unwrap();
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get_if("a", |&v| v == 1), Some(&1));
+        assert_eq!(cache.get_if("a", |&v| v == 2), None);
+        assert!(cache.contains_key("a"));
+        assert_eq!(cache.get_if_or_remove("b", |&v| v == 1), None);
+        assert!(!cache.contains_key("b"));
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.recent_len(), 1);
+    }
+
+    #[test]
+    fn ttl() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(64)
+            .ttl(Duration::from_secs(20))
+            .build()
+            .unwrap();
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert_with_ttl(3, 3, Duration::from_secs(60));
+        assert!(cache.persist(&1));
+        assert!(cache.set_ttl(&3, Duration::from_secs(1)));
+        cache.advance_time(Duration::from_secs(30));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+        assert!(!cache.contains_key(&3));
+        assert_eq!(cache.remove_expired(), 1);
+        assert_eq!(cache.stats().expired, 2);
+        assert_eq!(cache.len(), 1);
+        for i in 10..20 {
+            cache.insert(i, i);
+        }
+        cache.advance_time(Duration::from_secs(30));
+        cache.insert(20, 20);
+        assert_eq!(cache.stats().expired, 2 + EXPIRED_PER_INSERT as u64);
+        assert_eq!(cache.remove_expired(), 10 - EXPIRED_PER_INSERT);
+    }
+
+    #[test]
+    fn lifecycle() {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let mut cache: CartCache<u32, u32> = {
+            let transitions = transitions.clone();
+            CartCacheBuilder::new(8)
+                .lifecycle(move |&key: &u32, _: &u32, transition| {
+                    transitions.lock().unwrap().push((key, transition))
+                })
+                .build()
+                .unwrap()
+        };
+        for i in 0..200 {
+            let key = (i * 7) % 23;
+            cache.insert(key, i);
+            if i % 3 == 0 {
+                cache.get(&(key / 2));
+            }
+        }
+        cache.remove(&5);
+        let reported = transitions.lock().unwrap().len();
+        cache.clone().clear();
+        assert_eq!(transitions.lock().unwrap().len(), reported);
+        cache.clear();
+        let mut states = HashMap::new();
+        for (key, transition) in transitions.lock().unwrap().drain(..) {
+            let state = states.insert(key, transition);
+            let valid = match transition {
+                Transition::Admitted => matches!(state, None | Some(Transition::Evicted)),
+                Transition::Demoted => {
+                    matches!(state, Some(Transition::Admitted | Transition::Promoted))
+                }
+                Transition::Promoted => state == Some(Transition::Demoted),
+                Transition::Evicted => state.is_some_and(|state| state != Transition::Evicted),
+            };
+            assert!(valid, "{:?} after {:?}", transition, state);
+        }
+        assert!(states.values().all(|&state| state == Transition::Evicted));
+    }
+
+    #[test]
+    fn exhausted() {
+        let build = |on_exhausted| {
+            let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(16)
+                .max_weight(10)
+                .weigher(|_, &value| value as usize)
+                .static_parameters(16, 0)
+                .on_exhausted(on_exhausted)
+                .build()
+                .unwrap();
+            cache.insert(0, 6);
+            cache.insert(1, 6);
+            cache
+        };
+        let cache = build(Exhausted::OverProvision);
+        assert_eq!(cache.weight(), 12);
+        assert_eq!(cache.stats().exhausted, 1);
+        let mut cache = build(Exhausted::ForceEvict);
+        assert_eq!(cache.weight(), 6);
+        assert_eq!(cache.get(&1), Some(&6));
+        assert_eq!(cache.stats().exhausted, 1);
+        let mut cache = build(Exhausted::Reject);
+        assert_eq!(cache.weight(), 6);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.insert_or_return(2, 5), Some(5));
+        assert_eq!(cache.get(&0), Some(&6));
+        assert_eq!(cache.stats().evicted, 0);
+
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let mut cache: CartCache<u32, u32> = {
+            let transitions = transitions.clone();
+            CartCacheBuilder::new(16)
+                .max_weight(10)
+                .weigher(|_, &value| value as usize)
+                .static_parameters(16, 0)
+                .on_exhausted(Exhausted::Reject)
+                .lifecycle(move |&key: &u32, _: &u32, transition| {
+                    transitions.lock().unwrap().push((key, transition))
+                })
+                .build()
+                .unwrap()
+        };
+        cache.insert(0, 6);
+        assert_eq!(cache.insert_or_return(1, 6), Some(6));
+        let stats = cache.stats();
+        assert_eq!((stats.exhausted, stats.evicted), (1, 0));
+        assert_eq!(*transitions.lock().unwrap(), [(0, Transition::Admitted)]);
+    }
+
+    #[test]
+    fn pop_victim() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(4).unwrap();
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(&0);
+        let evicted = cache.stats().evicted_by_count;
+        let victim = *cache.peek_victim().unwrap();
+        assert_eq!(cache.pop_victim(), Some((victim, victim * 10)));
+        assert!(!cache.contains_key(&victim));
+        assert_eq!(cache.len(), 3);
+        let (p, q) = (cache.p, cache.q);
+        let victim = *cache.peek_victim().unwrap();
+        assert_eq!(cache.pop_victim().map(|(key, _)| key), Some(victim));
+        assert_eq!((cache.p, cache.q, cache.stats().exhausted), (p, q, 0));
+        assert_eq!(cache.stats().evicted_by_count, evicted + 2);
+        while cache.pop_victim().is_some() {}
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.recent_len() + cache.frequent_len(), 0);
+
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(4)
+            .map_on_evict(|_, value| *value = 0)
+            .lifecycle({
+                let transitions = transitions.clone();
+                move |&key: &u32, _: &u32, transition| {
+                    transitions.lock().unwrap().push((key, transition))
+                }
+            })
+            .build()
+            .unwrap();
+        cache.insert(0, 10);
+        cache.insert(1, 11);
+        transitions.lock().unwrap().clear();
+        let victim = *cache.peek_victim().unwrap();
+        assert_eq!(cache.pop_victim(), Some((victim, victim + 10)));
+        assert_eq!(
+            *transitions.lock().unwrap(),
+            [(victim, Transition::Evicted)]
+        );
+        assert_eq!(cache.stats().evicted, 1);
+    }
+
+    #[test]
+    fn remove() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(4).unwrap();
+        for i in 0..6 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(&5);
+        let len = cache.len();
+        assert_eq!(cache.remove(&5), Some(50));
+        assert_eq!(cache.remove(&5), None);
+        assert!(!cache.contains_key(&5));
+        assert_eq!(cache.recent_len() + cache.frequent_len(), 3);
+        assert_eq!(cache.len(), len - 1);
+        cache.insert(7, 71);
+        assert_eq!(cache.get(&7), Some(&71));
+    }
+
+    #[test]
+    fn remove_many() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(64).unwrap();
+        for i in 0..32 {
+            cache.insert(i, i * 10);
+        }
+        let mut removed = cache.remove_many(&[3, 5, 5, 100]);
+        removed.sort();
+        assert_eq!(removed, vec![(3, 30), (5, 50)]);
+        assert!(!cache.contains_key(&3));
+        assert_eq!(cache.len(), 30);
+        assert_eq!(cache.recent_len() + cache.frequent_len(), 30);
+    }
+
+    #[test]
+    fn memoize() {
+        let mut cache: CartCache<u32, u64> = CartCache::new(16).unwrap();
+        let mut calls = 0;
+        for _ in 0..3 {
+            let value = *memoize!(cache, 10, {
+                calls += 1;
+                (1..=10).product()
+            });
+            assert_eq!(value, 3628800);
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(cache.source(&10), Some(EntrySource::Loaded));
+        assert_eq!(cache.loaded_len(), 1);
+        cache.insert(10, 0);
+        assert_eq!(cache.source(&10), Some(EntrySource::Inserted));
+        assert_eq!(cache.loaded_len(), 0);
+        assert_eq!(cache.stats().loaded, 1);
+        let failed: Result<&u64, &str> = try_memoize!(cache, 11, Err("unavailable"));
+        assert!(failed.is_err());
+        assert!(!cache.contains_key(&11));
+        assert_eq!(try_memoize!(cache, 11, Ok::<u64, ()>(11)), Ok(&11));
+    }
+
+    #[test]
+    fn target_bounds() {
+        assert!(CartCacheBuilder::<u32, u32>::new(16)
+            .target_bounds(0.6, 0.4)
+            .build()
+            .is_err());
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(100)
+            .target_bounds(0.2, 0.4)
+            .build()
+            .unwrap();
+        for i in 0..10_000 {
+            cache.insert(i % 97, i);
+            cache.insert(i % 13, i);
+            assert!((10..=20).contains(&cache.policy_state().p));
+        }
+    }
+
+    #[test]
+    fn reference_counter() {
+        assert!(CartCacheBuilder::<u32, u32>::new(8)
+            .reference_counter(8, 1)
+            .build()
+            .is_err());
+        let references = |max_references| {
+            let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(8)
+                .reference_counter(max_references, 1)
+                .build()
+                .unwrap();
+            cache.insert(0, 0);
+            for _ in 0..5 {
+                cache.get(&0);
+            }
+            let token = cache.map[&0];
+            let before = cache.nodes[token].references();
+            for i in 1..6 {
+                cache.insert(i, i);
+            }
+            (before, cache.nodes[token].references())
+        };
+        assert_eq!(references(1), (1, 0));
+        assert_eq!(references(3), (3, 2));
+    }
+
+    #[test]
+    fn peek_victim() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        assert_eq!(cache.peek_victim(), None);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        cache.get(&0);
+        assert_eq!(cache.peek_victim(), Some(&1));
+        cache.insert(4, 4);
+        assert!(!cache.is_recent(&1) && !cache.is_frequent(&1));
+        for i in 5..40 {
+            let victim = *cache.peek_victim().unwrap();
+            cache.insert(i, i);
+            assert!(!cache.is_recent(&victim) && !cache.is_frequent(&victim));
+            cache.get(&(i - 1));
+        }
+    }
+
+    #[test]
+    fn reweigh_after_mutation() {
+        let mut cache: CartCache<u32, Vec<u8>> = CartCacheBuilder::new(16)
+            .max_weight(10)
+            .weigher(|_, value: &Vec<u8>| value.len())
+            .build()
+            .unwrap();
+        cache.insert(0, vec![0; 4]);
+        cache.insert(1, vec![0; 4]);
+        cache.get_mut(&1).unwrap().push(0);
+        assert_eq!(cache.weight(), 8);
+        assert!(cache.finish_mutation(&1));
+        assert_eq!(cache.weight(), 9);
+        cache.get_mut_weighed(&1).unwrap().extend([0; 4]);
+        assert_eq!(cache.weight(), 9);
+        assert!(!cache.contains_key(&0));
+    }
+
+    #[test]
+    fn ttl_jitter() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(256)
+            .ttl(Duration::from_secs(100))
+            .ttl_jitter(20)
+            .rng(SplitMix64::with_seed(42))
+            .build()
+            .unwrap();
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let expirations: Vec<Duration> = (0..100).filter_map(|i| cache.expires_in(&i)).collect();
+        assert!(expirations
+            .iter()
+            .all(|&expires_in| expires_in <= Duration::from_secs(120)
+                && expires_in >= Duration::from_secs(79)));
+        assert!(expirations
+            .iter()
+            .any(|&expires_in| expires_in < Duration::from_secs(95)));
+        assert!(expirations
+            .iter()
+            .any(|&expires_in| expires_in > Duration::from_secs(105)));
+
+        #[derive(Clone)]
+        struct Lowest;
+        impl CacheRng for Lowest {
+            fn next_u64(&mut self) -> u64 {
+                0
+            }
+        }
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .ttl(Duration::from_secs(100))
+            .ttl_jitter(20)
+            .rng(Lowest)
+            .build()
+            .unwrap();
+        cache.insert(0, 0);
+        let expires_in = cache.expires_in(&0).unwrap();
+        assert!(expires_in <= Duration::from_secs(80) && expires_in > Duration::from_secs(79));
+    }
+
+    #[test]
+    fn early_expiration() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .ttl(Duration::from_secs(1))
+            .early_expiration(1.0)
+            .build()
+            .unwrap();
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+        assert!((0..50).all(|_| cache.get(&0).is_some()));
+        assert!(cache.set_recompute_cost(&1, Duration::from_secs(10)));
+        assert!((0..50).any(|_| cache.get(&1).is_none()));
+        assert!((0..50).all(|_| cache.get_mut(&1).is_some()));
+        assert!((0..50).all(|_| cache.set_ttl(&1, Duration::from_secs(1))));
+        assert!(cache.persist(&1));
+        cache.get_or_insert_with(2, || 2);
+        assert!(cache.recompute_cost(&2).is_some());
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut cache: CartCache<&str, u32> = CartCache::new(32).unwrap();
+        cache.insert("fragment", 0);
+        cache.insert("unrelated", 0);
+        cache.insert_with_deps("template", 1, vec!["fragment", "page"]);
+        cache.insert_with_deps("page", 2, vec!["template"]);
+        assert_eq!(cache.invalidate("fragment"), 3);
+        assert!(!cache.contains_key("page"));
+        assert!(cache.contains_key("unrelated"));
+        assert_eq!(cache.invalidate("fragment"), 0);
+    }
+
+    #[test]
+    fn resident_inserts() {
+        let mut cache: CartCache<&str, u32> = CartCacheBuilder::new(16)
+            .merge_resident_values(|count, increment| *count += increment)
+            .build()
+            .unwrap();
+        assert!(!cache.insert("hits", 1));
+        assert!(cache.insert("hits", 2));
+        assert_eq!(cache.get("hits"), Some(&3));
+
+        let mut cache: CartCache<&str, u32> = CartCacheBuilder::new(16)
+            .keep_resident_values()
+            .build()
+            .unwrap();
+        assert_eq!(cache.insert_or_return("key", 1), None);
+        assert_eq!(cache.insert_or_return("key", 2), Some(2));
+        assert_eq!(cache.get("key"), Some(&1));
+    }
+
+    #[test]
+    fn scan() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        for i in 0..20 {
+            cache.insert(i, i * 2);
+            cache.get(&19);
+        }
+        let scan = cache.scan();
+        let entries: HashMap<u32, u32> = scan.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(entries.len(), 8);
+        assert_eq!(entries[&19], 38);
+        assert_eq!(scan.recent().count() + scan.frequent().count(), 8);
+    }
+
+    #[test]
+    fn trace_report() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(64)
+            .trace_window(1000)
+            .build()
+            .unwrap();
+        for i in 0..2000u32 {
+            let key = (i * 7919) % 61 + if i % 3 == 0 { i } else { 0 };
+            cache.get_or_insert_with(key, || key);
+        }
+        let report = cache.trace_report().unwrap();
+        assert_eq!((report.capacity, report.accesses), (32, 1000));
+        assert!(report.belady_hits >= report.hits && report.belady_hits >= report.lru_hits);
+    }
+
+    #[test]
+    fn try_reserve() {
+        let mut cache: CartCache<u64, [u64; 64]> = CartCache::new(16).unwrap();
+        assert_eq!(
+            cache.try_reserve(usize::MAX / 2),
+            Err(CartCacheError::AllocError)
+        );
+        assert_eq!(cache.slab.capacity(), 16);
+        assert!(cache.try_reserve(64).is_ok());
+        assert!(cache.slab.capacity() >= 64 && cache.nodes.capacity() >= 64);
+        cache.insert(1, [1; 64]);
+        assert_eq!(cache.get(&1).map(|value| value[0]), Some(1));
+    }
+
+    #[test]
+    fn maintenance() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(256)
+            .ttl(Duration::from_secs(1))
+            .maintenance_batch(2, 8)
+            .build()
+            .unwrap();
+        for i in 0..64 {
+            cache.insert(i, i);
+        }
+        cache.advance_time(Duration::from_secs(2));
+        assert_eq!(cache.run_maintenance(), 2);
+        assert_eq!(cache.run_maintenance(), 4);
+        assert_eq!(cache.run_maintenance(), 8);
+        assert_eq!(cache.run_maintenance(), 8);
+        for i in 0..8 {
+            cache.get(&i);
+        }
+        assert_eq!(cache.run_maintenance(), 4);
+        assert_eq!(cache.maintenance_batch(), 4);
+        assert!(CartCacheBuilder::<u32, u32>::new(8)
+            .maintenance_batch(4, 2)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn weigh_by_size() {
+        let mut cache: CartCache<String, Vec<u8>> = CartCacheBuilder::new(64)
+            .weigh_by_size()
+            .max_weight(1024)
+            .build()
+            .unwrap();
+        let entry = |i: usize| (i.to_string(), vec![0u8; 100]);
+        let (key, value) = entry(0);
+        let weight = key.total_size() + value.total_size();
+        for i in 0..32 {
+            let (key, value) = entry(i);
+            cache.insert(key, value);
+        }
+        assert!(cache.weight() <= 1024);
+        assert!(cache.weight() > 1024 - weight);
+    }
+
+    #[test]
+    fn content_eq() {
+        let mut a: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        let mut b: CartCache<u32, u32> = CartCache::new(64).unwrap();
+        for i in 0..4 {
+            a.insert(i, i);
+            b.insert(3 - i, 3 - i);
+        }
+        assert!(a.content_eq(&b));
+        b.get(&1);
+        assert!(a.content_eq(&b));
+        b.insert(1, 10);
+        assert!(!a.content_eq(&b));
+        b.insert(1, 1);
+        b.insert(4, 4);
+        assert!(!a.content_eq(&b) && !b.content_eq(&a));
+    }
+
+    #[test]
+    fn clone() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .key_filter(true)
+            .rng(SplitMix64::with_seed(42))
+            .build()
+            .unwrap();
+        for i in 0..32 {
+            cache.insert(i % 12, i);
+            cache.get(&(i % 5));
+        }
+        let mut fork = cache.clone();
+        assert!(fork.content_eq(&cache));
+        let order = |cache: &CartCache<u32, u32>| -> Vec<u32> {
+            cache.scan().iter().map(|(&key, _)| key).collect()
+        };
+        assert_eq!(order(&fork), order(&cache));
+        assert_eq!(fork.policy_state(), cache.policy_state());
+        assert_eq!(fork.stats(), cache.stats());
+        assert_eq!(fork.rng.next_u64(), cache.rng.next_u64());
+        fork.insert(100, 100);
+        assert!(fork.contains_hash(fork.key_hash(&100)));
+        assert!(!cache.contains_key(&100));
+        assert_eq!(fork.check_invariants(), Ok(()));
+
+        let evicted = Arc::new(Mutex::new(0));
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(4)
+            .merge_resident_values({
+                let mut merges = 0;
+                move |value, _| {
+                    merges += 1;
+                    *value = merges;
+                }
+            })
+            .on_evicted_hash({
+                let evicted = evicted.clone();
+                move |_, _, _, _| *evicted.lock().unwrap() += 1
+            })
+            .build()
+            .unwrap();
+        cache.insert(0, 0);
+        cache.insert(0, 0);
+        let mut fork = cache.clone();
+        fork.insert(0, 0);
+        fork.insert(0, 0);
+        cache.insert(0, 0);
+        assert_eq!(cache.get(&0), Some(&2));
+        assert_eq!(fork.get(&0), Some(&3));
+        for i in 1..8 {
+            fork.insert(i, i);
+        }
+        assert_eq!(*evicted.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn stable_pointers() {
+        let mut cache: CartCache<u32, Box<[u8]>> = CartCache::new(1024).unwrap();
+        cache.insert_boxed(0, vec![42u8; 16]);
+        let ptr = cache.get_unboxed_ptr(&0).unwrap();
+        for i in 1..512 {
+            cache.insert_boxed(i, vec![0u8; 16]);
+        }
+        assert_eq!(cache.get_unboxed_ptr(&0), Some(ptr));
+        assert_eq!(unsafe { ptr.as_ref() }[0], 42);
+    }
+
+    #[cfg(feature = "perf_counters")]
+    #[test]
+    fn perf_counters() {
+        let mut cache: CartCache<u64, u64> = CartCache::new(4).unwrap();
+        for i in 0..8 {
+            cache.insert(i, i);
+        }
+        cache.get(&7);
+        cache.insert(7, 7);
+        let ghost =
+            (0..8).find(|i| cache.contains_key(i) && !cache.scan().iter().any(|(k, _)| k == i));
+        cache.insert(ghost.unwrap(), 0);
+        for i in 8..12 {
+            cache.insert(i, i);
+        }
+        let perf = cache.perf_counters();
+        assert_eq!(perf.new_inserts, 12);
+        assert_eq!(perf.resident_hits, 1);
+        assert_eq!(perf.resident_updates, 1);
+        assert_eq!(perf.b1_promotions, 1);
+        assert!(perf.t1_sweeps + perf.t2_sweeps > 0);
+    }
+
+    #[test]
+    fn insert_cow() {
+        use std::borrow::Cow;
+        use std::cell::Cell;
+
+        thread_local!(static CLONES: Cell<usize> = const { Cell::new(0) });
+
+        #[derive(PartialEq, Eq, Hash)]
+        struct Key(u32);
+
+        impl Clone for Key {
+            fn clone(&self) -> Self {
+                CLONES.with(|clones| clones.set(clones.get() + 1));
+                Key(self.0)
+            }
+        }
+
+        let mut cache = CartCache::new(2).unwrap();
+        cache.insert_cow(Cow::Borrowed(&Key(1)), 1);
+        let admitted = CLONES.with(Cell::get);
+        assert!(admitted > 0);
+        cache.insert_cow(Cow::Borrowed(&Key(1)), 2);
+        assert_eq!(CLONES.with(Cell::get), admitted);
+        assert_eq!(cache.get(&Key(1)), Some(&2));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn bytes_values() {
+        use bytes::Bytes;
+
+        let mut cache: CartCache<u32, Bytes> = CartCache::with_max_bytes(16, 10).unwrap();
+        let body = Bytes::from(vec![1u8; 6]);
+        cache.insert(1, Bytes::from_static(b"12345"));
+        assert_eq!(cache.weight(), 5);
+        cache.insert(2, body.clone());
+        assert_eq!(cache.weight(), 6);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get_bytes(&2).unwrap().as_ptr(), body.as_ptr());
+    }
 
-        let cache = CartCache {
-            slab,
-            map,
-            t1,
-            t2,
-            b1,
-            b2,
-            c,
-            capacity,
-            p: 0,
-            q: 0,
-            shortterm_count: 0,
-            longterm_count: 0,
-            inserted: 0,
-            evicted: 0,
+    #[test]
+    fn promotion_threshold() {
+        let longterm = |factor| {
+            let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(64)
+                .promotion_threshold(factor)
+                .build()
+                .unwrap();
+            for i in 0..1000 {
+                cache.insert(i, i);
+                if i % 3 == 0 {
+                    cache.get(&i);
+                }
+            }
+            cache.frequent_len()
         };
-        Ok(cache)
+        assert!(longterm(0.0) >= longterm(1.0));
+        assert!(longterm(1.0) > longterm(100.0));
+        assert!(CartCacheBuilder::<u32, u32>::new(64)
+            .promotion_threshold(-1.0)
+            .build()
+            .is_err());
     }
 
-    pub fn capacity(&self) -> usize {
-        self.capacity
+    #[test]
+    fn last_hit() {
+        use std::cell::Cell;
+        use std::hash::{Hash, Hasher};
+
+        thread_local!(static HASHED: Cell<usize> = const { Cell::new(0) });
+
+        #[derive(Clone, PartialEq, Eq)]
+        struct Key(u32);
+
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                HASHED.with(|hashed| hashed.set(hashed.get() + 1));
+                self.0.hash(state);
+            }
+        }
+
+        let mut cache = CartCache::new(16).unwrap();
+        cache.insert(Key(1), 1);
+        cache.insert(Key(2), 2);
+        assert_eq!(cache.get(&Key(1)), Some(&1));
+        let hashed = HASHED.with(Cell::get);
+        for _ in 0..10 {
+            assert_eq!(cache.get(&Key(1)), Some(&1));
+        }
+        assert_eq!(HASHED.with(Cell::get), hashed);
+        assert_eq!(cache.get(&Key(2)), Some(&2));
+        cache.remove_many(Some(&Key(2)));
+        cache.insert(Key(3), 3);
+        assert_eq!(cache.get(&Key(2)), None);
+        assert_eq!(cache.get(&Key(3)), Some(&3));
     }
 
-    pub fn len(&self) -> usize {
-        self.map.len()
+    #[test]
+    fn reserve_headroom() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        for i in 0..20 {
+            cache.insert(i, i);
+        }
+        let resident = |cache: &CartCache<u32, u32>| cache.scan().iter().count();
+        assert_eq!(resident(&cache), 8);
+        let evicted = cache.stats().evicted_by_count;
+        assert_eq!(cache.reserve_headroom(3), 3);
+        assert_eq!(resident(&cache), 5);
+        assert_eq!(cache.stats().evicted_by_count, evicted + 3);
+        let evicted = cache.evicted();
+        for i in 20..23 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.evicted(), evicted);
+        assert_eq!(cache.reserve_headroom(100), 8);
+        assert_eq!(resident(&cache), 0);
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    #[test]
+    fn state_digest() {
+        let mut a: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        let mut b: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .with_random_seed()
+            .build()
+            .unwrap();
+        for i in 0..40 {
+            a.insert(i % 11, i);
+            b.insert(i % 11, 0);
+            a.get(&(i % 3));
+            b.get(&(i % 3));
+        }
+        assert_eq!(a.state_digest(), b.state_digest());
+        a.get(&10);
+        assert_ne!(a.state_digest(), b.state_digest());
+        assert_eq!(fingerprint(&1u32), 1742378985846435984);
     }
 
-    pub fn frequent_len(&self) -> usize {
-        self.longterm_count + self.b2.len()
+    #[test]
+    fn changes_since() {
+        let mut cache: CartCache<u32, u32> =
+            CartCacheBuilder::new(4).change_log(8).build().unwrap();
+        assert_eq!(cache.changes_since(0), Some((vec![], 0)));
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        let (changes, counter) = cache.changes_since(0).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0], KeyChange::Inserted(fingerprint(&1u32)));
+        cache.insert(3, 3);
+        let (changes, counter) = cache.changes_since(counter).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                KeyChange::Evicted(fingerprint(&1u32)),
+                KeyChange::Inserted(fingerprint(&3u32))
+            ]
+        );
+        for i in 4..10 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.changes_since(counter), None);
+        assert!(CartCache::<u32, u32>::new(4)
+            .unwrap()
+            .changes_since(0)
+            .is_none());
     }
 
-    pub fn recent_len(&self) -> usize {
-        self.shortterm_count + self.b1.len()
+    #[test]
+    fn stats_delta() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        cache.insert(1, 1);
+        let snapshot = cache.stats();
+        cache.insert(2, 2);
+        cache.get(&2);
+        cache.get(&3);
+        let delta = cache.stats_delta_since(&snapshot);
+        assert_eq!((delta.inserted, delta.hits, delta.misses), (1, 1, 1));
+        assert_eq!(delta.resets, 0);
+        cache.clear();
+        cache.insert(4, 4);
+        let delta = cache.stats_delta_since(&snapshot);
+        assert_eq!((delta.inserted, delta.hits, delta.resets), (1, 0, 1));
     }
 
-    pub fn inserted(&self) -> u64 {
-        self.inserted
+    #[test]
+    fn checksum() {
+        let mut cache: CartCache<u32, Vec<u8>> = CartCacheBuilder::new(8)
+            .checksum(|value: &Vec<u8>| fingerprint(value))
+            .eviction_notifications(NotificationMode::Queue)
+            .build()
+            .unwrap();
+        cache.insert(1, vec![1, 2, 3]);
+        cache.insert(2, vec![4, 5, 6]);
+        cache.get_mut(&1).unwrap().push(4);
+        assert!(cache.finish_mutation(&1));
+        assert_eq!(cache.get_verified(&1), Ok(Some(&vec![1, 2, 3, 4])));
+        cache.get_mut(&1).unwrap().push(5);
+        assert_eq!(cache.get_verified(&1), Ok(Some(&vec![1, 2, 3, 4, 5])));
+        let token = cache.map[&1];
+        cache.slab[token].value[0] = 0;
+        assert_eq!(cache.get_verified(&1), Err(CorruptEntry));
+        assert_eq!(cache.get_verified(&1), Ok(None));
+        let token = cache.map[&2];
+        cache.slab[token].value[0] = 0;
+        assert_eq!(cache.get(&2), None);
+        assert!(cache.is_empty());
+        let causes: Vec<_> = cache
+            .drain_evictions()
+            .iter()
+            .map(|event| event.cause)
+            .collect();
+        assert_eq!(causes, [EvictionCause::Corrupt, EvictionCause::Corrupt]);
+        assert_eq!(cache.stats().misses, 3);
     }
 
-    pub fn evicted(&self) -> u64 {
-        self.evicted
+    #[test]
+    fn checked() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        assert_eq!(cache.insert_checked(1, 1), Ok(false));
+        assert_eq!(cache.get_checked(&1), Ok(Some(&1)));
+        assert_eq!(cache.get_checked(&2), Ok(None));
+        assert_eq!(cache.insert_checked(1, 2), Ok(true));
+        cache.t1.len = 0;
+        assert!(cache.get_checked(&1).is_err());
+        assert!(cache.insert_checked(2, 2).is_err());
+        cache.clear();
+        assert_eq!(cache.insert_checked(1, 1), Ok(false));
     }
 
-    pub fn clear(&mut self) {
-        self.slab.clear();
-        self.map.clear();
-        self.t1.clear();
-        self.t2.clear();
-        self.b1.clear();
-        self.b2.clear();
-        self.p = 0;
-        self.q = 0;
-        self.shortterm_count = 0;
-        self.longterm_count = 0;
-        self.inserted = 0;
-        self.evicted = 0;
+    #[test]
+    fn heap_bytes() {
+        let mut cache: CartCache<u32, String> =
+            CartCacheBuilder::new(8).measure_memory().build().unwrap();
+        let empty = cache.heap_bytes().unwrap();
+        cache.insert(1, String::with_capacity(1000));
+        let one = cache.heap_bytes().unwrap();
+        assert!(one >= empty + 1000);
+        cache.insert(1, String::new());
+        assert!(cache.heap_bytes().unwrap() < one - 900);
+        assert_eq!(CartCache::<u32, u32>::new(8).unwrap().heap_bytes(), None);
     }
 
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
-    where
-        Q: Hash + Eq,
-        K: Borrow<Q>,
-    {
-        self.map.contains_key(key)
+    #[test]
+    fn hash_keys() {
+        let keyed = |k0, k1| {
+            CartCacheBuilder::<u32, u32>::new(8)
+                .hash_keys(k0, k1)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(keyed(1, 2).key_hash(&7), keyed(1, 2).key_hash(&7));
+        assert_ne!(keyed(1, 2).key_hash(&7), keyed(2, 1).key_hash(&7));
+        let mut cache = CartCacheBuilder::new(8)
+            .hash_keys(1, 2)
+            .with_random_seed()
+            .key_filter(true)
+            .build()
+            .unwrap();
+        assert_ne!(cache.key_hash(&7), keyed(1, 2).key_hash(&7));
+        cache.insert(7, 7);
+        assert!(cache.contains_hash(cache.key_hash(&7)));
+        assert_eq!(cache.get(&7), Some(&7));
+        let mut cache: CartCache<u32, u32, RandomState> = CartCacheBuilder::new(8)
+            .build_with_hasher(RandomState::new())
+            .unwrap();
+        cache.insert(7, 7);
+        assert_eq!(cache.get(&7), Some(&7));
+        assert_eq!(cache.key_hash(&7), cache.map.hasher().hash_one(7));
     }
 
-    pub fn get<Q: ?Sized>(&mut self, key: &Q) -> Option<&V>
-    where
-        Q: Hash + Eq,
-        K: Borrow<Q>,
-    {
-        match self.map.get(key) {
-            Some(&token) => {
-                let cached_entry = &mut self.slab[token];
-                cached_entry.is_reference = true;
-                Some(&cached_entry.value)
-            }
-            None => None,
+    #[test]
+    fn set_capacity() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(64).unwrap();
+        for i in 0..64 {
+            cache.insert(i, i);
+        }
+        let evicted = cache.stats().evicted_by_count;
+        assert_eq!(cache.scan().iter().count(), 32);
+        cache.set_capacity(16).unwrap();
+        assert_eq!(cache.capacity(), 16);
+        assert_eq!(cache.scan().iter().count(), 8);
+        assert_eq!(cache.stats().evicted_by_count, evicted + 24);
+        assert!(cache.len() <= 16);
+        assert!(cache.set_capacity(0).is_err());
+        cache.set_capacity(64).unwrap();
+        for i in 64..128 {
+            cache.insert(i, i);
         }
+        assert_eq!(cache.scan().iter().count(), 32);
     }
 
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
-    where
-        Q: Hash + Eq,
-        K: Borrow<Q>,
-    {
-        match self.map.get(key) {
-            Some(&token) => {
-                let cached_entry = &mut self.slab[token];
-                cached_entry.is_reference = true;
-                Some(&mut cached_entry.value)
-            }
-            None => None,
+    #[test]
+    fn manual_overrides() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        for i in 0..4 {
+            cache.insert(i, i);
+            cache.get(&i);
+        }
+        assert!(cache.promote(&1));
+        assert!(cache.is_frequent(&1));
+        assert!(cache.demote(&1));
+        assert!(cache.is_recent(&1));
+        assert!(cache.demote(&3));
+        assert_eq!(cache.peek_victim(), Some(&3));
+        cache.insert(4, 4);
+        assert!(!cache.demote(&3));
+        assert!(!cache.promote(&3));
+        assert_eq!(cache.frequent_len() + cache.recent_len(), cache.len());
+
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        for i in 0..8 {
+            cache.insert(i, i);
+            cache.get(&i);
+            cache.get(&i);
         }
+        cache.p = 4;
+        assert!(cache.demote(&7));
+        assert!(cache.is_frequent(&7));
+        assert_eq!(cache.peek_victim(), Some(&7));
+        cache.insert(8, 8);
+        assert!(!cache.scan().iter().any(|(&key, _)| key == 7));
+        assert_eq!(cache.check_invariants(), Ok(()));
     }
 
-    fn evict_if_full(&mut self, is_history: bool) {
-        if self.t1.len() + self.t2.len() >= self.c {
-            self.replace();
-            if !is_history && self.b1.len() + self.b2.len() >= self.c + 1 {
-                if self.b1.len() > max(0, self.q) || self.b2.is_empty() {
-                    let token = self
-                        .b1
-                        .pop_front(&mut self.slab)
-                        .expect("Front element vanished");
-                    self.map.remove(&self.slab[token].key);
-                    self.slab.remove(token);
-                } else if !self.b2.is_empty() {
-                    let token = self
-                        .b2
-                        .pop_front(&mut self.slab)
-                        .expect("Front element vanished");
-                    self.map.remove(&self.slab[token].key);
-                    self.slab.remove(token);
-                }
-            }
-            self.evicted += 1;
+    #[test]
+    fn warmup() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(64)
+            .warmup_operations(80)
+            .build()
+            .unwrap();
+        for i in 0..64 {
+            cache.insert(i, i);
+        }
+        assert!(cache.is_warming_up());
+        assert_eq!(cache.scan().iter().count(), 64);
+        for i in 64..80 {
+            cache.insert(i, i);
         }
+        assert!(!cache.is_warming_up());
+        assert_eq!(cache.scan().iter().count(), 32);
+        assert!(cache.len() <= 64);
+        assert!(cache.contains_key(&79));
     }
 
-    fn insert_new_entry(&mut self, key: K, value: V)
-    where
-        K: Hash + Eq + Clone,
-    {
-        let entry = Entry {
-            key: key.clone(),
-            value,
-            prev: None,
-            next: None,
-            is_history: false,
-            is_reference: false,
-            is_longterm: false,
-        };
-        let token = self.slab.insert(entry);
-        self.t1.push_back(token);
-        self.shortterm_count += 1;
-        self.map.insert(key, token);
-        self.inserted += 1;
+    #[test]
+    fn subscribe() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        let inserted = cache.subscribe(1);
+        let loaded = cache.subscribe(2);
+        let cleared = cache.subscribe(3);
+        cache.insert(0, 0);
+        assert!(inserted.try_recv().is_err());
+        cache.insert(1, 1);
+        assert!(inserted.try_recv().is_ok());
+        cache.get_or_insert_with(2, || 2);
+        assert!(loaded.try_recv().is_ok());
+        cache.clear();
+        assert!(cleared.recv().is_err());
     }
 
-    fn promote_from_b1(&mut self, token: Token) {
-        self.p = min(
-            self.p + max(1, self.shortterm_count / self.b1.len()),
-            self.c,
-        );
-        {
-            let cached_entry = &mut self.slab[token];
-            cached_entry.is_history = false;
-            cached_entry.is_reference = false;
-            cached_entry.is_longterm = true;
-            self.longterm_count += 1;
+    #[test]
+    fn static_parameters() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(100)
+            .static_parameters(20, 5)
+            .build()
+            .unwrap();
+        for i in 0..10_000 {
+            cache.insert(i % 97, i);
+            cache.insert(i % 13, i);
         }
-        self.b1.remove(&mut self.slab, token);
-        self.t1.push_back(token);
+        let state = cache.policy_state();
+        assert_eq!((state.p, state.q), (20, 5));
     }
 
-    fn promote_from_b2(&mut self, token: Token) {
-        let t = max(1, self.longterm_count / self.b2.len());
-        self.p = if self.p > t { self.p - t } else { 0 };
-        {
-            let cached_entry = &mut self.slab[token];
-            cached_entry.is_history = false;
-            cached_entry.is_reference = false;
-            assert!(cached_entry.is_longterm);
-            self.longterm_count += 1;
+    #[test]
+    fn classification() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(8).unwrap();
+        for i in 0..4 {
+            cache.insert(i, i);
         }
-        self.b2.remove(&mut self.slab, token);
-        self.t1.push_back(token);
-        if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count >= self.c {
-            self.q = min(self.q + 1, self.capacity - self.t1.len());
+        assert!(cache.is_recent(&0));
+        assert!(!cache.is_frequent(&0));
+        for i in 0..4 {
+            cache.get(&i);
         }
+        for i in 4..16 {
+            cache.insert(i, i);
+        }
+        assert!((0..16).any(|i| cache.is_frequent(&i)));
+        assert!(!cache.is_recent(&100));
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> bool
-    where
-        K: Hash + Eq + Clone,
-    {
-        let (token, is_history, is_longterm) = match self.map.get_mut(&key) {
-            Some(&mut token) => {
-                let cached_entry = &mut self.slab[token];
-                if !cached_entry.is_history {
-                    cached_entry.is_reference = true;
-                    cached_entry.value = value;
-                    return true;
-                }
-                (
-                    Some(token),
-                    cached_entry.is_history,
-                    cached_entry.is_longterm,
-                )
-            }
-            None => (None, false, false),
-        };
-        self.evict_if_full(is_history);
-        if !is_history {
-            self.insert_new_entry(key, value);
-        } else if !is_longterm {
-            self.promote_from_b1(token.unwrap());
-        } else {
-            self.promote_from_b2(token.unwrap());
+    #[test]
+    fn resident_capacity() {
+        let mut cache: CartCache<u32, u32> = CartCache::with_resident_capacity(100).unwrap();
+        assert_eq!(cache.capacity(), 200);
+        assert_eq!(cache.resident_capacity(), 100);
+        for i in 0..100 {
+            cache.insert(i, i);
         }
-        false
+        assert!((0..100).all(|i| cache.is_recent(&i)));
+        assert_eq!(cache.get_copied(&42), Some(42));
+        assert!(cache.is_recent(&42));
     }
 
-    fn replace_t2(&mut self) {
-        loop {
-            match self.t2.front() {
-                None => break,
-                Some(&token) => {
-                    if !self.slab[token].is_reference {
-                        break;
-                    }
-                }
-            }
-            let token = self.t2.pop_front().expect("Front element vanished");
-            let found = &mut self.slab[token];
-            found.is_reference = false;
-            self.t1.push_back(token);
-            if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count >= self.c {
-                self.q = min(self.q + 1, self.capacity - self.t1.len())
+    #[test]
+    fn equivalent_lookup() {
+        #[derive(Hash)]
+        struct Lookup<'a>(&'a str, u32);
+
+        impl<'a> Equivalent<(String, u32)> for Lookup<'a> {
+            fn equivalent(&self, key: &(String, u32)) -> bool {
+                self.0 == key.0 && self.1 == key.1
             }
         }
+
+        let mut cache: CartCache<(String, u32), u32> = CartCache::new(16).unwrap();
+        cache.insert(("a".to_string(), 1), 10);
+        assert_eq!(cache.get(&Lookup("a", 1)), Some(&10));
+        assert!(!cache.contains_key(&Lookup("a", 2)));
     }
 
-    fn replace_t1(&mut self) {
-        loop {
-            match self.t1.front() {
-                None => break,
-                Some(&token) => {
-                    let found = &mut self.slab[token];
-                    if !(found.is_longterm || found.is_reference) {
-                        break;
-                    }
-                }
-            }
-            let token = self.t1.pop_front().expect("Front element vanished");
-            let found = &mut self.slab[token];
-            if found.is_reference {
-                found.is_reference = false;
-                self.t1.push_back(token);
-                if self.t1.len() >= min(self.p + 1, self.b1.len()) && !found.is_longterm {
-                    assert!(!found.is_longterm);
-                    found.is_longterm = true;
-                    self.shortterm_count -= 1;
-                    self.longterm_count += 1;
-                }
-            } else {
-                self.t2.push_back(token);
-                if self.q > 0 {
-                    self.q = max(self.q - 1, self.c - self.t1.len());
-                } else {
-                    self.q = self.c - self.t1.len();
-                }
-            }
+    #[test]
+    fn map_on_evict() {
+        let mut cache: CartCache<u32, String> = CartCacheBuilder::new(8)
+            .map_on_evict(|_, v: &mut String| v.truncate(1))
+            .build()
+            .unwrap();
+        for i in 0..8 {
+            cache.insert(i, format!("{}-full", i));
         }
+        assert_eq!(cache.get(&0).map(|v| v.as_str()), Some("0"));
+        assert_eq!(cache.get(&7).map(|v| v.as_str()), Some("7-full"));
     }
 
-    fn demote(&mut self) {
-        if self.t1.len() >= max(1, self.p) {
-            if let Some(token) = self.t1.pop_front() {
-                {
-                    let demoted = &mut self.slab[token];
-                    assert!(!demoted.is_history);
-                    demoted.is_history = true;
-                    assert!(!demoted.is_longterm);
-                    self.shortterm_count -= 1;
-                }
-                self.b1.push_back(&mut self.slab, token);
-            }
-        } else if let Some(token) = self.t2.pop_front() {
-            {
-                let demoted = &mut self.slab[token];
-                assert!(!demoted.is_history);
-                demoted.is_history = true;
-                assert!(demoted.is_longterm);
-                self.longterm_count -= 1;
+    #[test]
+    fn from_map() {
+        let map: HashMap<u32, u32> = (0..50).map(|i| (i, i * 2)).collect();
+        let mut cache = CartCache::from(map);
+        assert_eq!(cache.capacity(), 100);
+        assert!((0..50).all(|i| cache.get(&i) == Some(&(i * 2))));
+        let empty: CartCache<u32, u32> = HashMap::new().into();
+        assert!(empty.is_empty());
+        for i in 50..200 {
+            cache.insert(i, i * 2);
+        }
+        let map: HashMap<u32, u32> = cache.into();
+        assert_eq!(map.len(), 50);
+        assert!(map.iter().all(|(k, v)| *v == k * 2));
+    }
+
+    #[test]
+    fn key_filter() {
+        let mut cache: CartCache<u32, u32> =
+            CartCacheBuilder::new(64).key_filter(true).build().unwrap();
+        for i in 0..200 {
+            cache.insert(i, i);
+        }
+        for i in 0..200 {
+            if cache.contains_key(&i) {
+                assert!(cache.contains_hash(cache.key_hash(&i)));
             }
-            self.b2.push_back(&mut self.slab, token);
         }
+        let misses = (1000..2000)
+            .filter(|i| !cache.contains_hash(cache.key_hash(i)))
+            .count();
+        assert!(misses > 500);
     }
 
-    fn replace(&mut self) {
-        self.replace_t2();
-        self.replace_t1();
-        self.demote();
+    #[test]
+    fn evicted_hashes() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(4)
+            .quota_group_by(|&key: &u32| key as usize % 2)
+            .on_evicted_hash({
+                let evicted = evicted.clone();
+                move |hash, group, cause, _| evicted.lock().unwrap().push((hash, group, cause))
+            })
+            .build()
+            .unwrap();
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        let evicted = evicted.lock().unwrap();
+        assert!(!evicted.is_empty());
+        assert_eq!(
+            evicted[0],
+            (fingerprint(&0u32), Some(0), EvictionCause::Capacity)
+        );
     }
-}
 
-trait XLinkedNode {
-    fn prev(&self) -> Option<Token>;
-    fn next(&self) -> Option<Token>;
-    fn set_prev(&mut self, prev: Option<Token>);
-    fn set_next(&mut self, next: Option<Token>);
-}
+    #[test]
+    fn export_by_priority() {
+        let mut cache: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        for i in 0..8 {
+            cache.insert(i, i);
+        }
+        cache.get(&5);
+        for i in 8..12 {
+            cache.insert(i, i);
+        }
+        cache.insert(2, 2);
+        let exported = cache.export_by_priority(6);
+        assert_eq!(exported.len(), 6);
+        assert_eq!(exported[0], (2, 1, EntryClass::Frequent));
+        assert_eq!(exported[1].0, 5);
+        assert_eq!(exported[2].0, 11);
 
-impl<K, V> XLinkedNode for Entry<K, V>
-where
-    K: Eq + Hash,
-{
-    #[inline]
-    fn prev(&self) -> Option<Token> {
-        self.prev
+        let mut warmed: CartCache<u32, u32> = CartCache::new(16).unwrap();
+        assert_eq!(warmed.warm_from(exported, |&key| Some(key * 10)), 6);
+        assert_eq!(warmed.get(&2), Some(&20));
+        assert_eq!(warmed.frequent_len(), 1);
     }
 
-    #[inline]
-    fn next(&self) -> Option<Token> {
-        self.next
+    #[test]
+    fn eviction_ctx() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(4)
+            .eviction_notifications(NotificationMode::Queue)
+            .build()
+            .unwrap();
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        cache.insert_with_ctx(4, 4, 42);
+        cache.insert(5, 5);
+        let events = cache.drain_evictions();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].ctx, events[1].ctx), (Some(42), None));
     }
 
-    #[inline]
-    fn set_prev(&mut self, prev: Option<Token>) {
-        self.prev = prev;
+    #[test]
+    fn coalesced_evictions() {
+        let mut queued: CartCache<u32, u32> = CartCacheBuilder::new(4)
+            .eviction_notifications(NotificationMode::Queue)
+            .build()
+            .unwrap();
+        let mut coalesced: CartCache<u32, u32> = CartCacheBuilder::new(4)
+            .eviction_notifications(NotificationMode::Coalesce)
+            .build()
+            .unwrap();
+        for i in 0..100 {
+            queued.insert(i % 10, i);
+            coalesced.insert(i % 10, i);
+        }
+        assert!(coalesced.pending_evictions() <= 10);
+        assert!(queued.pending_evictions() > coalesced.pending_evictions());
+        let events = coalesced.drain_evictions();
+        let total: u64 = events.iter().map(|e| e.count).sum();
+        assert_eq!(total as usize, queued.drain_evictions().len());
+        assert!(events.iter().all(|e| e.cause == EvictionCause::Capacity));
+        assert_eq!(coalesced.pending_evictions(), 0);
     }
 
-    #[inline]
-    fn set_next(&mut self, next: Option<Token>) {
-        self.next = next;
-    }
-}
+    #[test]
+    fn unsized_values() {
+        trait Response {
+            fn status(&self) -> u16;
+        }
 
-struct XLinkedList<K, V>
-where
-    K: Eq + Hash,
-{
-    head: Option<Token>,
-    tail: Option<Token>,
-    len: usize,
-    phantom_k: PhantomData<K>,
-    phantom_v: PhantomData<V>,
-}
+        struct Ok200;
 
-impl<K, V> XLinkedList<K, V>
-where
-    K: Eq + Hash,
-{
-    fn new() -> Self {
-        XLinkedList {
-            head: None,
-            tail: None,
-            len: 0,
-            phantom_k: PhantomData,
-            phantom_v: PhantomData,
+        impl Response for Ok200 {
+            fn status(&self) -> u16 {
+                200
+            }
         }
-    }
 
-    #[inline]
-    fn len(&self) -> usize {
-        self.len
-    }
+        impl From<Ok200> for Box<dyn Response> {
+            fn from(response: Ok200) -> Self {
+                Box::new(response)
+            }
+        }
 
-    #[inline]
-    fn is_empty(&self) -> bool {
-        self.len == 0
-    }
+        let mut responses: CartCache<u32, Box<dyn Response>> = CartCache::new(8).unwrap();
+        responses.insert_boxed(1, Ok200);
+        assert_eq!(responses.get_unboxed(&1).map(|r| r.status()), Some(200));
 
-    #[inline]
-    fn clear(&mut self) {
-        self.len = 0;
-        self.head = None;
-        self.tail = None;
-    }
+        let mut names: CartCache<u32, Box<str>> = CartCache::new(8).unwrap();
+        names.insert_boxed(1, "one");
+        assert_eq!(names.get_unboxed(&1), Some("one"));
 
-    fn remove(&mut self, slab: &mut Slab<Entry<K, V>>, token: Token) {
-        let (prev_token, next_token) = {
-            let elt = &mut slab[token];
-            let prev_token = elt.prev();
-            elt.set_prev(None);
-            let next_token = elt.next();
-            elt.set_next(None);
-            (prev_token, next_token)
-        };
-        if let Some(prev_token) = prev_token {
-            slab[prev_token].set_next(next_token);
-        } else {
-            self.head = next_token;
-        }
-        if let Some(next_token) = next_token {
-            slab[next_token].set_prev(prev_token);
-        } else {
-            self.tail = prev_token;
-        }
-        self.len -= 1;
+        let mut shared: CartCache<u32, Arc<[u8]>> = CartCache::new(8).unwrap();
+        shared.insert_arc(1, vec![1, 2, 3]);
+        assert_eq!(shared.get_arc(&1).as_deref(), Some(&[1u8, 2, 3][..]));
     }
 
-    fn push_back(&mut self, slab: &mut Slab<Entry<K, V>>, token: Token) {
-        {
-            let elt = &mut slab[token];
-            elt.set_prev(self.tail);
-            elt.set_next(None);
-        }
-        if let Some(tail_token) = self.tail {
-            slab[tail_token].set_next(Some(token));
+    #[test]
+    fn group_quotas() {
+        let mut cache: CartCache<(usize, u32), u32> = CartCacheBuilder::new(200)
+            .quota_group_by(|&(tenant, _)| tenant)
+            .group_quota(1, 10)
+            .build()
+            .unwrap();
+        for i in 0..50 {
+            cache.insert((0, i), i);
+            cache.insert((1, i), i);
         }
-        self.tail = Some(token);
-        self.head = self.head.or(self.tail);
-        self.len += 1;
-    }
+        assert_eq!(cache.group_weight(0), 50);
+        assert_eq!(cache.group_weight(1), 50);
+        assert_eq!(cache.groups[&1].resident_weight, 10);
+        assert_eq!(cache.stats().evicted_by_quota, 40);
+        assert_eq!(cache.get(&(1, 49)), Some(&49));
+        assert!(!cache.nodes[cache.map[&(1, 49)]].is_history());
+        assert!(cache.nodes[cache.map[&(1, 0)]].is_history());
+        cache.get_or_insert_with((1, 100), || 100);
+        assert_eq!(cache.groups[&1].resident_weight, 10);
+        let stats = cache.group_stats(1);
+        assert_eq!((stats.len, stats.weight), (51, 51));
+        assert_eq!((stats.hits, stats.misses, stats.evicted), (1, 1, 0));
+        assert_eq!(cache.stats().evicted_by_quota, 41);
+        assert_eq!(cache.group_stats(0).evicted, 0);
 
-    pub fn pop_front(&mut self, slab: &mut Slab<Entry<K, V>>) -> Option<Token> {
-        let head_token = self.head;
-        if let Some(head_token) = head_token {
-            let new_head_token = {
-                let former_head = &mut slab[head_token];
-                let next_token = former_head.next();
-                former_head.set_next(None);
-                next_token
-            };
-            match new_head_token {
-                None => self.clear(),
-                Some(new_head_token) => {
-                    slab[new_head_token].set_prev(None);
-                    self.head = Some(new_head_token);
-                    self.len -= 1;
-                }
-            }
+        let mut cache: CartCache<(usize, u32), u32> = CartCacheBuilder::new(16)
+            .quota_group_by(|&(tenant, _)| tenant)
+            .group_quota(1, 3)
+            .build()
+            .unwrap();
+        for i in 0..3 {
+            cache.insert((1, i), i);
         }
-        head_token
+        cache.get(&(1, 1));
+        cache.remove(&(1, 0));
+        cache.insert((1, 3), 3);
+        cache.insert((1, 4), 4);
+        let resident = |cache: &CartCache<(usize, u32), u32>| -> Vec<u32> {
+            cache.scan().iter().map(|(&(_, i), _)| i).collect()
+        };
+        assert_eq!(resident(&cache), [1, 3, 4]);
+        assert!(cache.nodes[cache.map[&(1, 1)]].is_reference());
     }
-}
-
-#[cfg(test)]
-mod tests {
-    extern crate rand;
-    use self::rand::prelude::*;
-    use crate::CartCache;
 
     #[test]
-    fn random_inserts() {
-        let count = 1_000_000;
-        let mut cached: u64 = 0;
-        let mut cache: CartCache<u8, u8> = CartCache::new(128).unwrap();
-        let mut rng = thread_rng();
-
-        for _ in 0..count {
-            let key: u8 = rng.gen();
-            cache.insert(key, key);
-            let key: u8 = rng.gen();
-            if cache.get(&key).is_some() {
-                cached += 1;
-            }
+    fn dual_limits() {
+        let mut cache: CartCache<u32, Vec<u8>> = CartCacheBuilder::new(64)
+            .max_weight(1000)
+            .weigher(|_, v: &Vec<u8>| v.len())
+            .build()
+            .unwrap();
+        for i in 0..100 {
+            cache.insert(i, vec![0; 10]);
         }
-        assert!(cached > count / 3);
+        assert!(cache.weight() <= 1000);
+        assert!(cache.stats().evicted_by_count > 0);
+        assert_eq!(cache.stats().evicted_by_weight, 0);
+        for i in 100..200 {
+            cache.insert(i, vec![0; 100]);
+            assert!(cache.weight() <= 1000);
+        }
+        assert!(cache.stats().evicted_by_weight > 0);
     }
 }
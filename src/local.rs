@@ -0,0 +1,192 @@
+use std::hash::Hash;
+use std::sync::Arc;
+
+use sharded::{BufferedRead, ShardedCartCache};
+
+// Hits replayed to the shared cache at once.
+const READ_BATCH: usize = 16;
+
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    shard: usize,
+    version: u64,
+    read: BufferedRead,
+    referenced: bool,
+}
+
+/// A tiny per-thread cache in front of a `ShardedCartCache`, replaced with
+/// plain CLOCK. Each copy is stamped with the version of its shard, and is
+/// dropped as soon as that shard is written to, so reads are never stale;
+/// hits only take an atomic load instead of a shard lock. Hits are batched
+/// and replayed to the shards like their own lock-free reads, so that the
+/// shared cache still sees the entries as referenced.
+///
+/// Invalidation is per shard, not per key: any write to a shard drops every
+/// copy from that shard, including copies of keys that were not written.
+/// With many writes per shard, most lookups miss the front cache and only
+/// pay for the version check. More shards make this less likely.
+///
+/// A `FrontCache` is meant to be owned by a single thread, e.g. kept in a
+/// `thread_local!`.
+pub struct FrontCache<K, V>
+where
+    K: Eq + Hash,
+{
+    shared: Arc<ShardedCartCache<K, V>>,
+    slots: Vec<Slot<K, V>>,
+    capacity: usize,
+    hand: usize,
+    hits: u64,
+    reads: Vec<(usize, BufferedRead)>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> FrontCache<K, V> {
+    pub fn new(shared: Arc<ShardedCartCache<K, V>>, capacity: usize) -> Result<Self, &'static str> {
+        if capacity == 0 {
+            return Err("Capacity cannot be zero");
+        }
+        Ok(FrontCache {
+            shared,
+            slots: Vec::with_capacity(capacity),
+            capacity,
+            hand: 0,
+            hits: 0,
+            reads: Vec::with_capacity(READ_BATCH),
+        })
+    }
+
+    pub fn shared(&self) -> &Arc<ShardedCartCache<K, V>> {
+        &self.shared
+    }
+
+    /// Lookups served without touching the shared cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        if let Some(i) = self.slots.iter().position(|slot| slot.key == *key) {
+            let slot = &mut self.slots[i];
+            if self.shared.shard_version(slot.shard) == slot.version {
+                slot.referenced = true;
+                self.hits += 1;
+                let value = slot.value.clone();
+                self.reads.push((slot.shard, slot.read));
+                if self.reads.len() == READ_BATCH {
+                    self.flush_reads();
+                }
+                return Some(value);
+            }
+            self.slots.swap_remove(i);
+        }
+        let (found, shard, version) = self.shared.get_versioned(key);
+        let (value, read) = found?;
+        self.store(key.clone(), value.clone(), shard, version, read);
+        Some(value)
+    }
+
+    /// Inserts into the shared cache, which invalidates copies held by other
+    /// threads.
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        self.slots.retain(|slot| slot.key != key);
+        self.shared.insert(key, value)
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.hand = 0;
+    }
+
+    fn store(&mut self, key: K, value: V, shard: usize, version: u64, read: BufferedRead) {
+        let slot = Slot {
+            key,
+            value,
+            shard,
+            version,
+            read,
+            referenced: false,
+        };
+        if self.slots.len() < self.capacity {
+            self.slots.push(slot);
+            return;
+        }
+        loop {
+            self.hand %= self.slots.len();
+            let victim = &mut self.slots[self.hand];
+            self.hand += 1;
+            if !victim.referenced {
+                *victim = slot;
+                return;
+            }
+            victim.referenced = false;
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> FrontCache<K, V> {
+    /// Replays the pending hits to the shared cache. This happens every few
+    /// hits, and when the front cache is dropped.
+    pub fn flush_reads(&mut self) {
+        self.reads.sort_unstable_by_key(|&(shard, _)| shard);
+        let mut start = 0;
+        while start < self.reads.len() {
+            let shard = self.reads[start].0;
+            let end = self.reads[start..]
+                .iter()
+                .position(|&(other, _)| other != shard)
+                .map_or(self.reads.len(), |len| start + len);
+            let reads = self.reads[start..end].iter().map(|&(_, read)| read);
+            self.shared.record_reads(shard, reads);
+            start = end;
+        }
+        self.reads.clear();
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for FrontCache<K, V> {
+    fn drop(&mut self) {
+        self.flush_reads();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use local::FrontCache;
+    use sharded::ShardedCartCache;
+    use std::sync::Arc;
+
+    #[test]
+    fn front_cache() {
+        let shared: Arc<ShardedCartCache<u32, u32>> =
+            Arc::new(ShardedCartCache::new(256, 4).unwrap());
+        let mut front = FrontCache::new(shared.clone(), 2).unwrap();
+        let mut other = FrontCache::new(shared.clone(), 2).unwrap();
+        front.insert(1, 1);
+        assert_eq!(front.get(&1), Some(1));
+        assert_eq!(front.get(&1), Some(1));
+        assert_eq!(front.hits(), 1);
+        other.insert(1, 2);
+        assert_eq!(front.get(&1), Some(2));
+        assert_eq!(front.hits(), 1);
+        for i in 2..10 {
+            shared.insert(i, i);
+            front.get(&i);
+        }
+        assert_eq!(front.get(&9), Some(9));
+        assert_eq!(front.hits(), 2);
+        assert_eq!(front.get(&42), None);
+
+        let shared: Arc<ShardedCartCache<u32, u32>> =
+            Arc::new(ShardedCartCache::new(256, 1).unwrap());
+        let mut front = FrontCache::new(shared.clone(), 2).unwrap();
+        shared.insert(1, 1);
+        for _ in 0..5 {
+            front.get(&1);
+        }
+        let hits = shared.stats().hits;
+        front.flush_reads();
+        shared.insert(2, 2);
+        assert_eq!(shared.stats().hits, hits + 5);
+    }
+}
@@ -0,0 +1,159 @@
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+use std::cmp::max;
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use CartCache;
+
+/// A cache operation, for fuzz targets and model tests driving a cache
+/// through `apply_ops()`.
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Get(K),
+    Remove(K),
+    /// Sets the capacity; zero is ignored.
+    Resize(u16),
+    Clear,
+    /// Moves the expiration times of all entries this many seconds closer.
+    AdvanceTime(u16),
+}
+
+/// Applies `ops` in order, checking the cache invariants after each one.
+/// On failure, returns the index of the offending operation with the
+/// violated invariant.
+pub fn apply_ops<K, V>(
+    cache: &mut CartCache<K, V>,
+    ops: &[Op<K, V>],
+) -> Result<(), (usize, &'static str)>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    for (i, op) in ops.iter().enumerate() {
+        match *op {
+            Op::Insert(ref key, ref value) => {
+                cache.insert(key.clone(), value.clone());
+            }
+            Op::Get(ref key) => {
+                cache.get(key);
+            }
+            Op::Remove(ref key) => {
+                cache.remove_many(Some(key));
+            }
+            Op::Resize(capacity) => {
+                let _ = cache.set_capacity(capacity as usize);
+            }
+            Op::Clear => cache.clear(),
+            Op::AdvanceTime(seconds) => cache.advance_time(Duration::from_secs(seconds as u64)),
+        }
+        cache
+            .check_invariants()
+            .map_err(|invariant| (i, invariant))?;
+    }
+    Ok(())
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> CartCache<K, V, S> {
+    /// Checks the internal consistency of the lists, counters and index.
+    pub fn check_invariants(&self) -> Result<(), &'static str> {
+        let lists = [&self.t1, &self.t2, &self.b1, &self.b2];
+        if lists.iter().map(|list| list.len()).sum::<usize>() != self.slab.len() + self.ghost_len()
+        {
+            return Err("List lengths do not add up to the number of entries");
+        }
+        if self.map.len() != self.slab.len() {
+            return Err("Index and entries are out of sync");
+        }
+        for (key, &token) in &self.map {
+            if !self.slab.get(token).is_some_and(|entry| entry.key == *key) {
+                return Err("Index points to the wrong entry");
+            }
+        }
+        for (i, list) in lists.iter().enumerate() {
+            if list.iter(&self.nodes).count() != list.len() {
+                return Err("List length does not match its links");
+            }
+            for token in list.iter(&self.nodes) {
+                let node = &self.nodes[token];
+                if !(self.slab.contains(token) || i >= 2 && self.slab.is_held(token)) {
+                    return Err("List points to a missing entry");
+                }
+                if node.is_history() != (i >= 2) || node.is_in_t2() != (i == 1) {
+                    return Err("Entry flags do not match its list");
+                }
+                if (i == 1 || i == 3) && !node.is_longterm() || i == 2 && node.is_longterm() {
+                    return Err("Entry in the wrong list for its type");
+                }
+            }
+        }
+        if self.shortterm_count + self.longterm_count != self.t1.len() + self.t2.len() {
+            return Err("Short-term and long-term counts do not add up");
+        }
+        let resident_limit = if self.warmup.is_some() {
+            self.capacity
+        } else {
+            self.c
+        };
+        if self.t1.len() + self.t2.len() > max(resident_limit, 1) {
+            return Err("Too many resident entries");
+        }
+        if self.slab.len() + self.ghost_len() > self.capacity {
+            return Err("Too many entries");
+        }
+        if self.p > self.c || self.q > 2 * self.c {
+            return Err("Target sizes out of range");
+        }
+        if self
+            .slab
+            .iter()
+            .map(|(_, entry)| entry.weight)
+            .sum::<usize>()
+            != self.weight
+        {
+            return Err("Weight does not match the entries");
+        }
+        if self.loaded_count != self.nodes_loaded() {
+            return Err("Loaded count does not match the entries");
+        }
+        Ok(())
+    }
+
+    fn nodes_loaded(&self) -> usize {
+        self.slab
+            .iter()
+            .filter(|&(token, _)| self.nodes[token].is_loaded())
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate rand;
+
+    use self::rand::prelude::*;
+    use CartCacheBuilder;
+
+    #[test]
+    fn random_ops() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut cache: CartCache<u16, u16> = CartCacheBuilder::new(64)
+            .ttl(Duration::from_secs(30))
+            .build()
+            .unwrap();
+        let ops: Vec<Op<u16, u16>> = (0..20_000)
+            .map(|_| match rng.gen_range(0..100) {
+                0 => Op::Clear,
+                1 => Op::Resize(rng.gen_range(0..128)),
+                2..=4 => Op::AdvanceTime(rng.gen_range(0..20)),
+                5..=9 => Op::Remove(rng.gen_range(0..256)),
+                10..=49 => Op::Get(rng.gen_range(0..256)),
+                _ => Op::Insert(rng.gen_range(0..256), 0),
+            })
+            .collect();
+        assert_eq!(apply_ops(&mut cache, &ops), Ok(()));
+    }
+}
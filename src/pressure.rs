@@ -0,0 +1,154 @@
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+use std::fs;
+use std::hash::Hash;
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+use std::path::PathBuf;
+
+use CartCache;
+
+/// Reports how close the process is to its memory limit.
+pub trait MemoryPressure {
+    /// Fraction of the memory limit in use, or `None` if it is unknown or
+    /// there is no limit.
+    fn usage(&mut self) -> Option<f64>;
+}
+
+impl<F: FnMut() -> Option<f64>> MemoryPressure for F {
+    fn usage(&mut self) -> Option<f64> {
+        self()
+    }
+}
+
+/// Shrinks a cache by an eighth of its capacity whenever usage is above the
+/// high watermark, and grows it back, up to its original capacity, whenever
+/// usage is below the low watermark. Call `adjust()` periodically.
+pub struct PressureGovernor<P> {
+    source: P,
+    max_capacity: usize,
+    min_capacity: usize,
+    high: f64,
+    low: f64,
+}
+
+impl<P: MemoryPressure> PressureGovernor<P> {
+    pub fn new(source: P, max_capacity: usize) -> Self {
+        PressureGovernor {
+            source,
+            max_capacity,
+            min_capacity: (max_capacity / 8).max(2),
+            high: 0.9,
+            low: 0.7,
+        }
+    }
+
+    pub fn min_capacity(mut self, min_capacity: usize) -> Self {
+        self.min_capacity = min_capacity.max(1);
+        self
+    }
+
+    pub fn watermarks(mut self, low: f64, high: f64) -> Self {
+        self.low = low;
+        self.high = high;
+        self
+    }
+
+    /// Resizes `cache` according to the current usage and returns its
+    /// capacity.
+    pub fn adjust<K: Eq + Hash, V>(&mut self, cache: &mut CartCache<K, V>) -> usize {
+        let capacity = cache.capacity();
+        let step = (self.max_capacity / 8).max(1);
+        let target = match self.source.usage() {
+            Some(usage) if usage > self.high => capacity.saturating_sub(step),
+            Some(usage) if usage < self.low => capacity + step,
+            _ => capacity,
+        };
+        let target = target
+            .max(self.min_capacity)
+            .min(self.max_capacity.max(capacity));
+        if target != capacity && cache.set_capacity(target).is_err() {
+            return capacity;
+        }
+        cache.capacity()
+    }
+}
+
+/// Memory usage of the current cgroup (v2), read from `memory.current` and
+/// `memory.max`.
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+pub struct CgroupMemory {
+    path: PathBuf,
+}
+
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+impl CgroupMemory {
+    pub fn new() -> Self {
+        Self::with_path("/sys/fs/cgroup")
+    }
+
+    pub fn with_path<P: Into<PathBuf>>(path: P) -> Self {
+        CgroupMemory { path: path.into() }
+    }
+
+    fn read(&self, name: &str) -> Option<u64> {
+        fs::read_to_string(self.path.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+impl Default for CgroupMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(feature = "cgroup", target_os = "linux"))]
+impl MemoryPressure for CgroupMemory {
+    fn usage(&mut self) -> Option<f64> {
+        let max = self.read("memory.max").filter(|&max| max > 0)?;
+        let current = self.read("memory.current")?;
+        Some(current as f64 / max as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn governor() {
+        let usage = Cell::new(0.95);
+        let mut cache: CartCache<u32, u32> = CartCache::new(64).unwrap();
+        let mut governor = PressureGovernor::new(|| Some(usage.get()), 64);
+        assert_eq!(governor.adjust(&mut cache), 56);
+        for _ in 0..16 {
+            governor.adjust(&mut cache);
+        }
+        assert_eq!(cache.capacity(), 8);
+        usage.set(0.8);
+        assert_eq!(governor.adjust(&mut cache), 8);
+        usage.set(0.1);
+        for _ in 0..16 {
+            governor.adjust(&mut cache);
+        }
+        assert_eq!(cache.capacity(), 64);
+    }
+
+    #[cfg(all(feature = "cgroup", target_os = "linux"))]
+    #[test]
+    fn cgroup() {
+        let path = std::env::temp_dir().join(format!("cart-cache-cgroup-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("memory.current"), "512\n").unwrap();
+        fs::write(path.join("memory.max"), "max\n").unwrap();
+        let mut memory = CgroupMemory::with_path(&path);
+        assert_eq!(memory.usage(), None);
+        fs::write(path.join("memory.max"), "1024\n").unwrap();
+        assert_eq!(memory.usage(), Some(0.5));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+use std::time::Duration;
+
+use {CartCache, CartCacheBuilder};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum PyKey {
+    Bytes(Vec<u8>),
+    Str(String),
+}
+
+impl<'py> FromPyObject<'py> for PyKey {
+    fn extract_bound(key: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(key) = key.downcast::<PyString>() {
+            Ok(PyKey::Str(key.to_str()?.to_owned()))
+        } else if let Ok(key) = key.downcast::<PyBytes>() {
+            Ok(PyKey::Bytes(key.as_bytes().to_vec()))
+        } else {
+            Err(PyTypeError::new_err("Keys must be str or bytes"))
+        }
+    }
+}
+
+fn duration(seconds: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| PyValueError::new_err("Invalid TTL"))
+}
+
+/// `cart_cache.CartCache(capacity, ttl=None)`: a cache of arbitrary objects
+/// under `str` or `bytes` keys, with an optional default TTL in seconds.
+#[pyclass(name = "CartCache")]
+pub struct PyCartCache {
+    cache: CartCache<PyKey, PyObject>,
+}
+
+#[pymethods]
+impl PyCartCache {
+    #[new]
+    #[pyo3(signature = (capacity, ttl = None))]
+    fn new(capacity: usize, ttl: Option<f64>) -> PyResult<Self> {
+        let mut builder = CartCacheBuilder::new(capacity);
+        if let Some(ttl) = ttl {
+            builder = builder.ttl(duration(ttl)?);
+        }
+        let cache = builder.build().map_err(PyValueError::new_err)?;
+        Ok(PyCartCache { cache })
+    }
+
+    fn get(&mut self, py: Python<'_>, key: PyKey) -> Option<PyObject> {
+        self.cache.get(&key).map(|value| value.clone_ref(py))
+    }
+
+    #[pyo3(signature = (key, value, ttl = None))]
+    fn insert(&mut self, key: PyKey, value: PyObject, ttl: Option<f64>) -> PyResult<bool> {
+        Ok(match ttl {
+            Some(ttl) => self.cache.insert_with_ttl(key, value, duration(ttl)?),
+            None => self.cache.insert(key, value),
+        })
+    }
+
+    fn clear(&mut self) {
+        self.cache.clear()
+    }
+
+    fn __len__(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn __contains__(&self, key: PyKey) -> bool {
+        self.cache.contains_key(&key)
+    }
+}
+
+#[pymodule]
+fn cart_cache(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyCartCache>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::ffi::CString;
+
+    #[test]
+    fn python_class() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            locals
+                .set_item("CartCache", py.get_type::<PyCartCache>())
+                .unwrap();
+            let code = CString::new(
+                "
+cache = CartCache(8, ttl=60.0)
+cache.insert('a', [1, 2])
+cache.insert(b'a', 3, ttl=1.0)
+assert cache.get('a') == [1, 2]
+assert cache.get(b'a') == 3
+assert cache.get('b') is None
+assert 'a' in cache and len(cache) == 2
+try:
+    cache.insert(1, 1)
+    assert False
+except TypeError:
+    pass
+",
+            )
+            .unwrap();
+            py.run(&code, None, Some(&locals)).unwrap();
+        });
+    }
+}
@@ -0,0 +1,112 @@
+// Process-wide registry of named caches, to export the statistics of every
+// cache built with `CartCacheBuilder::register()` or registered with
+// `ShardedCartCache::register()` without wiring each one into metrics by hand.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use Stats;
+
+static REGISTRY: Mutex<Vec<(String, Weak<dyn Source>)>> = Mutex::new(Vec::new());
+
+// Statistics of a registered cache, read whenever the registry is queried.
+pub(crate) trait Source: Send + Sync {
+    fn stats(&self) -> Stats;
+}
+
+impl Source for Mutex<Stats> {
+    fn stats(&self) -> Stats {
+        *self.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Adds `source` under `name`. The entry goes away with the last reference to
+// `source`.
+pub(crate) fn add(name: String, source: &Arc<dyn Source>) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.retain(|(_, source)| source.strong_count() > 0);
+    registry.push((name, Arc::downgrade(source)));
+}
+
+// Held by a registered cache, which publishes a snapshot of its statistics
+// every `interval` operations. The registry entry goes away with the cache.
+pub(crate) struct Registration {
+    stats: Arc<Mutex<Stats>>,
+    interval: u64,
+    operations: u64,
+}
+
+impl Registration {
+    pub fn new(name: String, interval: u64, stats: Stats) -> Self {
+        let stats = Arc::new(Mutex::new(stats));
+        add(name, &(stats.clone() as Arc<dyn Source>));
+        Registration {
+            stats,
+            interval,
+            operations: 0,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> bool {
+        self.operations += 1;
+        self.operations.is_multiple_of(self.interval)
+    }
+
+    pub fn publish(&self, stats: Stats) {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner()) = stats;
+    }
+}
+
+/// The statistics of every live registered cache, by name. If several caches
+/// share a name, the most recently registered one is reported.
+///
+/// A `CartCache` reports the statistics it last published, which an idle
+/// cache only refreshes in `run_maintenance()`. A `ShardedCartCache` reports
+/// the current sum of its shards.
+pub fn all() -> HashMap<String, Stats> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.retain(|(_, source)| source.strong_count() > 0);
+    registry
+        .iter()
+        .filter_map(|(name, source)| Some((name.clone(), source.upgrade()?.stats())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use registry;
+    use {CartCache, CartCacheBuilder, ShardedCartCache};
+
+    #[test]
+    fn registry() {
+        let mut cache: CartCache<u32, u32> = CartCacheBuilder::new(16)
+            .register("registry-test", 4)
+            .build()
+            .unwrap();
+        assert_eq!(registry::all()["registry-test"].inserted, 0);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        assert!(registry::all()["registry-test"].inserted > 0);
+        cache.get(&0);
+        cache.run_maintenance();
+        assert_eq!(registry::all()["registry-test"].hits, 1);
+        drop(cache);
+        assert!(!registry::all().contains_key("registry-test"));
+    }
+
+    #[test]
+    fn sharded_registry() {
+        let mut cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(64, 4).unwrap();
+        cache.register("sharded-registry-test");
+        for i in 0..16 {
+            cache.insert(i, i);
+        }
+        cache.get_cloned(&1000);
+        let stats = registry::all()["sharded-registry-test"];
+        assert_eq!((stats.inserted, stats.misses), (16, 1));
+        drop(cache);
+        assert!(!registry::all().contains_key("sharded-registry-test"));
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Source of randomness for TTL jitter and early expiration. Cloning a cache
+/// clones its generator, so implementations must be `Clone`.
+pub trait CacheRng: CloneRng + Send + Sync {
+    fn next_u64(&mut self) -> u64;
+}
+
+#[doc(hidden)]
+pub trait CloneRng {
+    fn clone_rng(&self) -> Box<dyn CacheRng>;
+}
+
+impl<R: CacheRng + Clone + 'static> CloneRng for R {
+    fn clone_rng(&self) -> Box<dyn CacheRng> {
+        Box::new(self.clone())
+    }
+}
+
+/// The default generator, randomly seeded unless created with `with_seed()`.
+#[derive(Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+}
+
+impl Default for SplitMix64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheRng for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,888 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "serde")]
+use std::thread;
+
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use epoch::Copies;
+use filter::KeyFilter;
+#[cfg(feature = "registry")]
+use registry::{self, Source};
+use {CartCache, Equivalent, KeyedState, Stats, Token};
+
+const READ_BUFFER_CAPACITY: usize = 64;
+
+// A token found by a lookup, with the hash of the key it was found for.
+pub(crate) type BufferedRead = (Token, u64);
+
+/// What to do when a thread panicked while holding a shard lock. Has no
+/// effect with the `parking_lot` feature, whose locks are never poisoned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// Panic on every subsequent access to the shard.
+    Propagate,
+    /// Clear the shard, since it may be in an inconsistent state, and keep
+    /// using it.
+    Clear,
+}
+
+// Sizes and counters of a shard, stored whenever its write lock is released,
+// so that they can be observed without locking.
+#[derive(Default)]
+struct Published {
+    len: AtomicUsize,
+    resident_len: AtomicUsize,
+    weight: AtomicUsize,
+    inserted: AtomicU64,
+    loaded: AtomicU64,
+    evicted: AtomicU64,
+    evicted_by_count: AtomicU64,
+    evicted_by_weight: AtomicU64,
+    evicted_by_quota: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expired: AtomicU64,
+    resets: AtomicU64,
+    exhausted: AtomicU64,
+    // Misses from lookups, not yet added to the shard's own counter.
+    buffered_misses: AtomicU64,
+}
+
+impl Published {
+    fn store<K: Eq + Hash, V>(&self, cache: &CartCache<K, V>) {
+        let stats = cache.stats();
+        self.len.store(cache.len(), Ordering::Relaxed);
+        self.resident_len
+            .store(cache.t1.len() + cache.t2.len(), Ordering::Relaxed);
+        self.weight.store(cache.weight(), Ordering::Relaxed);
+        self.inserted.store(stats.inserted, Ordering::Relaxed);
+        self.loaded.store(stats.loaded, Ordering::Relaxed);
+        self.evicted.store(stats.evicted, Ordering::Relaxed);
+        self.evicted_by_count
+            .store(stats.evicted_by_count, Ordering::Relaxed);
+        self.evicted_by_weight
+            .store(stats.evicted_by_weight, Ordering::Relaxed);
+        self.evicted_by_quota
+            .store(stats.evicted_by_quota, Ordering::Relaxed);
+        self.hits.store(stats.hits, Ordering::Relaxed);
+        self.misses.store(stats.misses, Ordering::Relaxed);
+        self.expired.store(stats.expired, Ordering::Relaxed);
+        self.resets.store(stats.resets, Ordering::Relaxed);
+        self.exhausted.store(stats.exhausted, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            inserted: self.inserted.load(Ordering::Relaxed),
+            loaded: self.loaded.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            evicted_by_count: self.evicted_by_count.load(Ordering::Relaxed),
+            evicted_by_weight: self.evicted_by_weight.load(Ordering::Relaxed),
+            evicted_by_quota: self.evicted_by_quota.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            resets: self.resets.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn sum_stats<'a>(shards: impl Iterator<Item = &'a Published>) -> Stats {
+    shards.fold(Stats::default(), |total, published| {
+        let stats = published.stats();
+        let buffered_misses = published.buffered_misses.load(Ordering::Relaxed);
+        Stats {
+            inserted: total.inserted + stats.inserted,
+            loaded: total.loaded + stats.loaded,
+            evicted: total.evicted + stats.evicted,
+            evicted_by_count: total.evicted_by_count + stats.evicted_by_count,
+            evicted_by_weight: total.evicted_by_weight + stats.evicted_by_weight,
+            evicted_by_quota: total.evicted_by_quota + stats.evicted_by_quota,
+            hits: total.hits + stats.hits,
+            misses: total.misses + stats.misses + buffered_misses,
+            expired: total.expired + stats.expired,
+            resets: total.resets + stats.resets,
+            exhausted: total.exhausted + stats.exhausted,
+        }
+    })
+}
+
+// The published counters of every shard of a registered cache.
+#[cfg(feature = "registry")]
+struct ShardedStats(Vec<Arc<Published>>);
+
+#[cfg(feature = "registry")]
+impl Source for ShardedStats {
+    fn stats(&self) -> Stats {
+        sum_stats(self.0.iter().map(|published| &**published))
+    }
+}
+
+struct ShardWriteGuard<'a, K, V>
+where
+    K: Eq + Hash,
+{
+    cache: RwLockWriteGuard<'a, CartCache<K, V>>,
+    published: &'a Published,
+}
+
+impl<K: Eq + Hash, V> Deref for ShardWriteGuard<'_, K, V> {
+    type Target = CartCache<K, V>;
+
+    fn deref(&self) -> &CartCache<K, V> {
+        &self.cache
+    }
+}
+
+impl<K: Eq + Hash, V> DerefMut for ShardWriteGuard<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut CartCache<K, V> {
+        &mut self.cache
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for ShardWriteGuard<'_, K, V> {
+    fn drop(&mut self) {
+        self.published.store(&self.cache);
+    }
+}
+
+// Reads only take a shared lock on the cache. Reference bits they would have
+// set are recorded in a lossy buffer, and applied by whichever thread next
+// takes the write lock.
+struct Shard<K, V>
+where
+    K: Eq + Hash,
+{
+    cache: RwLock<CartCache<K, V>>,
+    key_filter: Option<Arc<KeyFilter>>,
+    hasher: KeyedState,
+    read_buffer: Mutex<Vec<BufferedRead>>,
+    // Bumped by every operation that may change the entries, under the write
+    // lock, so that copies made under a read lock can be checked for
+    // staleness without locking.
+    version: AtomicU64,
+    // Copies of entries found under the read lock, at the current version,
+    // for hits served without locking.
+    copies: Option<Copies<K, V>>,
+    published: Arc<Published>,
+    poison_policy: PoisonPolicy,
+}
+
+impl<K: Eq + Hash, V> Shard<K, V> {
+    #[cfg(feature = "parking_lot")]
+    fn read(&self) -> RwLockReadGuard<'_, CartCache<K, V>> {
+        self.cache.read()
+    }
+
+    #[cfg(not(feature = "parking_lot"))]
+    fn read(&self) -> RwLockReadGuard<'_, CartCache<K, V>> {
+        if self.cache.is_poisoned() && self.poison_policy == PoisonPolicy::Clear {
+            drop(self.lock_cache());
+        }
+        self.cache.read().expect("Shard lock poisoned")
+    }
+
+    #[cfg(feature = "parking_lot")]
+    fn lock_cache(&self) -> ShardWriteGuard<'_, K, V> {
+        let cache = self.cache.write();
+        ShardWriteGuard {
+            cache,
+            published: &self.published,
+        }
+    }
+
+    #[cfg(not(feature = "parking_lot"))]
+    fn lock_cache(&self) -> ShardWriteGuard<'_, K, V> {
+        let cache = self.cache.write();
+        let cache = match cache {
+            Ok(cache) => cache,
+            Err(poisoned) => {
+                if self.poison_policy == PoisonPolicy::Propagate {
+                    panic!("Shard lock poisoned");
+                }
+                let mut cache = poisoned.into_inner();
+                cache.clear();
+                self.version.fetch_add(1, Ordering::Release);
+                self.read_buffer
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .clear();
+                self.cache.clear_poison();
+                cache
+            }
+        };
+        ShardWriteGuard {
+            cache,
+            published: &self.published,
+        }
+    }
+
+    #[cfg(feature = "parking_lot")]
+    fn try_lock_read_buffer(&self) -> Option<MutexGuard<'_, Vec<BufferedRead>>> {
+        self.read_buffer.try_lock()
+    }
+
+    #[cfg(not(feature = "parking_lot"))]
+    fn try_lock_read_buffer(&self) -> Option<MutexGuard<'_, Vec<BufferedRead>>> {
+        self.read_buffer.try_lock().ok()
+    }
+
+    fn write(&self) -> ShardWriteGuard<'_, K, V> {
+        let mut cache = self.lock_cache();
+        let reads = match self.try_lock_read_buffer() {
+            Some(mut read_buffer) => mem::take(&mut *read_buffer),
+            None => Vec::new(),
+        };
+        let misses = self.published.buffered_misses.swap(0, Ordering::Relaxed);
+        cache.apply_buffered_reads(&reads, misses);
+        cache
+    }
+
+    // Like `write()`, for operations that may change the entries.
+    fn modify(&self) -> ShardWriteGuard<'_, K, V> {
+        let cache = self.write();
+        self.version.fetch_add(1, Ordering::Release);
+        cache
+    }
+
+    fn record_read(&self, read: Option<BufferedRead>) {
+        match read {
+            Some(read) => {
+                if let Some(mut read_buffer) = self.try_lock_read_buffer() {
+                    if read_buffer.len() < READ_BUFFER_CAPACITY {
+                        read_buffer.push(read);
+                    }
+                }
+            }
+            None => {
+                self.published
+                    .buffered_misses
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+pub struct ShardedCartCache<K, V>
+where
+    K: Eq + Hash,
+{
+    shards: Vec<Shard<K, V>>,
+    // Seeded independently of the shards' own hash keys, so that knowing
+    // which shard a key lands in says nothing about its slot in the shard.
+    router: KeyedState,
+    router_keys: (u64, u64),
+    #[cfg(feature = "registry")]
+    registration: Option<Arc<dyn Source>>,
+}
+
+impl<K: Eq + Hash, V> ShardedCartCache<K, V> {
+    pub fn new(capacity: usize, shard_count: usize) -> Result<Self, &'static str> {
+        if shard_count == 0 {
+            return Err("Shard count cannot be zero");
+        }
+        let shard_capacity = capacity.div_ceil(shard_count);
+        let mut shards = Vec::with_capacity(shard_count);
+        for _ in 0..shard_count {
+            shards.push(CartCache::new(shard_capacity)?);
+        }
+        Self::from_shards(shards)
+    }
+
+    pub fn from_shards(shards: Vec<CartCache<K, V>>) -> Result<Self, &'static str> {
+        if shards.is_empty() {
+            return Err("Shard count cannot be zero");
+        }
+        let shards = shards
+            .into_iter()
+            .map(|cache| {
+                let published = Published::default();
+                published.store(&cache);
+                Shard {
+                    key_filter: cache.key_filter.clone(),
+                    hasher: cache.map.hasher().clone(),
+                    cache: RwLock::new(cache),
+                    read_buffer: Mutex::new(Vec::with_capacity(READ_BUFFER_CAPACITY)),
+                    version: AtomicU64::new(0),
+                    copies: None,
+                    published: Arc::new(published),
+                    poison_policy: PoisonPolicy::Propagate,
+                }
+            })
+            .collect();
+        let random = RandomState::new();
+        let router_keys = (random.hash_one(0u8), random.hash_one(1u8));
+        Ok(ShardedCartCache {
+            shards,
+            router: KeyedState::with_keys(router_keys.0, router_keys.1),
+            router_keys,
+            #[cfg(feature = "registry")]
+            registration: None,
+        })
+    }
+
+    /// Keys routing entries to shards, needed to restore a dump.
+    pub fn router_keys(&self) -> (u64, u64) {
+        self.router_keys
+    }
+
+    pub fn set_poison_policy(&mut self, poison_policy: PoisonPolicy) {
+        for shard in &mut self.shards {
+            shard.poison_policy = poison_policy;
+        }
+    }
+
+    /// Serves hits from copies of the entries, made by earlier lookups of
+    /// the same keys, without locking the shard. Any write to a shard
+    /// invalidates all its copies, so this pays off for read-mostly shards.
+    /// Copies are reclaimed once no lookup may still hold them, and each
+    /// shard keeps up to half its capacity of them.
+    pub fn set_lock_free_reads(&mut self, enabled: bool) {
+        for shard in &mut self.shards {
+            shard.copies = match enabled {
+                true => Some(Copies::new(shard.read().capacity().div_ceil(2).max(1))),
+                false => None,
+            };
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().capacity())
+            .sum()
+    }
+
+    /// Read without locking, like `resident_len()`, `weight()` and
+    /// `stats()`: these are published by each shard as it is written to.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.published.len.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn resident_len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.published.resident_len.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn weight(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.published.weight.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Sum of the shards' counters. Hits from
+    /// lookups are only counted once their shard is next written to.
+    pub fn stats(&self) -> Stats {
+        sum_stats(self.shards.iter().map(|shard| &*shard.published))
+    }
+
+    /// Adds the cache to the process-wide `registry` under `name`. Unlike a
+    /// registered `CartCache`, it reports the sum of its shards' counters as
+    /// of the query, like `stats()`.
+    #[cfg(feature = "registry")]
+    pub fn register(&mut self, name: &str) {
+        let published = self
+            .shards
+            .iter()
+            .map(|shard| shard.published.clone())
+            .collect();
+        let source: Arc<dyn Source> = Arc::new(ShardedStats(published));
+        registry::add(name.to_string(), &source);
+        self.registration = Some(source);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.modify().clear();
+        }
+    }
+
+    fn shard_index<Q>(&self, key: &Q) -> usize
+    where
+        Q: Hash + ?Sized,
+    {
+        (self.router.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Shard<K, V>
+    where
+        Q: Hash + ?Sized,
+    {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Lock-free check that returns `false` only if `key` is definitely not
+    /// stored. Always returns `true` for shards without a key filter.
+    pub fn may_contain<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + ?Sized,
+    {
+        let shard = self.shard(key);
+        match shard.key_filter {
+            Some(ref key_filter) => key_filter.may_contain(shard.hasher.hash_one(key)),
+            None => true,
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.shard(key).read().contains_key(key)
+    }
+
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        K: Clone,
+        V: Clone,
+    {
+        Self::get_cloned_from(self.shard(key), key)
+    }
+
+    fn get_cloned_from<Q>(shard: &Shard<K, V>, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        K: Clone,
+        V: Clone,
+    {
+        Self::get_versioned_from(shard, key)
+            .0
+            .map(|(value, _)| value)
+    }
+
+    fn get_versioned_from<Q>(shard: &Shard<K, V>, key: &Q) -> (Option<(V, BufferedRead)>, u64)
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        K: Clone,
+        V: Clone,
+    {
+        if let Some(ref copies) = shard.copies {
+            let version = shard.version.load(Ordering::Acquire);
+            if let Some((value, read)) = copies.get(shard.hasher.hash_one(key), key, version) {
+                shard.record_read(Some(read));
+                return (Some((value, read)), version);
+            }
+        }
+        let (read, value, version) = {
+            let cache = shard.read();
+            let version = shard.version.load(Ordering::Acquire);
+            match cache.peek_token(key) {
+                Some(token) => {
+                    let read = (token, cache.map.hasher().hash_one(key));
+                    let entry = &cache.slab[token];
+                    if let Some(ref copies) = shard.copies {
+                        // Published under the read lock, before any write
+                        // can bump the version.
+                        copies.publish(
+                            version,
+                            read.1,
+                            entry.expires_at,
+                            read,
+                            entry.key.clone(),
+                            entry.value.clone(),
+                        );
+                    }
+                    (Some(read), Some(entry.value.clone()), version)
+                }
+                None => (None, None, version),
+            }
+        };
+        shard.record_read(read);
+        (value.zip(read), version)
+    }
+
+    /// Like `get_cloned()`, also returning the read to replay on later hits
+    /// of a copy, the shard index and its version at the time of the read.
+    pub(crate) fn get_versioned<Q>(&self, key: &Q) -> (Option<(V, BufferedRead)>, usize, u64)
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        K: Clone,
+        V: Clone,
+    {
+        let index = self.shard_index(key);
+        let (value, version) = Self::get_versioned_from(&self.shards[index], key);
+        (value, index, version)
+    }
+
+    pub(crate) fn shard_version(&self, index: usize) -> u64 {
+        self.shards[index].version.load(Ordering::Acquire)
+    }
+
+    // Hits served from copies, whose reference bits are set like those of
+    // buffered reads. Reads that don't fit in the buffer are dropped.
+    pub(crate) fn record_reads(&self, index: usize, reads: impl Iterator<Item = BufferedRead>) {
+        let shard = &self.shards[index];
+        if let Some(mut read_buffer) = shard.try_lock_read_buffer() {
+            let room = READ_BUFFER_CAPACITY.saturating_sub(read_buffer.len());
+            read_buffer.extend(reads.take(room));
+        }
+    }
+
+    pub fn get_all_cloned<'a, I>(&self, keys: I) -> HashMap<K, V>
+    where
+        I: IntoIterator<Item = &'a K>,
+        K: Clone + 'a,
+        V: Clone,
+    {
+        let mut grouped: Vec<Vec<&K>> = vec![Vec::new(); self.shards.len()];
+        for key in keys {
+            grouped[self.shard_index(key)].push(key);
+        }
+        let mut found = HashMap::new();
+        for (shard, keys) in self.shards.iter().zip(grouped) {
+            if keys.is_empty() {
+                continue;
+            }
+            let mut reads = Vec::with_capacity(keys.len());
+            {
+                let cache = shard.read();
+                for key in keys {
+                    let read = cache
+                        .peek_token(key)
+                        .map(|token| (token, cache.map.hasher().hash_one(key)));
+                    if let Some((token, _)) = read {
+                        found.insert(key.clone(), cache.slab[token].value.clone());
+                    }
+                    reads.push(read);
+                }
+            }
+            for read in reads {
+                shard.record_read(read);
+            }
+        }
+        found
+    }
+
+    pub fn insert(&self, key: K, value: V) -> bool
+    where
+        K: Clone,
+    {
+        self.shard(&key).modify().insert(key, value)
+    }
+
+    // Shard hints bypass the router, so that callers can align shards with
+    // their own partitioning, e.g. one shard per core. Keys inserted with a
+    // hint can only be found with the same hint.
+    fn hinted_shard(&self, shard_hint: usize) -> &Shard<K, V> {
+        &self.shards[shard_hint % self.shards.len()]
+    }
+
+    pub fn contains_key_with_shard<Q>(&self, key: &Q, shard_hint: usize) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.hinted_shard(shard_hint).read().contains_key(key)
+    }
+
+    pub fn get_cloned_with_shard<Q>(&self, key: &Q, shard_hint: usize) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+        K: Clone,
+        V: Clone,
+    {
+        Self::get_cloned_from(self.hinted_shard(shard_hint), key)
+    }
+
+    /// Inserts into shard `shard_hint` modulo the shard count, instead of
+    /// the shard picked by hashing the key.
+    pub fn insert_with_shard(&self, key: K, value: V, shard_hint: usize) -> bool
+    where
+        K: Clone,
+    {
+        self.hinted_shard(shard_hint).modify().insert(key, value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V> ShardedCartCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+{
+    /// One `to_json_debug()` snapshot per shard.
+    pub fn to_json_debug(&self) -> Vec<String>
+    where
+        K: Serialize,
+    {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().to_json_debug())
+            .collect()
+    }
+
+    /// Rebuilds a cache from `to_json_debug()` snapshots and the
+    /// `router_keys()` of the dumped cache, with one thread per shard to
+    /// parse its snapshot and restore it. Keys are routed to the same shards
+    /// as before, and each shard gets its exact replacement state back.
+    pub fn from_json_debug<F>(
+        snapshots: &[String],
+        router_keys: (u64, u64),
+        value: F,
+    ) -> Result<Self, &'static str>
+    where
+        K: DeserializeOwned,
+        F: Fn(&K) -> V + Sync,
+    {
+        let shards = thread::scope(|scope| {
+            let restorers: Vec<_> = snapshots
+                .iter()
+                .map(|json| {
+                    let value = &value;
+                    scope.spawn(move || CartCache::from_json_debug(json, value))
+                })
+                .collect();
+            restorers
+                .into_iter()
+                .map(|restorer| restorer.join().expect("Snapshot restorer panicked"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        let mut cache = Self::from_shards(shards)?;
+        cache.router = KeyedState::with_keys(router_keys.0, router_keys.1);
+        cache.router_keys = router_keys;
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(feature = "parking_lot"))]
+    use sharded::PoisonPolicy;
+    use sharded::ShardedCartCache;
+    use std::hash::BuildHasher;
+    #[cfg(not(feature = "parking_lot"))]
+    use std::panic;
+    use std::sync::Arc;
+    use std::thread;
+    use {CartCache, CartCacheBuilder};
+
+    #[test]
+    fn get_all_cloned() {
+        let cache: ShardedCartCache<u32, String> = ShardedCartCache::new(256, 4).unwrap();
+        for i in 0..32 {
+            cache.insert(i, i.to_string());
+        }
+        let found = cache.get_all_cloned(&[1, 5, 31, 1000]);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[&5], "5");
+    }
+
+    #[test]
+    fn published_counters() {
+        let cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(256, 4).unwrap();
+        for i in 0..32 {
+            cache.insert(i, i);
+        }
+        cache.get_cloned(&1);
+        cache.get_cloned(&1000);
+        let stats = cache.stats();
+        assert_eq!((stats.inserted, stats.hits, stats.misses), (32, 0, 1));
+        assert_eq!(cache.len(), 32);
+        assert_eq!(cache.resident_len(), 32);
+        assert_eq!(cache.weight(), 32);
+        cache.insert(1, 2);
+        assert_eq!(cache.stats().hits, 1);
+        cache.clear();
+        assert_eq!((cache.len(), cache.weight()), (0, 0));
+    }
+
+    #[test]
+    fn versions() {
+        let cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(64, 1).unwrap();
+        cache.insert(1, 1);
+        let version = cache.shard_version(0);
+        cache.get_cloned(&1);
+        drop(cache.shards[0].write());
+        assert_eq!(cache.shard_version(0), version);
+        cache.insert(2, 2);
+        assert!(cache.shard_version(0) > version);
+    }
+
+    #[test]
+    fn lock_free_reads() {
+        let mut cache: ShardedCartCache<u32, String> = ShardedCartCache::new(64, 1).unwrap();
+        cache.set_lock_free_reads(true);
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        let version = cache.shard_version(0);
+        assert_eq!(cache.get_cloned(&1), Some("one".to_string()));
+        {
+            let _guard = cache.shards[0].read();
+            let copies = cache.shards[0].copies.as_ref().unwrap();
+            let hash = cache.shards[0].hasher.hash_one(1);
+            assert!(copies.get(hash, &1, version).is_some());
+            assert!(copies.get(hash, &1, version + 1).is_none());
+        }
+        let writer = cache.shards[0].write();
+        assert_eq!(cache.get_cloned(&1), Some("one".to_string()));
+        drop(writer);
+        assert_eq!(cache.stats().hits, 1);
+        cache.insert(1, "uno".to_string());
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.get_cloned(&1), Some("uno".to_string()));
+        assert_eq!(cache.get_cloned(&1), Some("uno".to_string()));
+        cache.clear();
+        assert_eq!(cache.get_cloned(&1), None);
+        cache.set_lock_free_reads(false);
+        assert!(cache.shards[0].copies.is_none());
+    }
+
+    #[test]
+    fn concurrent_lock_free_reads() {
+        let mut cache: ShardedCartCache<u32, Vec<u32>> = ShardedCartCache::new(256, 4).unwrap();
+        cache.set_lock_free_reads(true);
+        let cache = Arc::new(cache);
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        let key = (i * 7 + t) % 300;
+                        match cache.get_cloned(&key) {
+                            Some(value) => assert_eq!(value, vec![key; 3]),
+                            None if i % 3 == 0 => {
+                                cache.insert(key, vec![key; 3]);
+                            }
+                            None => {}
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(cache.len() <= 256);
+    }
+
+    #[test]
+    fn stale_buffered_reads() {
+        let cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(64, 1).unwrap();
+        cache.insert(1, 1);
+        let token = cache.shards[0].read().peek_token(&1).unwrap();
+        let hash = cache.shards[0].read().map.hasher().hash_one(1);
+        cache.shards[0].write().remove(&1);
+        cache.insert(2, 2);
+        assert_eq!(cache.shards[0].read().peek_token(&2), Some(token));
+        cache.shards[0].record_read(Some((token, hash)));
+        cache.insert(3, 3);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn shard_hints() {
+        let cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(256, 4).unwrap();
+        for i in 0..32 {
+            cache.insert_with_shard(i, i, 6);
+        }
+        assert_eq!(cache.shards[2].read().len(), 32);
+        assert_eq!(cache.get_cloned_with_shard(&5, 2), Some(5));
+        assert!(cache.contains_key_with_shard(&5, 6));
+        assert!(!cache.contains_key_with_shard(&5, 3));
+    }
+
+    #[test]
+    fn may_contain() {
+        let shards: Vec<CartCache<u32, u32>> = (0..4)
+            .map(|_| CartCacheBuilder::new(64).key_filter(true).build().unwrap())
+            .collect();
+        let cache = ShardedCartCache::from_shards(shards).unwrap();
+        cache.insert(1, 1);
+        assert!(cache.may_contain(&1));
+        assert!((1000..1100).any(|i| !cache.may_contain(&i)));
+    }
+
+    #[test]
+    #[cfg(not(feature = "parking_lot"))]
+    fn poison_recovery() {
+        let mut cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(64, 1).unwrap();
+        cache.set_poison_policy(PoisonPolicy::Clear);
+        cache.insert(1, 1);
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = cache.shards[0].write();
+            panic!("consumer panicked");
+        }));
+        assert_eq!(cache.get_cloned(&1), None);
+        cache.insert(2, 2);
+        assert_eq!(cache.get_cloned(&2), Some(2));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parallel_restore() {
+        let cache: ShardedCartCache<u32, u32> = ShardedCartCache::new(256, 4).unwrap();
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let snapshots = cache.to_json_debug();
+        let restored: ShardedCartCache<u32, u32> =
+            ShardedCartCache::from_json_debug(&snapshots, cache.router_keys(), |&key| key * 2)
+                .unwrap();
+        assert_eq!(restored.shard_count(), 4);
+        assert_eq!(restored.capacity(), cache.capacity());
+        assert_eq!(restored.len(), cache.len());
+        assert_eq!(restored.to_json_debug(), snapshots);
+        let resident = (0..100)
+            .find(|key| cache.get_cloned(key).is_some())
+            .unwrap();
+        assert_eq!(restored.get_cloned(&resident), Some(resident * 2));
+        assert!(ShardedCartCache::<u32, u32>::from_json_debug(
+            &["{".to_string()],
+            (0, 0),
+            |&key| key
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn concurrent_reads() {
+        let cache: Arc<ShardedCartCache<u32, u32>> =
+            Arc::new(ShardedCartCache::new(1024, 8).unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        let key = (i * 7 + t) % 600;
+                        if cache.get_cloned(&key).is_none() {
+                            cache.insert(key, key);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(cache.len() <= 1024);
+    }
+}
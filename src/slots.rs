@@ -0,0 +1,232 @@
+use std::collections::TryReserveError;
+use std::mem;
+use std::ops::{Index, IndexMut};
+
+#[cfg(feature = "allocator_api")]
+use allocator::CacheAlloc;
+
+#[cfg(feature = "allocator_api")]
+type SlotVec<T> = Vec<Slot<T>, CacheAlloc>;
+#[cfg(not(feature = "allocator_api"))]
+type SlotVec<T> = Vec<Slot<T>>;
+
+// Storage with stable indices, like `slab::Slab`, but with fallible growth.
+// Vacant slots form a free list, and the most recently freed one is reused
+// first. Held slots are empty, but kept out of the free list until released.
+#[derive(Clone)]
+pub(crate) struct Slots<T> {
+    slots: SlotVec<T>,
+    len: usize,
+    next_free: usize,
+}
+
+#[derive(Clone)]
+enum Slot<T> {
+    Occupied(T),
+    Vacant(usize),
+    Held,
+}
+
+impl<T> Slots<T> {
+    #[cfg(not(feature = "allocator_api"))]
+    pub fn new() -> Self {
+        Slots {
+            slots: Vec::new(),
+            len: 0,
+            next_free: 0,
+        }
+    }
+
+    #[cfg(feature = "allocator_api")]
+    pub fn new() -> Self {
+        Self::new_in(CacheAlloc::default())
+    }
+
+    #[cfg(feature = "allocator_api")]
+    pub fn new_in(alloc: CacheAlloc) -> Self {
+        Slots {
+            slots: Vec::new_in(alloc),
+            len: 0,
+            next_free: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    // Makes room for `additional` more items than are currently stored.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let vacant = self.slots.len() - self.len;
+        self.slots
+            .try_reserve_exact(additional.saturating_sub(vacant))
+    }
+
+    pub fn insert(&mut self, item: T) -> usize {
+        self.len += 1;
+        if let Some(slot) = self.slots.get_mut(self.next_free) {
+            if let Slot::Vacant(next_free) = *slot {
+                *slot = Slot::Occupied(item);
+                return mem::replace(&mut self.next_free, next_free);
+            }
+        }
+        self.slots.push(Slot::Occupied(item));
+        self.next_free = self.slots.len();
+        self.slots.len() - 1
+    }
+
+    pub fn try_remove(&mut self, index: usize) -> Option<T> {
+        if !self.contains(index) {
+            return None;
+        }
+        Some(self.remove(index))
+    }
+
+    // Panics if the slot is vacant, like indexing.
+    pub fn remove(&mut self, index: usize) -> T {
+        let slot = &mut self.slots[index];
+        match mem::replace(slot, Slot::Vacant(self.next_free)) {
+            Slot::Occupied(item) => {
+                self.next_free = index;
+                self.len -= 1;
+                item
+            }
+            vacant => {
+                *slot = vacant;
+                panic!("Invalid slot");
+            }
+        }
+    }
+
+    // Takes the item out, keeping its slot until `restore()` or `release()`.
+    pub fn hold(&mut self, index: usize) -> T {
+        match mem::replace(&mut self.slots[index], Slot::Held) {
+            Slot::Occupied(item) => {
+                self.len -= 1;
+                item
+            }
+            slot => {
+                self.slots[index] = slot;
+                panic!("Invalid slot");
+            }
+        }
+    }
+
+    pub fn restore(&mut self, index: usize, item: T) {
+        assert!(self.is_held(index), "Invalid slot");
+        self.slots[index] = Slot::Occupied(item);
+        self.len += 1;
+    }
+
+    pub fn release(&mut self, index: usize) {
+        assert!(self.is_held(index), "Invalid slot");
+        self.slots[index] = Slot::Vacant(mem::replace(&mut self.next_free, index));
+    }
+
+    #[inline]
+    pub fn is_held(&self, index: usize) -> bool {
+        matches!(self.slots.get(index), Some(Slot::Held))
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(item)) => Some(item),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match self.slots.get_mut(index) {
+            Some(Slot::Occupied(item)) => Some(item),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, index: usize) -> bool {
+        self.get(index).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.len = 0;
+        self.next_free = 0;
+    }
+
+    pub fn into_items(self) -> impl Iterator<Item = (usize, T)> {
+        self.slots
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied(item) => Some((index, item)),
+                Slot::Vacant(_) | Slot::Held => None,
+            })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied(item) => Some((index, item)),
+                Slot::Vacant(_) | Slot::Held => None,
+            })
+    }
+}
+
+impl<T> Index<usize> for Slots<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("Invalid slot")
+    }
+}
+
+impl<T> IndexMut<usize> for Slots<T> {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        self.get_mut(index).expect("Invalid slot")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slots::Slots;
+
+    #[test]
+    fn slots() {
+        let mut slots = Slots::new();
+        assert_eq!(
+            (slots.insert('a'), slots.insert('b'), slots.insert('c')),
+            (0, 1, 2)
+        );
+        assert_eq!(slots.remove(0), 'a');
+        assert_eq!(slots.remove(2), 'c');
+        assert!(!slots.contains(2) && slots.contains(1));
+        assert_eq!(slots.insert('d'), 2);
+        assert_eq!(slots.insert('e'), 0);
+        assert_eq!(slots.insert('f'), 3);
+        assert_eq!(slots.len(), 4);
+        assert_eq!(slots.iter().map(|(_, &c)| c).collect::<String>(), "ebdf");
+        slots.try_reserve(10).unwrap();
+        assert!(slots.capacity() >= 14);
+        assert!(slots.try_reserve(usize::MAX / 2).is_err());
+        assert_eq!(slots.hold(2), 'd');
+        assert!(slots.is_held(2) && !slots.contains(2));
+        assert_eq!(slots.insert('g'), 4);
+        slots.restore(2, 'h');
+        assert_eq!(slots.remove(2), 'h');
+        assert_eq!(slots.hold(1), 'b');
+        slots.release(1);
+        assert_eq!(slots.insert('i'), 1);
+        assert_eq!(slots.insert('j'), 2);
+    }
+}
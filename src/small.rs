@@ -0,0 +1,128 @@
+use std::hash::Hash;
+
+use {CartCache, Stats, Token};
+
+const NO_TOKEN: u32 = u32::MAX;
+
+/// Keys drawn from a small dense range, usable as direct array indices.
+pub trait SmallKey: Copy + Eq + Hash {
+    const SPACE: usize;
+
+    fn index(self) -> usize;
+}
+
+impl SmallKey for u8 {
+    const SPACE: usize = 1 << 8;
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl SmallKey for u16 {
+    const SPACE: usize = 1 << 16;
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A cache for small keys, whose lookups go through an array indexed by key
+/// instead of being hashed. Slots are hints, checked against the stored key,
+/// so they never need to be cleared on eviction.
+pub struct SmallKeyCache<K, V>
+where
+    K: SmallKey,
+{
+    cache: CartCache<K, V>,
+    index: Box<[u32]>,
+}
+
+impl<K: SmallKey, V> SmallKeyCache<K, V> {
+    pub fn new(capacity: usize) -> Result<Self, &'static str> {
+        Ok(Self::from_cache(CartCache::new(capacity)?))
+    }
+
+    pub fn from_cache(cache: CartCache<K, V>) -> Self {
+        let mut index = vec![NO_TOKEN; K::SPACE].into_boxed_slice();
+        for (key, &token) in cache.map.iter() {
+            index[key.index()] = token as u32;
+        }
+        SmallKeyCache { cache, index }
+    }
+
+    pub fn into_inner(self) -> CartCache<K, V> {
+        self.cache
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.cache.stats()
+    }
+
+    #[inline]
+    fn token(&self, key: K) -> Option<Token> {
+        let token = self.index[key.index()];
+        if token == NO_TOKEN {
+            return None;
+        }
+        let token = token as Token;
+        match self.cache.slab.get(token) {
+            Some(entry) if entry.key == key => Some(token),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        self.token(key).is_some()
+    }
+
+    #[inline]
+    pub fn get(&mut self, key: K) -> Option<&V> {
+        let token = self.token(key);
+        self.cache.get_found(token)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let updated = self.cache.insert(key, value);
+        if let Some(&token) = self.cache.map.get(&key) {
+            self.index[key.index()] = token as u32;
+        }
+        updated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use small::SmallKeyCache;
+
+    #[test]
+    fn direct_index() {
+        let mut cache: SmallKeyCache<u8, u32> = SmallKeyCache::new(64).unwrap();
+        for i in 0..=255u8 {
+            cache.insert(i, i as u32);
+            cache.get(i / 2);
+        }
+        assert!(cache.len() <= cache.capacity());
+        assert_eq!(cache.get(255), Some(&255));
+        let resident = (0..=255u8).filter(|&i| cache.contains_key(i)).count();
+        let inner = cache.into_inner();
+        assert_eq!(
+            resident,
+            (0..=255u8).filter(|i| inner.contains_key(i)).count()
+        );
+    }
+}
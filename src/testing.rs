@@ -0,0 +1,221 @@
+use std::cmp::{max, min};
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+use {CartCache, Op};
+
+struct Page<K> {
+    key: K,
+    reference: bool,
+    longterm: bool,
+}
+
+/// A direct transcription of CART, with the default parameters of
+/// `CartCache::new()`: linear searches over plain queues, no TTLs, weights
+/// or values. Only meant as an oracle for `differential()`.
+pub struct ReferenceCart<K> {
+    capacity: usize,
+    c: usize,
+    p: usize,
+    q: usize,
+    t1: VecDeque<Page<K>>,
+    t2: VecDeque<Page<K>>,
+    b1: VecDeque<Page<K>>,
+    b2: VecDeque<Page<K>>,
+}
+
+fn position<K: Eq>(list: &VecDeque<Page<K>>, key: &K) -> Option<usize> {
+    list.iter().position(|page| page.key == *key)
+}
+
+impl<K: Eq + Clone> ReferenceCart<K> {
+    pub fn new(capacity: usize) -> Self {
+        ReferenceCart {
+            capacity,
+            c: capacity / 2,
+            p: 0,
+            q: 0,
+            t1: VecDeque::new(),
+            t2: VecDeque::new(),
+            b1: VecDeque::new(),
+            b2: VecDeque::new(),
+        }
+    }
+
+    pub fn resident_keys(&self) -> impl Iterator<Item = &K> {
+        self.t1.iter().chain(self.t2.iter()).map(|page| &page.key)
+    }
+
+    fn shortterm_count(&self) -> usize {
+        self.t1.iter().filter(|page| !page.longterm).count()
+    }
+
+    fn longterm_count(&self) -> usize {
+        self.t1.iter().filter(|page| page.longterm).count() + self.t2.len()
+    }
+
+    pub fn get(&mut self, key: &K) {
+        let lists = [&mut self.t1, &mut self.t2, &mut self.b1, &mut self.b2];
+        for list in lists {
+            if let Some(i) = position(list, key) {
+                list[i].reference = true;
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: K) {
+        for list in [&mut self.t1, &mut self.t2] {
+            if let Some(i) = position(list, &key) {
+                list[i].reference = true;
+                return;
+            }
+        }
+        let in_b1 = position(&self.b1, &key).is_some();
+        let in_b2 = position(&self.b2, &key).is_some();
+        if self.t1.len() + self.t2.len() >= self.c {
+            self.replace();
+            if !(in_b1 || in_b2) && self.b1.len() + self.b2.len() > self.c {
+                if self.b1.len() > self.q || self.b2.is_empty() {
+                    self.b1.pop_front();
+                } else {
+                    self.b2.pop_front();
+                }
+            }
+        }
+        if in_b1 {
+            let step = max(1, self.shortterm_count() / self.b1.len());
+            self.p = min(self.p + step, self.c);
+            let i = position(&self.b1, &key).expect("Page vanished from B1");
+            let mut page = self.b1.remove(i).expect("Page vanished from B1");
+            page.reference = false;
+            page.longterm = true;
+            self.t1.push_back(page);
+        } else if in_b2 {
+            let step = max(1, self.longterm_count() / self.b2.len());
+            self.p = self.p.saturating_sub(step);
+            let i = position(&self.b2, &key).expect("Page vanished from B2");
+            let mut page = self.b2.remove(i).expect("Page vanished from B2");
+            page.reference = false;
+            self.t1.push_back(page);
+            if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count() >= self.c {
+                self.q = min(self.q + 1, self.capacity.saturating_sub(self.t1.len()));
+            }
+        } else {
+            self.t1.push_back(Page {
+                key,
+                reference: false,
+                longterm: false,
+            });
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        for list in [&mut self.t1, &mut self.t2, &mut self.b1, &mut self.b2] {
+            if let Some(i) = position(list, key) {
+                list.remove(i);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = ReferenceCart::new(self.capacity);
+    }
+
+    fn replace(&mut self) {
+        while self.t2.front().is_some_and(|page| page.reference) {
+            let mut page = self.t2.pop_front().expect("Empty T2");
+            page.reference = false;
+            self.t1.push_back(page);
+            if self.t2.len() + self.b2.len() + self.t1.len() - self.shortterm_count() >= self.c {
+                self.q = min(self.q + 1, self.capacity.saturating_sub(self.t1.len()));
+            }
+        }
+        while self
+            .t1
+            .front()
+            .is_some_and(|page| page.longterm || page.reference)
+        {
+            let mut page = self.t1.pop_front().expect("Empty T1");
+            if page.reference {
+                page.reference = false;
+                if self.t1.len() + 1 >= min(self.p + 1, self.b1.len()) {
+                    page.longterm = true;
+                }
+                self.t1.push_back(page);
+            } else {
+                self.t2.push_back(page);
+                let target = self.c.saturating_sub(self.t1.len());
+                self.q = max(self.q.saturating_sub(1), target);
+            }
+        }
+        if self.t1.len() >= max(1, self.p) {
+            if let Some(page) = self.t1.pop_front() {
+                self.b1.push_back(page);
+            }
+        } else if let Some(page) = self.t2.pop_front() {
+            self.b2.push_back(page);
+        }
+    }
+}
+
+/// Runs `ops` against a `CartCache::new(capacity)` and a `ReferenceCart`,
+/// and returns the index of the first operation after which their resident
+/// keys differ. `Resize` and `AdvanceTime` are skipped.
+pub fn differential<K, V>(capacity: usize, ops: &[Op<K, V>]) -> Result<(), usize>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    let mut cache = CartCache::new(capacity).map_err(|_| 0usize)?;
+    let mut reference = ReferenceCart::new(capacity);
+    for (i, op) in ops.iter().enumerate() {
+        match *op {
+            Op::Insert(ref key, ref value) => {
+                cache.insert(key.clone(), value.clone());
+                reference.insert(key.clone());
+            }
+            Op::Get(ref key) => {
+                cache.get(key);
+                reference.get(key);
+            }
+            Op::Remove(ref key) => {
+                cache.remove_many(Some(key));
+                reference.remove(key);
+            }
+            Op::Clear => {
+                cache.clear();
+                reference.clear();
+            }
+            Op::Resize(_) | Op::AdvanceTime(_) => continue,
+        }
+        let resident: HashSet<&K> = cache.scan().iter().map(|(key, _)| key).collect();
+        if resident != reference.resident_keys().collect() {
+            return Err(i);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use self::rand::prelude::*;
+    use super::*;
+
+    #[test]
+    fn matches_reference() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for &capacity in &[2, 7, 64] {
+            let ops: Vec<Op<u8, ()>> = (0..5_000)
+                .map(|_| match rng.gen_range(0..100) {
+                    0 => Op::Clear,
+                    1..=5 => Op::Remove(rng.gen_range(0..96)),
+                    6..=45 => Op::Get(rng.gen_range(0..96)),
+                    _ => Op::Insert(rng.gen_range(0..96), ()),
+                })
+                .collect();
+            assert_eq!(differential(capacity, &ops), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+/// Hit counts for a captured access window, replayed with `capacity`
+/// resident entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceReport {
+    pub capacity: usize,
+    pub accesses: usize,
+    pub hits: usize,
+    pub lru_hits: usize,
+    pub belady_hits: usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct Trace {
+    window: usize,
+    accesses: VecDeque<(u64, bool)>,
+}
+
+impl Trace {
+    pub(crate) fn new(window: usize) -> Self {
+        Trace {
+            window,
+            accesses: VecDeque::with_capacity(window),
+        }
+    }
+
+    pub(crate) fn record(&mut self, fingerprint: u64, hit: bool) {
+        if self.accesses.len() == self.window {
+            self.accesses.pop_front();
+        }
+        self.accesses.push_back((fingerprint, hit));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.accesses.clear();
+    }
+
+    pub(crate) fn report(&self, capacity: usize) -> TraceReport {
+        let keys: Vec<u64> = self.accesses.iter().map(|&(key, _)| key).collect();
+        TraceReport {
+            capacity,
+            accesses: keys.len(),
+            hits: self.accesses.iter().filter(|&&(_, hit)| hit).count(),
+            lru_hits: lru_hits(&keys, capacity),
+            belady_hits: belady_hits(&keys, capacity),
+        }
+    }
+}
+
+fn lru_hits(keys: &[u64], capacity: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    let mut last_use: HashMap<u64, usize> = HashMap::new();
+    let mut by_age = BTreeSet::new();
+    let mut hits = 0;
+    for (i, &key) in keys.iter().enumerate() {
+        match last_use.insert(key, i) {
+            Some(previous) => {
+                by_age.remove(&(previous, key));
+                hits += 1;
+            }
+            None => {
+                if by_age.len() == capacity {
+                    let (_, oldest) = by_age.pop_first().expect("Empty LRU list");
+                    last_use.remove(&oldest);
+                }
+            }
+        }
+        by_age.insert((i, key));
+    }
+    hits
+}
+
+fn belady_hits(keys: &[u64], capacity: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    let mut next_use = vec![usize::MAX; keys.len()];
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+    for (i, &key) in keys.iter().enumerate().rev() {
+        if let Some(next) = seen.insert(key, i) {
+            next_use[i] = next;
+        }
+    }
+    let mut resident: HashMap<u64, usize> = HashMap::new();
+    let mut by_next_use = BTreeSet::new();
+    let mut hits = 0;
+    for (i, &key) in keys.iter().enumerate() {
+        match resident.get(&key) {
+            Some(&next) => {
+                by_next_use.remove(&(next, key));
+                hits += 1;
+            }
+            None => {
+                if resident.len() == capacity {
+                    let (_, farthest) = by_next_use.pop_last().expect("Empty resident set");
+                    resident.remove(&farthest);
+                }
+            }
+        }
+        resident.insert(key, next_use[i]);
+        by_next_use.insert((next_use[i], key));
+    }
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use trace::{belady_hits, lru_hits};
+    use {CartCache, CartCacheBuilder};
+
+    #[test]
+    fn replay() {
+        let keys = [1, 2, 3, 1, 4, 1, 2, 3, 4, 1];
+        assert_eq!(lru_hits(&keys, 3), 2);
+        assert_eq!(belady_hits(&keys, 3), 5);
+        assert_eq!(lru_hits(&keys, 0), 0);
+        assert_eq!(belady_hits(&keys, 0), 0);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache: CartCache<u32, u32> =
+            CartCacheBuilder::new(1).trace_window(16).build().unwrap();
+        cache.insert(0, 0);
+        for _ in 0..4 {
+            cache.get(&0);
+        }
+        let report = cache.trace_report().unwrap();
+        assert_eq!(report.capacity, 0);
+        assert_eq!((report.lru_hits, report.belady_hits), (0, 0));
+    }
+}